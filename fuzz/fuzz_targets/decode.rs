@@ -0,0 +1,24 @@
+//! Decode every word of the input, and disassemble the input as a ROM for every variant.
+//! Decoding has to round-trip through `encode`, nothing may panic.
+
+#![no_main]
+
+use chip8_rs::chip8::Variant;
+use chip8_rs::disasm::{disassemble, Instruction, Syntax};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for word in data.chunks_exact(2) {
+        let opcode = u16::from_be_bytes([word[0], word[1]]);
+        let instruction = Instruction::decode(opcode);
+        assert_eq!(instruction.encode(), opcode, "{:?}", instruction);
+        instruction.format(Syntax::Cowgod);
+        instruction.format(Syntax::Octo);
+    }
+
+    for variant in [Variant::Chip8, Variant::SuperChip, Variant::XoChip] {
+        let listing = disassemble(data, variant);
+        listing.listing(Syntax::Cowgod);
+        listing.listing(Syntax::Octo);
+    }
+});
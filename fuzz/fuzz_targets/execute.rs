@@ -0,0 +1,81 @@
+//! Load the input as a ROM and run it for a bounded number of instructions.
+//!
+//! The first four bytes pick the variant, the quirks and the keys held down, the rest is the
+//! ROM. While FX0A waits, the keys are released and pressed again. Any panic is a bug, and so
+//! is a broken invariant: the loader copies exactly the ROM to 0x200, the stack pointer stays
+//! within the stack, executed instructions were fetched from memory, and a fault leaves PC, SP,
+//! I and the registers as they were before the instruction.
+
+#![no_main]
+
+use chip8_rs::chip8::{Chip8, StepOutcome, Variant};
+use chip8_rs::quirks::Quirks;
+use libfuzzer_sys::fuzz_target;
+
+const STEPS: u32 = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    let [variant, quirks, keys_high, keys_low, ref rom @ ..] = *data else {
+        return;
+    };
+    let variant = [Variant::Chip8, Variant::SuperChip, Variant::XoChip][variant as usize % 3];
+    let quirks = Quirks {
+        shift: quirks & 0x01 != 0,
+        load_store: quirks & 0x02 != 0,
+        jump: quirks & 0x04 != 0,
+        vf_reset: quirks & 0x08 != 0,
+        clipping: quirks & 0x10 != 0,
+        display_wait: quirks & 0x20 != 0,
+        key_press: quirks & 0x40 != 0,
+    };
+    let keys = u16::from_be_bytes([keys_high, keys_low]);
+
+    let mut chip8 = Chip8::with_variant(variant);
+    chip8.set_quirks(quirks);
+    let memory_size = chip8.memory().len();
+    if chip8.reset_and_load_bytes(rom).is_err() {
+        assert!(0x200 + rom.len() > memory_size, "a {} byte ROM fits", rom.len());
+        return;
+    }
+    assert_eq!(&chip8.memory()[0x200..0x200 + rom.len()], rom);
+    assert_eq!((chip8.program_counter(), chip8.stack_pointer(), chip8.index_register()), (0x200, 0, 0));
+
+    for key in (0..16).filter(|key| keys & 1 << key != 0) {
+        chip8.key_down(key);
+    }
+
+    for step in 0..STEPS {
+        let before = (chip8.program_counter(), chip8.stack_pointer(), chip8.index_register(), *chip8.registers());
+        match chip8.step() {
+            Ok(StepOutcome::Executed { pc, .. }) => {
+                assert_eq!(pc, before.0);
+                assert!((pc as usize) + 1 < memory_size, "executed {:#06X} past the end of memory", pc);
+            }
+            Ok(StepOutcome::WaitingVblank) => chip8.tick_timers(),
+            // FX0A blocks until a key comes, so let go of the keys or press them again
+            Ok(StepOutcome::WaitingKey) => {
+                for key in (0..16).filter(|key| keys & 1 << key != 0) {
+                    if chip8.is_key_down(key) {
+                        chip8.key_up(key);
+                    } else {
+                        chip8.key_down(key);
+                    }
+                }
+            }
+            Ok(StepOutcome::Exited) => break,
+            Ok(StepOutcome::Break(hit)) => panic!("no breakpoints are set, got {:?}", hit),
+            Err(error) => {
+                let after = (chip8.program_counter(), chip8.stack_pointer(), chip8.index_register(), *chip8.registers());
+                assert_eq!(after, before, "{} changed the machine", error);
+                assert_eq!(chip8.fault(), Some(&error));
+                assert_eq!(chip8.step(), Err(error));
+                break;
+            }
+        }
+        assert!(chip8.stack_pointer() <= 16, "stack pointer {}", chip8.stack_pointer());
+        assert_eq!(chip8.stack().len(), chip8.stack_pointer() as usize);
+        if step % 10 == 9 {
+            chip8.tick_timers();
+        }
+    }
+});
@@ -0,0 +1,618 @@
+//! Assembler: turns Cowgod-style source into a ROM, the inverse of `disasm`.
+//!
+//! ```text
+//! ; comments start with a semicolon
+//! SPEED   EQU 2               ; constants, usable wherever a number is
+//!         include "font.s"    ; paths are relative to the including file
+//! main:   LD V0, 0
+//! loop:   ADD V0, SPEED
+//!         LD I, sprite
+//!         DRW V0, V1, sprite_end - sprite
+//!         JP loop
+//! sprite: db 0x80, 0b01000000, "ok"
+//! sprite_end:
+//!         dw 0x1234
+//! ```
+//!
+//! The mnemonics are the ones `disasm` prints in Cowgod syntax, so each instruction of a listing
+//! assembles back to the same opcode. Mnemonics, registers and directives are case-insensitive,
+//! labels and constants aren't. Numbers are decimal, `0x` hex or `0b` binary, and operands can
+//! add and subtract them. `I`, `DT`, `ST`, `K`, `F`, `HF`, `B`, `R`, `LONG` and `V0`-`VF` are
+//! reserved and can't be used as names.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::disasm::{Instruction, ROM_START};
+
+/// Includes nested deeper than this are reported as an error, which also catches include cycles
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Why a source didn't assemble, and where
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in characters
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assemble source text, includes are looked up relative to the working directory
+///
+/// ```
+/// # use chip8_rs::asm::assemble;
+/// assert_eq!(assemble("loop: LD V1, 0x20\n JP loop").unwrap(), [0x61, 0x20, 0x12, 0x00]);
+/// ```
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::new();
+    assembler.read(source, "<input>", Path::new(""), 0)?;
+    assembler.emit()
+}
+
+/// Assemble a source file
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let source = fs::read_to_string(path).map_err(|e| AsmError {
+        file: path.display().to_string(),
+        line: 0,
+        column: 0,
+        message: format!("can't read the file: {}", e),
+    })?;
+    let mut assembler = Assembler::new();
+    assembler.read(&source, &path.display().to_string(), path.parent().unwrap_or(Path::new("")), 0)?;
+    assembler.emit()
+}
+
+/// Where a token came from
+#[derive(Debug, Clone)]
+struct Pos {
+    file: String,
+    line: usize,
+    column: usize,
+}
+
+impl Pos {
+    fn error<T>(&self, message: String) -> Result<T, AsmError> {
+        Err(AsmError { file: self.file.clone(), line: self.line, column: self.column, message })
+    }
+}
+
+/// The tokens of a line that are left to parse
+type Tokens<'a> = &'a [(Token, Pos)];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(String),
+    Comma,
+    Colon,
+    Plus,
+    Minus,
+    Open,
+    Close,
+}
+
+/// `[sign] atom { (+|-) atom }`
+#[derive(Debug, Clone)]
+struct Expr {
+    terms: Vec<(i64, Atom)>,
+    pos: Pos,
+}
+
+#[derive(Debug, Clone)]
+enum Atom {
+    Number(i64),
+    Symbol(String, Pos),
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Reg(u8),
+    Range(u8, u8),
+    I,
+    /// `[I]`
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(Expr),
+    Expr(Expr),
+    Str(String),
+}
+
+#[derive(Debug)]
+enum Statement {
+    Instruction { mnemonic: String, operands: Vec<(Operand, Pos)> },
+    Db(Vec<(Operand, Pos)>),
+    Dw(Vec<(Operand, Pos)>),
+}
+
+struct Assembler {
+    statements: Vec<(Statement, Pos)>,
+    symbols: HashMap<String, i64>,
+    address: i64,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Assembler { statements: Vec::new(), symbols: HashMap::new(), address: ROM_START as i64 }
+    }
+
+    /// First pass: parse every line, give labels their address and evaluate constants
+    fn read(&mut self, source: &str, file: &str, dir: &Path, depth: usize) -> Result<(), AsmError> {
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            let tokens = lex(text, file, line)?;
+            let mut tokens = &tokens[..];
+
+            // label:
+            if let [(Token::Ident(name), pos), (Token::Colon, _), rest @ ..] = tokens {
+                self.define(name, self.address, pos)?;
+                tokens = rest;
+            }
+
+            let ((first, pos), rest) = match tokens.split_first() {
+                Some(split) => split,
+                None => continue,
+            };
+            let word = match first {
+                Token::Ident(word) => word,
+                _ => return pos.error("expected a mnemonic, directive or label".to_string()),
+            };
+
+            // NAME EQU value
+            if let [(Token::Ident(equ), _), value @ ..] = rest {
+                if equ.eq_ignore_ascii_case("equ") {
+                    let value = match parse_operands(value, pos)?.as_slice() {
+                        [(Operand::Expr(expr), _)] => self.eval(expr)?,
+                        _ => return pos.error("EQU takes a single value".to_string()),
+                    };
+                    self.define(word, value, pos)?;
+                    continue;
+                }
+            }
+
+            let operands = parse_operands(rest, pos)?;
+            let statement = match word.to_ascii_uppercase().as_str() {
+                "INCLUDE" => {
+                    let path = match operands.as_slice() {
+                        [(Operand::Str(path), _)] => dir.join(path),
+                        _ => return pos.error("INCLUDE takes a quoted path".to_string()),
+                    };
+                    self.include(&path, pos, depth)?;
+                    continue;
+                }
+                "DB" => {
+                    for (operand, pos) in operands.iter() {
+                        self.address += match operand {
+                            Operand::Str(text) => text.len() as i64,
+                            Operand::Expr(_) => 1,
+                            _ => return pos.error("DB takes numbers and strings".to_string()),
+                        };
+                    }
+                    Statement::Db(operands)
+                }
+                "DW" => {
+                    self.address += 2 * operands.len() as i64;
+                    Statement::Dw(operands)
+                }
+                mnemonic => {
+                    let long = operands.iter().any(|(operand, _)| matches!(operand, Operand::Long(_)));
+                    self.address += if long { 4 } else { 2 };
+                    Statement::Instruction { mnemonic: mnemonic.to_string(), operands }
+                }
+            };
+            self.statements.push((statement, pos.clone()));
+        }
+        Ok(())
+    }
+
+    fn include(&mut self, path: &Path, pos: &Pos, depth: usize) -> Result<(), AsmError> {
+        if depth >= MAX_INCLUDE_DEPTH {
+            return pos.error("includes are nested too deeply".to_string());
+        }
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => return pos.error(format!("can't read {}: {}", path.display(), e)),
+        };
+        let dir: PathBuf = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        self.read(&source, &path.display().to_string(), &dir, depth + 1)
+    }
+
+    fn define(&mut self, name: &str, value: i64, pos: &Pos) -> Result<(), AsmError> {
+        if is_reserved(name) {
+            return pos.error(format!("{} is reserved", name));
+        }
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return pos.error(format!("{} is already defined", name));
+        }
+        Ok(())
+    }
+
+    fn eval(&self, expr: &Expr) -> Result<i64, AsmError> {
+        let mut value = 0;
+        for (sign, atom) in expr.terms.iter() {
+            let term = match atom {
+                Atom::Number(number) => *number,
+                Atom::Symbol(name, pos) => match self.symbols.get(name) {
+                    Some(value) => *value,
+                    None => return pos.error(format!("{} is not defined", name)),
+                },
+            };
+            value += sign * term;
+        }
+        Ok(value)
+    }
+
+    /// Second pass: every label is known, so every operand can be evaluated
+    fn emit(&self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::new();
+        for (statement, pos) in self.statements.iter() {
+            match statement {
+                Statement::Db(operands) => {
+                    for (operand, pos) in operands.iter() {
+                        match operand {
+                            Operand::Str(text) => rom.extend_from_slice(text.as_bytes()),
+                            Operand::Expr(expr) => rom.push(self.fit(expr, -0x80, 0xFF, "byte")? as u8),
+                            _ => return pos.error("DB takes numbers and strings".to_string()),
+                        }
+                    }
+                }
+                Statement::Dw(operands) => {
+                    for (operand, pos) in operands.iter() {
+                        match operand {
+                            Operand::Expr(expr) => {
+                                let word = self.fit(expr, -0x8000, 0xFFFF, "word")? as u16;
+                                rom.extend_from_slice(&word.to_be_bytes());
+                            }
+                            _ => return pos.error("DW takes numbers".to_string()),
+                        }
+                    }
+                }
+                Statement::Instruction { mnemonic, operands } => {
+                    let instruction = self.instruction(mnemonic, operands, pos)?;
+                    rom.extend_from_slice(&instruction.encode().to_be_bytes());
+                    if let [_, (Operand::Long(expr), _)] = operands.as_slice() {
+                        let addr = self.fit(expr, 0, 0xFFFF, "address")? as u16;
+                        rom.extend_from_slice(&addr.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(rom)
+    }
+
+    /// Evaluate `expr` and check that it's between `min` and `max`
+    fn fit(&self, expr: &Expr, min: i64, max: i64, what: &str) -> Result<i64, AsmError> {
+        let value = self.eval(expr)?;
+        if value < min || value > max {
+            return expr.pos.error(format!("{} doesn't fit in a {}", value, what));
+        }
+        Ok(value)
+    }
+
+    fn instruction(
+        &self,
+        mnemonic: &str,
+        operands: &[(Operand, Pos)],
+        pos: &Pos,
+    ) -> Result<Instruction, AsmError> {
+        use Instruction::*;
+        use Operand as O;
+
+        let addr = |expr: &Expr| self.fit(expr, 0, 0xFFF, "12-bit address").map(|v| v as u16);
+        let byte = |expr: &Expr| self.fit(expr, -0x80, 0xFF, "byte").map(|v| v as u8);
+        let nibble = |expr: &Expr| self.fit(expr, 0, 0xF, "nibble").map(|v| v as u8);
+
+        let operands: Vec<&Operand> = operands.iter().map(|(operand, _)| operand).collect();
+        let instruction = match (mnemonic, operands.as_slice()) {
+            ("SYS", [O::Expr(a)]) => Sys { addr: addr(a)? },
+            ("CLS", []) => Cls,
+            ("RET", []) => Ret,
+            ("SCD", [O::Expr(n)]) => ScrollDown { n: nibble(n)? },
+            ("SCU", [O::Expr(n)]) => ScrollUp { n: nibble(n)? },
+            ("SCR", []) => ScrollRight,
+            ("SCL", []) => ScrollLeft,
+            ("EXIT", []) => Exit,
+            ("LOW", []) => Lores,
+            ("HIGH", []) => Hires,
+            ("JP", [O::Expr(a)]) => Jump { addr: addr(a)? },
+            ("JP", [O::Reg(0), O::Expr(a)]) => JumpV0 { addr: addr(a)? },
+            ("CALL", [O::Expr(a)]) => Call { addr: addr(a)? },
+            ("SE", [O::Reg(x), O::Reg(y)]) => SkipEqReg { x: *x, y: *y },
+            ("SE", [O::Reg(x), O::Expr(b)]) => SkipEqByte { x: *x, byte: byte(b)? },
+            ("SNE", [O::Reg(x), O::Reg(y)]) => SkipNeReg { x: *x, y: *y },
+            ("SNE", [O::Reg(x), O::Expr(b)]) => SkipNeByte { x: *x, byte: byte(b)? },
+            ("SAVE", [O::Range(x, y)]) => SaveRange { x: *x, y: *y },
+            ("LOAD", [O::Range(x, y)]) => LoadRange { x: *x, y: *y },
+            ("LD", [O::Reg(x), O::Reg(y)]) => Move { x: *x, y: *y },
+            ("LD", [O::Reg(x), O::Expr(b)]) => LoadByte { x: *x, byte: byte(b)? },
+            ("LD", [O::I, O::Long(_)]) => LoadILong,
+            ("LD", [O::I, O::Expr(a)]) => LoadI { addr: addr(a)? },
+            ("LD", [O::Reg(x), O::Dt]) => GetDelay { x: *x },
+            ("LD", [O::Reg(x), O::K]) => WaitKey { x: *x },
+            ("LD", [O::Dt, O::Reg(x)]) => SetDelay { x: *x },
+            ("LD", [O::St, O::Reg(x)]) => SetSound { x: *x },
+            ("LD", [O::F, O::Reg(x)]) => Font { x: *x },
+            ("LD", [O::Hf, O::Reg(x)]) => BigFont { x: *x },
+            ("LD", [O::B, O::Reg(x)]) => Bcd { x: *x },
+            ("LD", [O::IndirectI, O::Reg(x)]) => Store { x: *x },
+            ("LD", [O::Reg(x), O::IndirectI]) => Load { x: *x },
+            ("LD", [O::R, O::Reg(x)]) => SaveFlags { x: *x },
+            ("LD", [O::Reg(x), O::R]) => LoadFlags { x: *x },
+            ("ADD", [O::Reg(x), O::Reg(y)]) => AddReg { x: *x, y: *y },
+            ("ADD", [O::Reg(x), O::Expr(b)]) => AddByte { x: *x, byte: byte(b)? },
+            ("ADD", [O::I, O::Reg(x)]) => AddI { x: *x },
+            ("OR", [O::Reg(x), O::Reg(y)]) => Or { x: *x, y: *y },
+            ("AND", [O::Reg(x), O::Reg(y)]) => And { x: *x, y: *y },
+            ("XOR", [O::Reg(x), O::Reg(y)]) => Xor { x: *x, y: *y },
+            ("SUB", [O::Reg(x), O::Reg(y)]) => Sub { x: *x, y: *y },
+            ("SUBN", [O::Reg(x), O::Reg(y)]) => SubReverse { x: *x, y: *y },
+            // without Vy, Vx is shifted in place whatever the shift quirk says
+            ("SHR", [O::Reg(x)]) => ShiftRight { x: *x, y: *x },
+            ("SHR", [O::Reg(x), O::Reg(y)]) => ShiftRight { x: *x, y: *y },
+            ("SHL", [O::Reg(x)]) => ShiftLeft { x: *x, y: *x },
+            ("SHL", [O::Reg(x), O::Reg(y)]) => ShiftLeft { x: *x, y: *y },
+            ("RND", [O::Reg(x), O::Expr(b)]) => Random { x: *x, byte: byte(b)? },
+            ("DRW", [O::Reg(x), O::Reg(y), O::Expr(n)]) => Draw { x: *x, y: *y, n: nibble(n)? },
+            ("SKP", [O::Reg(x)]) => SkipKey { x: *x },
+            ("SKNP", [O::Reg(x)]) => SkipNotKey { x: *x },
+            ("PLANE", [O::Expr(n)]) => Plane { n: nibble(n)? },
+            ("AUDIO", []) => Audio,
+            ("PITCH", [O::Reg(x)]) => Pitch { x: *x },
+            (mnemonic, _) if MNEMONICS.contains(&mnemonic) => {
+                return pos.error(format!("invalid operands for {}", mnemonic));
+            }
+            (mnemonic, _) => return pos.error(format!("unknown mnemonic {}", mnemonic)),
+        };
+        Ok(instruction)
+    }
+}
+
+const MNEMONICS: [&str; 32] = [
+    "SYS", "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE",
+    "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND",
+    "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+fn register(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v') | Some('V'), Some(digit), None) => digit.to_digit(16).map(|d| d as u8),
+        _ => None,
+    }
+}
+
+fn is_reserved(name: &str) -> bool {
+    const RESERVED: [&str; 9] = ["I", "DT", "ST", "K", "F", "HF", "B", "R", "LONG"];
+    register(name).is_some() || RESERVED.iter().any(|word| word.eq_ignore_ascii_case(name))
+}
+
+fn lex(text: &str, file: &str, line: usize) -> Result<Vec<(Token, Pos)>, AsmError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let pos = Pos { file: file.to_string(), line, column: i + 1 };
+        let start = i;
+        i += 1;
+        let token = match c {
+            ';' => break,
+            c if c.is_whitespace() => continue,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '[' => Token::Open,
+            ']' => Token::Close,
+            '"' => {
+                while i < chars.len() && chars[i] != '"' {
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return pos.error("unterminated string".to_string());
+                }
+                i += 1;
+                Token::Str(chars[start + 1..i - 1].iter().collect())
+            }
+            c if c.is_ascii_digit() => {
+                while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                    i += 1;
+                }
+                let literal: String = chars[start..i].iter().collect();
+                let lower = literal.to_ascii_lowercase();
+                let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+                    i64::from_str_radix(hex, 16)
+                } else if let Some(binary) = lower.strip_prefix("0b") {
+                    i64::from_str_radix(binary, 2)
+                } else {
+                    lower.parse()
+                };
+                match parsed {
+                    Ok(number) => Token::Number(number),
+                    Err(_) => return pos.error(format!("invalid number {}", literal)),
+                }
+            }
+            c if c.is_alphabetic() || c == '_' || c == '.' => {
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                    i += 1;
+                }
+                Token::Ident(chars[start..i].iter().collect())
+            }
+            c => return pos.error(format!("unexpected character {:?}", c)),
+        };
+        tokens.push((token, pos));
+    }
+    Ok(tokens)
+}
+
+/// Parse a comma separated operand list, `pos` is the mnemonic for error messages
+fn parse_operands(mut tokens: Tokens, pos: &Pos) -> Result<Vec<(Operand, Pos)>, AsmError> {
+    let mut operands = Vec::new();
+    if tokens.is_empty() {
+        return Ok(operands);
+    }
+    loop {
+        let operand_pos = match tokens.first() {
+            Some((_, pos)) => pos.clone(),
+            None => return pos.error("missing operand after ,".to_string()),
+        };
+        let (operand, rest) = parse_operand(tokens, &operand_pos)?;
+        operands.push((operand, operand_pos));
+        match rest.split_first() {
+            None => return Ok(operands),
+            Some(((Token::Comma, _), rest)) => tokens = rest,
+            Some(((_, pos), _)) => return pos.error("expected , between operands".to_string()),
+        }
+    }
+}
+
+fn parse_operand<'a>(tokens: Tokens<'a>, pos: &Pos) -> Result<(Operand, Tokens<'a>), AsmError> {
+    match tokens {
+        [(Token::Open, _), (Token::Ident(i), _), (Token::Close, _), rest @ ..] if i.eq_ignore_ascii_case("i") => {
+            Ok((Operand::IndirectI, rest))
+        }
+        [(Token::Str(text), _), rest @ ..] => Ok((Operand::Str(text.clone()), rest)),
+        [(Token::Ident(name), _), rest @ ..] => {
+            if let Some(x) = register(name) {
+                if let [(Token::Minus, _), (Token::Ident(last), _), rest @ ..] = rest {
+                    if let Some(y) = register(last) {
+                        return Ok((Operand::Range(x, y), rest));
+                    }
+                }
+                return Ok((Operand::Reg(x), rest));
+            }
+            let special = match name.to_ascii_uppercase().as_str() {
+                "I" => Some(Operand::I),
+                "DT" => Some(Operand::Dt),
+                "ST" => Some(Operand::St),
+                "K" => Some(Operand::K),
+                "F" => Some(Operand::F),
+                "HF" => Some(Operand::Hf),
+                "B" => Some(Operand::B),
+                "R" => Some(Operand::R),
+                "LONG" => {
+                    let (expr, rest) = parse_expr(rest, pos)?;
+                    return Ok((Operand::Long(expr), rest));
+                }
+                _ => None,
+            };
+            match special {
+                Some(operand) => Ok((operand, rest)),
+                None => parse_expr(tokens, pos).map(|(expr, rest)| (Operand::Expr(expr), rest)),
+            }
+        }
+        _ => parse_expr(tokens, pos).map(|(expr, rest)| (Operand::Expr(expr), rest)),
+    }
+}
+
+fn parse_expr<'a>(mut tokens: Tokens<'a>, pos: &Pos) -> Result<(Expr, Tokens<'a>), AsmError> {
+    let mut terms = Vec::new();
+    let mut sign = 1;
+    if let [(Token::Minus, _), rest @ ..] = tokens {
+        sign = -1;
+        tokens = rest;
+    }
+    loop {
+        let atom = match tokens.split_first() {
+            Some(((Token::Number(number), _), _)) => Atom::Number(*number),
+            Some(((Token::Ident(name), pos), _)) => Atom::Symbol(name.clone(), pos.clone()),
+            Some(((_, pos), _)) => return pos.error("expected a number or a name".to_string()),
+            None => return pos.error("expected a number or a name".to_string()),
+        };
+        terms.push((sign, atom));
+        tokens = &tokens[1..];
+        sign = match tokens.first() {
+            Some((Token::Plus, _)) => 1,
+            Some((Token::Minus, _)) => -1,
+            _ => return Ok((Expr { terms, pos: pos.clone() }, tokens)),
+        };
+        tokens = &tokens[1..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, assemble_file};
+    use crate::disasm::Instruction;
+    use std::path::Path;
+
+    #[test]
+    fn ibm_logo_matches_the_rom() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let rom = std::fs::read(root.join("roms/IBM-Logo.ch8")).unwrap();
+        assert_eq!(assemble_file(&root.join("roms/IBM-Logo.s")).unwrap(), rom);
+    }
+
+    #[test]
+    fn disassembly_assembles_back() {
+        for opcode in 0..=0xFFFF {
+            let instruction = Instruction::decode(opcode);
+            if instruction == Instruction::LoadILong {
+                continue;
+            }
+            let text = instruction.to_string();
+            assert_eq!(assemble(&text), Ok(opcode.to_be_bytes().to_vec()), "{}", text);
+        }
+        assert_eq!(assemble("LD I, LONG 0x1234"), Ok(vec![0xF0, 0x00, 0x12, 0x34]));
+    }
+
+    #[test]
+    fn labels_constants_and_data() {
+        let source = "
+            N    EQU 3
+            main: CALL draw     ; forward reference
+                  JP main
+            draw: LD I, sprite + 1
+                  DRW V0, V1, end - sprite - 1
+                  SHR V2
+                  RET
+            sprite: db 0xFF, -1, N, \"ab\"
+            end:  dw 0x1234
+        ";
+        let rom = assemble(source).unwrap();
+        assert_eq!(
+            rom,
+            [
+                0x22, 0x04, 0x12, 0x00, // main
+                0xA2, 0x0D, 0xD0, 0x14, 0x82, 0x26, 0x00, 0xEE, // draw
+                0xFF, 0xFF, 0x03, b'a', b'b', // sprite
+                0x12, 0x34,
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let error = |source: &str| {
+            let error = assemble(source).unwrap_err();
+            (error.line, error.column, error.message)
+        };
+
+        assert_eq!(error("CLS\n  JP nowhere"), (2, 6, "nowhere is not defined".to_string()));
+        assert_eq!(error("LD V0, 0x100"), (1, 8, "256 doesn't fit in a byte".to_string()));
+        assert_eq!(error("  FOO V0"), (1, 3, "unknown mnemonic FOO".to_string()));
+        assert_eq!(error("DRW V0, V1"), (1, 1, "invalid operands for DRW".to_string()));
+        assert_eq!(error("a: CLS\na: CLS"), (2, 1, "a is already defined".to_string()));
+        assert_eq!(error("LD V0 V1"), (1, 7, "expected , between operands".to_string()));
+        assert_eq!(error("db \"open"), (1, 4, "unterminated string".to_string()));
+        assert!(error("include \"missing.s\"").2.starts_with("can't read missing.s"));
+    }
+}
@@ -0,0 +1,316 @@
+//! Sound for the terminal frontend.
+//!
+//! The machine renders its own sound with `Chip8::fill_audio`. [`Audio`] takes 1/60 s of it
+//! after every frame and writes it as 16-bit signed little-endian mono samples to its output: a
+//! raw PCM file or a command like `aplay` reading them on its stdin, or a WAV file. Without a
+//! sound device the terminal bell rings at the start of each beep.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+
+use crate::chip8::Chip8;
+
+/// Frames per second, the rate `Audio::frame` is called at
+const FRAME_RATE: u64 = 60;
+
+/// Where the sound goes, parsed from `off`, `bell`, `pcm:<file>`, `pcm:|<command>` or `wav:<file>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    Off,
+    /// The terminal bell, once per beep
+    Bell,
+    /// Raw samples written to a file or a named pipe
+    Pcm(PathBuf),
+    /// Raw samples piped to the stdin of a shell command, e.g. `aplay -q -f S16_LE -r 44100 -c 1`
+    Command(String),
+    /// A WAV file, its header is completed when the output is finished
+    Wav(PathBuf),
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (kind, target) = match spec.split_once(':') {
+            Some((kind, target)) => (kind, Some(target)),
+            None => (spec, None),
+        };
+        match (kind, target) {
+            ("off", None) => Ok(Output::Off),
+            ("bell", None) => Ok(Output::Bell),
+            ("pcm", Some(target)) => match target.strip_prefix('|') {
+                Some(command) if !command.trim().is_empty() => Ok(Output::Command(command.trim().to_string())),
+                None if !target.is_empty() => Ok(Output::Pcm(PathBuf::from(target))),
+                _ => Err(format!("{}: missing file or command", spec)),
+            },
+            ("wav", Some(target)) if !target.is_empty() => Ok(Output::Wav(PathBuf::from(target))),
+            _ => Err(format!("unknown audio output {} (expected off, bell, pcm:<file>, pcm:|<command> or wav:<file>)", spec)),
+        }
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::Off => write!(f, "off"),
+            Output::Bell => write!(f, "bell"),
+            Output::Pcm(path) => write!(f, "pcm:{}", path.display()),
+            Output::Command(command) => write!(f, "pcm:|{}", command),
+            Output::Wav(path) => write!(f, "wav:{}", path.display()),
+        }
+    }
+}
+
+/// A WAV file being written, the sizes in its header are filled in by `finish`
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_len: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Write the header of a 16-bit mono file
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        out.write_all(b"RIFF")?;
+        out.write_all(&36u32.to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&1u16.to_le_bytes())?; // channels
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * 2).to_le_bytes())?; // bytes per second
+        out.write_all(&2u16.to_le_bytes())?; // bytes per sample
+        out.write_all(&16u16.to_le_bytes())?; // bits per sample
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter { out, data_len: 0 })
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.data_len = self.data_len.saturating_add(bytes.len() as u32);
+        Ok(())
+    }
+
+    /// Fill in the sizes, the file is valid after this. Writing can go on, `finish` again after
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + self.data_len).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&self.data_len.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+enum Sink {
+    Off,
+    Bell,
+    Raw(Box<dyn Write>),
+    Command(Child),
+    Wav(WavWriter<BufWriter<File>>),
+}
+
+/// The beeper of the terminal frontend
+pub struct Audio {
+    sink: Sink,
+    volume: f32,
+    sample_rate: u32,
+    frames: u64,
+    // whether the last frame beeped, for the bell
+    on: bool,
+    buf: Vec<f32>,
+    samples: Vec<u8>,
+}
+
+impl Audio {
+    /// Open `output` at `volume` between 0 and 1, `sample_rate` only matters to the PCM and WAV outputs
+    pub fn open(output: &Output, volume: f32, sample_rate: u32) -> io::Result<Audio> {
+        let sink = match output {
+            Output::Off => Sink::Off,
+            Output::Bell => Sink::Bell,
+            Output::Pcm(path) => Sink::Raw(Box::new(BufWriter::new(File::create(path)?))),
+            Output::Command(command) => Sink::Command(
+                Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()?,
+            ),
+            Output::Wav(path) => Sink::Wav(WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)?),
+        };
+        Ok(Audio::with_sink(sink, volume, sample_rate))
+    }
+
+    /// Raw samples to any writer
+    pub fn raw(out: impl Write + 'static, volume: f32, sample_rate: u32) -> Audio {
+        Audio::with_sink(Sink::Raw(Box::new(out)), volume, sample_rate)
+    }
+
+    fn with_sink(sink: Sink, volume: f32, sample_rate: u32) -> Audio {
+        Audio {
+            sink,
+            volume: volume.clamp(0.0, 1.0),
+            sample_rate,
+            frames: 0,
+            on: false,
+            buf: Vec::new(),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Output the next 1/60 s of the machine's sound. Call this every frame, also while the
+    /// machine is paused: the sample outputs need the silence to keep time
+    pub fn frame(&mut self, chip8: &mut Chip8) -> io::Result<()> {
+        if let Sink::Off = self.sink {
+            return Ok(());
+        }
+
+        // whole samples per frame, the remainders add up over the frames
+        let rate = self.sample_rate as u64;
+        let count = ((self.frames + 1) * rate / FRAME_RATE - self.frames * rate / FRAME_RATE) as usize;
+        self.frames += 1;
+        self.buf.resize(count, 0.0);
+        chip8.fill_audio(&mut self.buf, self.sample_rate);
+
+        if let Sink::Bell = self.sink {
+            // the fade out of a beep spills into the next frame, it takes most of a frame to count
+            let on = self.buf.iter().filter(|sample| **sample != 0.0).count() > count / 2;
+            let started = on && !self.on;
+            self.on = on;
+            if started {
+                let mut stdout = io::stdout();
+                stdout.write_all(b"\x07")?;
+                stdout.flush()?;
+            }
+            return Ok(());
+        }
+
+        self.samples.clear();
+        for sample in &self.buf {
+            let sample = (sample * self.volume * i16::MAX as f32) as i16;
+            self.samples.extend_from_slice(&sample.to_le_bytes());
+        }
+        match &mut self.sink {
+            Sink::Raw(out) => out.write_all(&self.samples),
+            Sink::Command(child) => match child.stdin.as_mut() {
+                Some(stdin) => stdin.write_all(&self.samples),
+                None => Ok(()),
+            },
+            Sink::Wav(wav) => wav.write(&self.samples),
+            Sink::Off | Sink::Bell => Ok(()),
+        }
+    }
+
+    /// Flush the output, complete the WAV header and wait for the command to play what it was sent.
+    /// Dropping the output does the same but can't report errors
+    pub fn finish(mut self) -> io::Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::Raw(out) => out.flush(),
+            Sink::Command(child) => {
+                drop(child.stdin.take());
+                child.wait().map(|_| ())
+            }
+            Sink::Wav(wav) => wav.finish(),
+            Sink::Off | Sink::Bell => Ok(()),
+        }
+    }
+}
+
+impl Drop for Audio {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    use super::{Audio, Output, WavWriter};
+    use crate::chip8::testing::Shared;
+    use crate::chip8::Chip8;
+
+    fn samples(bytes: &[u8]) -> Vec<i16> {
+        bytes.chunks(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect()
+    }
+
+    #[test]
+    fn outputs() {
+        assert_eq!("bell".parse(), Ok(Output::Bell));
+        assert_eq!("off".parse(), Ok(Output::Off));
+        assert_eq!("pcm:/tmp/beep.raw".parse(), Ok(Output::Pcm(PathBuf::from("/tmp/beep.raw"))));
+        assert_eq!("pcm:| aplay -f S16_LE".parse(), Ok(Output::Command("aplay -f S16_LE".to_string())));
+        assert_eq!("wav:beep.wav".parse(), Ok(Output::Wav(PathBuf::from("beep.wav"))));
+        assert!("pcm:".parse::<Output>().is_err());
+        assert!("pcm:|".parse::<Output>().is_err());
+        assert!("wav".parse::<Output>().is_err());
+        assert!("speaker".parse::<Output>().is_err());
+    }
+
+    #[test]
+    fn machine_sound_at_the_volume() {
+        // V0 = 1, sound timer = V0
+        let mut chip8 = Chip8::new();
+        chip8.reset_and_load_bytes(&[0x60, 0x01, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+        let out = Shared::default();
+        let mut audio = Audio::raw(out.clone(), 0.5, 48000);
+        for _ in 0..3 {
+            chip8.run_frame().unwrap();
+            audio.frame(&mut chip8).unwrap();
+        }
+        audio.finish().unwrap();
+
+        let samples = samples(&out.bytes());
+        assert_eq!(samples.len(), 3 * 800);
+        let high = i16::MAX / 2;
+        assert!(samples[..800].contains(&high));
+        assert!(samples[..800].contains(&-high));
+        assert!(samples.iter().all(|sample| sample.abs() <= high));
+        assert!(samples[1600..].iter().all(|sample| *sample == 0));
+    }
+
+    #[test]
+    fn frames_keep_time() {
+        // 22050 / 60 = 367.5
+        let out = Shared::default();
+        let mut audio = Audio::raw(out.clone(), 0.25, 22050);
+        let mut chip8 = Chip8::new();
+        for _ in 0..60 {
+            audio.frame(&mut chip8).unwrap();
+        }
+        assert_eq!(out.bytes().len(), 2 * 22050);
+    }
+
+    #[test]
+    fn wav_header() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44100).unwrap();
+        wav.write(&[1, 0, 2, 0]).unwrap();
+        wav.finish().unwrap();
+        let bytes = wav.into_inner().into_inner();
+
+        assert_eq!(bytes.len(), 48);
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(bytes[4..8], 40u32.to_le_bytes());
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(bytes[24..28], 44100u32.to_le_bytes());
+        assert_eq!(bytes[28..32], 88200u32.to_le_bytes());
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(bytes[40..44], 4u32.to_le_bytes());
+        assert_eq!(bytes[44..], [1, 0, 2, 0]);
+    }
+}
@@ -1,15 +1,29 @@
 #![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
 use chip8_rs::chip8;
-use chip8::Chip8;
-use std::{io, thread, time};
+use chip8::{AddressRange, Beep, Breakpoint, Chip8, OpClass, StepOutcome, TraceFilter, TraceFormat, Tracer, Variant, Waveform};
+use chip8_rs::asm;
+use chip8_rs::audio::{self, Audio};
+use chip8_rs::{dap, gdb};
+use chip8_rs::headless::{self, InputScript};
+use chip8_rs::disasm::{self, Instruction, Syntax};
+use chip8_rs::quirks::Quirks;
+use std::net::TcpListener;
+use std::{fs, io, thread, time::{Duration, Instant}};
+use std::io::BufWriter;
 
 use chip8_rs::platform::{
     Chip8Screen,
+    HostAction,
+    Keyboard,
+    PALETTE,
     pump_input,
     fit_chip8_top_left,
+    registers_panel,
+    disasm_panel,
+    memory_panel,
     LogBuf
 };
 
@@ -23,8 +37,9 @@ use ratatui::{
 };
 
 use ratatui::crossterm::{
+    event::{KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags},
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
 #[derive(Debug, StructOpt)]
@@ -34,20 +49,412 @@ struct Opt {
     #[structopt(short, long)]
     debug: bool,
 
-    /// Set speed (ms per cycle)
-    #[structopt(short = "c", long = "clock", default_value = "10")]
-    speed: u64,
+    /// Set speed (instructions per frame, there are 60 frames per second)
+    #[structopt(short = "i", long = "ipf", default_value = "10")]
+    ipf: u32,
 
     /// Input file
     #[structopt(short = "r", long = "rom", parse(from_os_str))]
-    rom: PathBuf,
+    rom: Option<PathBuf>,
+
+    /// Instruction set: chip8, schip or xochip
+    #[structopt(long = "variant", default_value = "chip8")]
+    variant: Variant,
+
+    /// Quirks preset for ambiguous opcodes: vip, chip48, schip or xochip, with quirks to turn on
+    /// after a + (e.g. vip+key_press). Defaults to the preset matching the variant
+    #[structopt(long = "quirks")]
+    quirks: Option<Quirks>,
+
+    /// Pause when a breakpoint is hit: an address (0x2A4), an address with a condition
+    /// (0x2A4 if VF == 1), a register condition (VF == 1) or an opcode class (Dxyn). Repeatable
+    #[structopt(long = "break", number_of_values = 1)]
+    breakpoints: Vec<Breakpoint>,
+
+    /// Where the beeper goes: bell (the terminal bell), off, pcm:<file> or pcm:|<command> for raw
+    /// 16-bit signed little-endian mono samples, or wav:<file>
+    #[structopt(long = "audio", default_value = "bell")]
+    audio: audio::Output,
+
+    /// Frequency of the beep in Hz
+    #[structopt(long = "pitch", default_value = "440")]
+    pitch: f32,
+
+    /// Volume of the beep, from 0 to 1
+    #[structopt(long = "volume", default_value = "0.25", parse(try_from_str = parse_volume))]
+    volume: f32,
+
+    /// Shape of the beep: square, triangle, sawtooth or sine
+    #[structopt(long = "waveform", default_value = "square")]
+    waveform: Waveform,
+
+    /// Samples per second of the pcm and wav outputs
+    #[structopt(long = "sample-rate", default_value = "44100")]
+    sample_rate: u32,
+
+    /// In terminals without key release events, how many milliseconds a key stays down after a
+    /// press (700). Set it just above the terminal's key repeat delay
+    #[structopt(long = "key-hold")]
+    key_hold: Option<u64>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Play the ROM in the terminal, like --rom without a subcommand, optionally tracing every
+    /// instruction to a file. With --headless it runs without a terminal instead
+    Run {
+        /// Input file
+        #[structopt(short = "r", long = "rom", parse(from_os_str))]
+        rom: PathBuf,
+
+        /// Write a record of every instruction run to this file
+        #[structopt(long = "trace", parse(from_os_str))]
+        trace: Option<PathBuf>,
+
+        /// Trace format: text or jsonl. Defaults to jsonl for .jsonl and .json files, text otherwise
+        #[structopt(long = "trace-format")]
+        trace_format: Option<TraceFormat>,
+
+        /// Only trace the instructions in this address range, e.g. 0x200-0x2FF
+        #[structopt(long = "trace-pc")]
+        trace_pc: Option<AddressRange>,
+
+        /// Only trace these instructions: a mnemonic (DRW, CALL) or an opcode class (Dxyn, 8xy4). Repeatable
+        #[structopt(long = "trace-op", number_of_values = 1)]
+        trace_ops: Vec<OpClass>,
+
+        /// Run without a terminal and as fast as possible, then print how the run ended and a hash of the screen.
+        /// The same options always give the same run
+        #[structopt(long = "headless")]
+        headless: bool,
+
+        /// Stop after this many frames, otherwise a headless run goes on until the ROM exits
+        #[structopt(long = "frames")]
+        frames: Option<u32>,
+
+        /// Keys for a headless run: lines like `30 down 5` and `45 up 5`, the number is the frame
+        #[structopt(long = "input", parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// Write the final screen of a headless run to this file, as a PBM image if it ends in .pbm and as text otherwise
+        #[structopt(long = "dump-screen", parse(from_os_str))]
+        dump_screen: Option<PathBuf>,
+    },
+
+    /// Print a ROM as assembly, telling code and data apart by following its jumps and calls
+    Disasm {
+        /// Input file
+        #[structopt(short = "r", long = "rom", parse(from_os_str))]
+        rom: PathBuf,
+
+        /// Assembly syntax: cowgod or octo
+        #[structopt(long = "syntax", default_value = "cowgod")]
+        syntax: Syntax,
+
+        /// Instruction set: chip8, schip or xochip. Opcodes it doesn't have are listed as data
+        #[structopt(long = "variant", default_value = "chip8")]
+        variant: Variant,
+    },
+
+    /// Assemble Cowgod-style source into a ROM
+    Asm {
+        /// Source file
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Output ROM, defaults to the source file with a .ch8 extension
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Wait for gdb on a local port and let it debug the ROM over the remote serial protocol.
+    /// The options before the subcommand (variant, quirks, speed, breakpoints) still apply
+    Gdbserver {
+        /// Input file
+        #[structopt(short = "r", long = "rom", parse(from_os_str))]
+        rom: PathBuf,
+
+        /// TCP port on 127.0.0.1
+        #[structopt(long = "port", default_value = "1234")]
+        port: u16,
+    },
+
+    /// Debug the ROM from an editor over the Debug Adapter Protocol. The ROM, symbol file,
+    /// variant and quirks come with the editor's launch request
+    Dap {
+        /// Listen on this TCP port on 127.0.0.1 instead of speaking over stdin/stdout
+        #[structopt(long = "port")]
+        port: Option<u16>,
+    },
+}
+
+fn open_audio(opt: &Opt) -> Result<Audio, String> {
+    Audio::open(&opt.audio, opt.volume, opt.sample_rate).map_err(|e| format!("audio {}: {}", opt.audio, e))
+}
+
+fn parse_volume(text: &str) -> Result<f32, String> {
+    match text.parse::<f32>() {
+        Ok(volume) if (0.0..=1.0).contains(&volume) => Ok(volume),
+        _ => Err(format!("volume must be between 0 and 1, got {}", text)),
+    }
+}
+
+/// Number of save state slots, switched with F6/F7
+const STATE_SLOTS: u8 = 10;
+
+/// How long a save/load notice stays on screen
+const NOTICE_TIME: Duration = Duration::from_secs(2);
+
+/// Rewinding goes on this long after the last Backspace event, to bridge the gaps of the key repeat
+const REWIND_HOLD: Duration = Duration::from_millis(150);
+
+/// Save states live next to the ROM: game.ch8 -> game.state0 .. game.state9
+fn state_path(rom: &Path, slot: u8) -> PathBuf {
+    rom.with_extension(format!("state{}", slot))
+}
+
+/// Execution control of the debug view
+struct Debugger {
+    paused: bool,
+    // selected line of the disassembly
+    cursor: u16,
+    // first address of the memory view
+    memory_view: usize,
+}
+
+impl Debugger {
+    /// Handle a debugger key, returns what to tell the user
+    fn handle(&mut self, action: HostAction, chip8: &mut Chip8, halted: &mut Option<String>) -> Option<String> {
+        let pc = chip8.program_counter();
+        match action {
+            HostAction::Pause => {
+                self.paused = !self.paused;
+                chip8.cancel_run_to();
+                self.cursor = pc;
+                Some(if self.paused { "paused" } else { "running" }.to_string())
+            }
+            HostAction::StepOver if chip8.step_over() => {
+                self.paused = false;
+                None
+            }
+            HostAction::Step | HostAction::StepOver => {
+                self.paused = true;
+                chip8.cancel_run_to();
+                if halted.is_none() {
+                    match chip8.debug_step() {
+                        Ok(StepOutcome::Exited) => *halted = Some("ROM exited".to_string()),
+                        Ok(_) => {}
+                        Err(e) => *halted = Some(format!("halted: {}", e)),
+                    }
+                }
+                self.cursor = chip8.program_counter();
+                Some(halted.clone().unwrap_or_else(|| {
+                    format!("{:03X}: {}", chip8.last_pc(), Instruction::decode(chip8.last_opcode()))
+                }))
+            }
+            HostAction::RunToCursor => {
+                chip8.run_to(self.cursor, None);
+                self.paused = false;
+                Some(format!("running to {:03X}", self.cursor))
+            }
+            HostAction::ToggleBreakpoint => {
+                let existing = chip8
+                    .breakpoints()
+                    .find(|(_, breakpoint)| **breakpoint == Breakpoint::Pc(self.cursor))
+                    .map(|(id, _)| id);
+                match existing {
+                    Some(id) => {
+                        chip8.remove_breakpoint(id);
+                        Some(format!("breakpoint at {:03X} removed", self.cursor))
+                    }
+                    None => {
+                        chip8.add_breakpoint(Breakpoint::Pc(self.cursor));
+                        Some(format!("breakpoint at {:03X}", self.cursor))
+                    }
+                }
+            }
+            HostAction::CursorUp => {
+                self.cursor = self.cursor.saturating_sub(2);
+                None
+            }
+            HostAction::CursorDown => {
+                // XO-CHIP memory is 64 KiB, its length doesn't fit a u16
+                self.cursor = (self.cursor as usize + 2).min(chip8.memory().len() - 2) as u16;
+                None
+            }
+            HostAction::MemoryUp => {
+                self.memory_view = self.memory_view.saturating_sub(MEMORY_PAGE);
+                None
+            }
+            HostAction::MemoryDown => {
+                self.memory_view = (self.memory_view + MEMORY_PAGE).min(chip8.memory().len() - MEMORY_PAGE);
+                None
+            }
+            HostAction::MemoryToI => {
+                self.memory_view = chip8.index_register() as usize & !0x7;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Run a frame, or part of it when a breakpoint pauses the machine. Returns what to tell the user
+    fn run_frame(&mut self, chip8: &mut Chip8, halted: &mut Option<String>) -> Option<String> {
+        let hit = match chip8.run_frame() {
+            Ok(StepOutcome::Break(hit)) => hit,
+            Ok(StepOutcome::Exited) => {
+                *halted = Some("ROM exited".to_string());
+                return halted.clone();
+            }
+            Ok(_) => return None,
+            Err(e) => {
+                *halted = Some(format!("halted: {}", e));
+                return halted.clone();
+            }
+        };
+
+        self.paused = true;
+        self.cursor = chip8.program_counter();
+        // reaching the end of a step over or the cursor needs no message
+        hit.id().map(|_| hit.to_string())
+    }
+}
+
+/// Bytes the memory view scrolls by
+const MEMORY_PAGE: usize = 0x40;
+
+/// A machine set up from the command line options, with the ROM loaded
+fn machine(opt: &Opt, rom: &Path) -> Result<Chip8, Box<dyn std::error::Error>> {
+    let mut chip8 = Chip8::with_variant(opt.variant);
+    if let Some(quirks) = opt.quirks {
+        chip8.set_quirks(quirks);
+    }
+    chip8.set_ipf(opt.ipf);
+    chip8.set_beep(Beep { pitch: opt.pitch, waveform: opt.waveform });
+    chip8.load_rom(rom.to_path_buf())?;
+    for breakpoint in &opt.breakpoints {
+        chip8.add_breakpoint(*breakpoint);
+    }
+    Ok(chip8)
+}
+
+/// `run --headless`: no terminal and no clock, the screen and its hash are the result.
+/// The sound is recorded when `audio` goes to a file or a command
+fn run_headless(
+    chip8: &mut Chip8,
+    frames: Option<u32>,
+    input: Option<&Path>,
+    dump: Option<&Path>,
+    mut audio: Option<Audio>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let script: InputScript = match input {
+        Some(path) => fs::read_to_string(path)?.parse().map_err(|e| format!("{}: {}", path.display(), e))?,
+        None => InputScript::default(),
+    };
+    let mut audio_error = None;
+    let outcome = headless::run_with(chip8, frames, &script, |chip8| {
+        if let Some(output) = &mut audio {
+            if let Err(e) = output.frame(chip8) {
+                audio_error = Some(e);
+                audio = None;
+            }
+        }
+    });
+    if let Some(tracer) = chip8.take_tracer() {
+        tracer.finish()?;
+    }
+    if let Some(e) = audio_error {
+        return Err(format!("audio: {}", e).into());
+    }
+    if let Some(output) = audio {
+        output.finish()?;
+    }
+
+    // the screen is written even after a fault, it's often the best clue
+    if let Some(path) = dump {
+        let screen = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("pbm") => headless::screen_pbm(chip8),
+            _ => headless::screen_text(chip8),
+        };
+        fs::write(path, screen)?;
+    }
+    let (end, ran) = outcome?;
+    println!("{} frames, {}", ran, end);
+    println!("screen {}x{} hash {:016x}", chip8.width(), chip8.height(), headless::screen_hash(chip8));
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opt = Opt::from_args();
 
-    let mut chip8: Chip8 = Chip8::new();
-    chip8.load_rom(opt.rom.clone());
+    match &opt.command {
+        Some(Command::Disasm { rom, syntax, variant }) => {
+            let rom = fs::read(rom)?;
+            let max = variant.memory_size() - disasm::ROM_START as usize;
+            if rom.len() > max {
+                return Err(format!("ROM too large: {} bytes, at most {} fit in memory", rom.len(), max).into());
+            }
+            print!("{}", disasm::disassemble(&rom, *variant).listing(*syntax));
+            return Ok(());
+        }
+        Some(Command::Asm { input, output }) => {
+            let rom = match asm::assemble_file(input) {
+                Ok(rom) => rom,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            let output = output.clone().unwrap_or_else(|| input.with_extension("ch8"));
+            fs::write(&output, &rom)?;
+            println!("{}: {} bytes", output.display(), rom.len());
+            return Ok(());
+        }
+        Some(Command::Gdbserver { rom, port }) => {
+            let mut chip8 = machine(&opt, rom)?;
+            let listener = TcpListener::bind(("127.0.0.1", *port))?;
+            println!("Waiting for gdb on {}", listener.local_addr()?);
+            let (stream, peer) = listener.accept()?;
+            println!("Debugging {} for {}", rom.display(), peer);
+            gdb::serve(&mut chip8, stream)?;
+            return Ok(());
+        }
+        Some(Command::Dap { port: Some(port) }) => {
+            let listener = TcpListener::bind(("127.0.0.1", *port))?;
+            eprintln!("Waiting for the editor on {}", listener.local_addr()?);
+            let (stream, _) = listener.accept()?;
+            dap::serve(stream.try_clone()?, stream)?;
+            return Ok(());
+        }
+        // stdout carries the protocol, nothing else may be printed there
+        Some(Command::Dap { port: None }) => {
+            dap::serve(io::stdin(), io::stdout())?;
+            return Ok(());
+        }
+        Some(Command::Run { .. }) | None => {}
+    }
+    let rom = match &opt.command {
+        Some(Command::Run { rom, .. }) => rom.clone(),
+        _ => opt.rom.clone().ok_or("--rom is required")?,
+    };
+    let mut chip8 = machine(&opt, &rom)?;
+    if let Some(Command::Run { trace: Some(path), trace_format, trace_pc, trace_ops, .. }) = &opt.command {
+        let format = trace_format.unwrap_or_else(|| TraceFormat::for_path(path));
+        let filter = TraceFilter { pc: *trace_pc, ops: trace_ops.clone() };
+        let out = BufWriter::new(fs::File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?);
+        chip8.set_tracer(Some(Tracer::new(out, format).with_filter(filter)));
+    }
+    if let Some(Command::Run { headless: true, frames, input, dump_screen, .. }) = &opt.command {
+        // there's no terminal to ring
+        let audio = match opt.audio {
+            audio::Output::Bell => None,
+            _ => Some(open_audio(&opt)?),
+        };
+        return run_headless(&mut chip8, *frames, input.as_deref(), dump_screen.as_deref(), audio);
+    }
 
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -56,52 +463,189 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut terminal = Terminal::new(backend)?;
     terminal.clear()?;
 
-    struct Cleanup;
+    // the kitty keyboard protocol reports key releases, other terminals only presses and repeats
+    let releases = matches!(supports_keyboard_enhancement(), Ok(true));
+    if releases {
+        let flags = KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+            | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+            // shifted letters as upper case, for Q
+            | KeyboardEnhancementFlags::REPORT_ALTERNATE_KEYS;
+        execute!(io::stdout(), PushKeyboardEnhancementFlags(flags))?;
+    }
+
+    struct Cleanup {
+        releases: bool,
+    }
     impl Drop for Cleanup {
         fn drop(&mut self) {
+            if self.releases {
+                let _ = execute!(io::stdout(), PopKeyboardEnhancementFlags);
+            }
             let _ = disable_raw_mode();
             let _ = execute!(io::stdout(), LeaveAlternateScreen);
         }
     }
-    let _cleanup = Cleanup;
+    let _cleanup = Cleanup { releases };
 
     let mut logs = LogBuf::new(200);
+    let mut keyboard = Keyboard::new(releases);
+    if let Some(hold) = opt.key_hold {
+        keyboard.set_hold(Duration::from_millis(hold));
+    }
+    if !releases {
+        logs.push("no key release events, keys are let go when the key repeat stops");
+    }
+
+    let mut audio = Some(open_audio(&opt)?);
+
+    let hz = chip8.ipf() * 60;
+    let frame_time = Duration::from_secs(1) / 60;
+    let mut next_frame = Instant::now();
 
-    let hz = 1000 / opt.speed;
+    // set once the ROM exits or faults, the last frame stays on screen until the user quits
+    let mut halted: Option<String> = None;
+
+    let mut slot: u8 = 0;
+    let mut notice: Option<(String, Instant)> = None;
+    // while Backspace is held the emulation runs backwards, one frame per frame
+    let mut rewind_until: Option<Instant> = None;
+
+    let mut debugger = Debugger {
+        paused: false,
+        cursor: chip8.program_counter(),
+        memory_view: 0x200,
+    };
 
     loop {
-        chip8.Cycle();
+        if rewind_until.is_some_and(|until| Instant::now() < until) {
+            if chip8.rewind(1) > 0 {
+                halted = None;
+                debugger.cursor = chip8.program_counter();
+                keyboard.sync(&mut chip8);
+            }
+            notice = Some((format!("rewind: {} frames left", chip8.rewind_len()), Instant::now()));
+        } else if halted.is_none() && !debugger.paused {
+            if let Some(message) = debugger.run_frame(&mut chip8, &mut halted) {
+                logs.push(message.clone());
+                if halted.is_none() {
+                    notice = Some((message, Instant::now()));
+                }
+            }
+        }
+        if let Some(output) = &mut audio {
+            if let Err(e) = output.frame(&mut chip8) {
+                logs.push(format!("audio stopped: {}", e));
+                audio = None;
+            }
+        }
 
-        let mut frame_keys = [0u8; 16];
-        if pump_input(&mut chip8, &mut frame_keys, &mut logs)? {
-            return Ok(());
+        for action in pump_input(&mut chip8, &mut keyboard, &mut logs)? {
+            let message = match action {
+                HostAction::Quit => {
+                    if let Some(tracer) = chip8.take_tracer() {
+                        tracer.finish()?;
+                    }
+                    if let Some(output) = audio.take() {
+                        output.finish()?;
+                    }
+                    return Ok(());
+                }
+                HostAction::SaveState => {
+                    let path = state_path(&rom, slot);
+                    match fs::write(&path, chip8.save_state()) {
+                        Ok(()) => format!("saved slot {}", slot),
+                        Err(e) => format!("saving {} failed: {}", path.display(), e),
+                    }
+                }
+                HostAction::LoadState => {
+                    let path = state_path(&rom, slot);
+                    let loaded = fs::read(&path)
+                        .map_err(|e| format!("reading {} failed: {}", path.display(), e))
+                        .and_then(|data| chip8.load_state(&data).map_err(|e| e.to_string()));
+                    match loaded {
+                        Ok(()) => {
+                            halted = None;
+                            debugger.cursor = chip8.program_counter();
+                            keyboard.sync(&mut chip8);
+                            format!("loaded slot {}", slot)
+                        }
+                        Err(e) => e,
+                    }
+                }
+                HostAction::PrevSlot => {
+                    slot = (slot + STATE_SLOTS - 1) % STATE_SLOTS;
+                    format!("slot {}", slot)
+                }
+                HostAction::NextSlot => {
+                    slot = (slot + 1) % STATE_SLOTS;
+                    format!("slot {}", slot)
+                }
+                HostAction::Rewind => {
+                    rewind_until = Some(Instant::now() + REWIND_HOLD);
+                    continue;
+                }
+                action => match debugger.handle(action, &mut chip8, &mut halted) {
+                    Some(message) => message,
+                    None => continue,
+                },
+            };
+            logs.push(message.clone());
+            notice = Some((message, Instant::now()));
         }
-        chip8.keypad = frame_keys;
 
-        let video = chip8.video;
+        let notice_text = notice
+            .as_ref()
+            .filter(|(_, shown)| shown.elapsed() < NOTICE_TIME)
+            .map(|(message, _)| message.clone());
+
+        let video = chip8.export_video().to_vec();
+        let (width, height) = (chip8.width(), chip8.height());
 
-        let last_op: u16 = chip8.last_opcode(); // implement this in your Chip8 as needed
+        let last_op = Instruction::decode(chip8.last_opcode());
+        let last_pc = chip8.last_pc();
 
         terminal.draw(|f| {
             let area = f.area();
 
             if !opt.debug {
-                // Fullscreen simple mode
+                // Fullscreen simple mode, with a message line at the bottom once halted
+                // or for a moment after a save state hotkey
+                let bottom = match (&halted, &notice_text) {
+                    (Some(message), _) => Some(Span::styled(message.clone(), Style::default().fg(Color::Red))),
+                    (None, Some(message)) => Some(Span::raw(message.clone())),
+                    (None, None) if debugger.paused => Some(Span::raw("paused")),
+                    (None, None) => None,
+                };
+                let (screen, message) = match bottom {
+                    Some(span) => {
+                        let v = Layout::default()
+                            .direction(Direction::Vertical)
+                            .constraints([Constraint::Min(1), Constraint::Length(1)])
+                            .split(area);
+                        let line = Line::from(vec![span, Span::raw("  quit: Esc or Q")]);
+                        (v[0], Some((Paragraph::new(line), v[1])))
+                    }
+                    None => (area, None),
+                };
                 f.render_widget(
-                    Chip8Screen { video: &video, on: Color::White, off: Color::Black },
-                    area,
+                    Chip8Screen { video: &video, width, height, palette: PALETTE },
+                    screen,
                 );
+                if let Some((paragraph, rect)) = message {
+                    f.render_widget(paragraph, rect);
+                }
                 return;
             }
 
             // Debug layout:
-            // [ top: main row => (left chip8 screen, right logs) ]
-            // [ bottom: status bar with last opcode ]
+            // [ screen                 | registers ]
+            // [ disassembly | memory   | logs      ]
+            // [ status bar with the last instruction and the key bindings ]
             let v = Layout::default()
                 .direction(Direction::Vertical)
                 .constraints([
                     Constraint::Min(3),
-                    Constraint::Length(3),
+                    Constraint::Length(4),
                 ])
                 .split(area);
 
@@ -111,36 +655,94 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let h = Layout::default()
                 .direction(Direction::Horizontal)
                 .constraints([
-                    Constraint::Min(10),
-                    Constraint::Length(32),
+                    Constraint::Min(40),
+                    Constraint::Length(34),
                 ])
                 .split(main);
 
-            let screen_host = h[0];
-            let log_host = h[1];
+            let left = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Percentage(50), Constraint::Min(5)])
+                .split(h[0]);
+            let code = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Length(34), Constraint::Min(20)])
+                .split(left[1]);
+            let right = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(10), Constraint::Min(3)])
+                .split(h[1]);
 
-            let screen_rect = fit_chip8_top_left(screen_host);
+            let screen_rect = fit_chip8_top_left(left[0]);
             f.render_widget(
-                Chip8Screen { video: &video, on: Color::White, off: Color::Black },
+                Chip8Screen { video: &video, width, height, palette: PALETTE },
                 screen_rect,
             );
 
+            let rows = |rect: ratatui::layout::Rect| rect.height.saturating_sub(2);
+            f.render_widget(disasm_panel(&chip8, debugger.cursor, rows(code[0])), code[0]);
+            f.render_widget(memory_panel(&chip8, debugger.memory_view, rows(code[1])), code[1]);
+            f.render_widget(registers_panel(&chip8), right[0]);
+
             let log_para = logs.to_paragraph("logs");
-            f.render_widget(log_para, log_host);
+            f.render_widget(log_para, right[1]);
 
-            let status_line = Paragraph::new(Line::from(vec![
-                Span::raw("opcode: "),
-                Span::styled(format!("{:04X}", last_op), Style::default().fg(Color::Yellow)),
-                Span::raw("\tquit:\t Esc or Q"),
+            let mut status_spans = vec![
+                Span::raw("last: "),
+                Span::styled(format!("{:03X}: {}", last_pc, last_op), Style::default().fg(Color::Yellow)),
                 Span::raw("\t Speed: "),
-                Span::styled(format!("{:X}", hz), Style::default().fg(Color::Red)),
+                Span::styled(format!("{}", hz), Style::default().fg(Color::Red)),
                 Span::raw(" Hz"),
-            ]))
+                Span::raw("\t slot: "),
+                Span::styled(format!("{}", slot), Style::default().fg(Color::Yellow)),
+            ];
+            if let Some(message) = &halted {
+                status_spans.push(Span::raw("\t"));
+                status_spans.push(Span::styled(message.clone(), Style::default().fg(Color::Red)));
+            } else if let Some(message) = &notice_text {
+                status_spans.push(Span::raw("\t"));
+                status_spans.push(Span::raw(message.clone()));
+            } else if debugger.paused {
+                status_spans.push(Span::raw("\t"));
+                status_spans.push(Span::styled("paused", Style::default().fg(Color::Yellow)));
+            }
+            let keys = Line::from(
+                "p pause  n step  o step over  g run to cursor  b breakpoint  \u{2191}\u{2193} cursor  PgUp/PgDn/i memory  \
+                 F5/F9 save/load  F6/F7 slot  Backspace rewind  Esc/Q quit",
+            );
+            let status_line = Paragraph::new(vec![Line::from(status_spans), keys])
             .block(Block::default().borders(Borders::ALL).title("status"));
             f.render_widget(status_line, status);
         })?;
 
-        thread::sleep(time::Duration::from_millis(opt.speed));
+        // timers run at 60 Hz no matter how long a frame took to emulate and draw
+        next_frame += frame_time;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::{Debugger, HostAction};
+    use chip8_rs::chip8::{Chip8, Variant};
+
+    #[test]
+    fn cursor_stops_at_the_end_of_memory() {
+        let mut chip8 = Chip8::with_variant(Variant::XoChip);
+        let mut debugger = Debugger { paused: true, cursor: 0xFFFA, memory_view: 0x200 };
+        for _ in 0..4 {
+            debugger.handle(HostAction::CursorDown, &mut chip8, &mut None);
+        }
+        assert_eq!(debugger.cursor, 0xFFFE);
+
+        let mut chip8 = Chip8::new();
+        debugger.cursor = 0xFFE;
+        debugger.handle(HostAction::CursorDown, &mut chip8, &mut None);
+        assert_eq!(debugger.cursor, 0xFFE);
+    }
+}
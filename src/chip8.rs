@@ -2,22 +2,91 @@
 #![allow(unused_parens)]
 #![allow(dead_code)]
 
-use std::{convert::TryFrom, ops::ShlAssign};
-use std::fs::File;
-use std::io::prelude::*;
-use std::convert::TryInto;
 use std::path::PathBuf;
-use std::ops::ShrAssign;
 use std::collections::VecDeque;
 use std::{thread, time};
 
-use function_name::named;
+use crate::disasm::Instruction;
+use crate::quirks::Quirks;
+
+mod audio;
+mod breakpoints;
+mod rewind;
+mod state;
+#[cfg(test)]
+pub(crate) mod testing;
+mod trace;
+pub use audio::{Beep, Waveform};
+use audio::AudioQueue;
+pub use breakpoints::{Access, Break, Breakpoint, BreakpointId, Watchpoint};
+use breakpoints::Hooks;
+pub use rewind::DEFAULT_REWIND_BUDGET;
+use rewind::RewindBuffer;
+pub use state::StateError;
+pub use trace::{AddressRange, OpClass, TraceFilter, TraceFormat, TraceRecord, Tracer};
+
+/// Number of pixels in the biggest framebuffer, the 128x64 SUPER-CHIP high resolution mode
+pub const VIDEO_SIZE: usize = 128 * 64;
+
+/// Instructions run per 60 Hz frame unless `set_ipf` is called, roughly 600 instructions per second
+pub const DEFAULT_IPF: u32 = 10;
+
+/// Where the small 4x5 font starts in memory
+const FONTSET_START_ADDRESS: usize = 0x50;
+/// Where the big 8x10 SUPER-CHIP font starts in memory, right after the small one
+const BIG_FONTSET_START_ADDRESS: usize = 0xA0;
+
+/// The instruction set the interpreter understands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    /// The original 35 CHIP-8 opcodes
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1: scrolling, 128x64 high resolution mode, 16x16 sprites, big font and RPL flags
+    SuperChip,
+    /// XO-CHIP: SUPER-CHIP plus 64 KiB of memory, two bitplanes and an audio pattern buffer
+    XoChip,
+}
+
+impl Variant {
+    /// The quirks preset matching the interpreter this variant comes from
+    pub fn default_quirks(self) -> Quirks {
+        match self {
+            Variant::Chip8 => Quirks::COSMAC_VIP,
+            Variant::SuperChip => Quirks::SUPER_CHIP,
+            Variant::XoChip => Quirks::XO_CHIP,
+        }
+    }
+
+    /// Size of the address space in bytes
+    pub fn memory_size(self) -> usize {
+        match self {
+            Variant::Chip8 | Variant::SuperChip => 0x1000,
+            Variant::XoChip => 0x10000,
+        }
+    }
+}
+
+impl std::str::FromStr for Variant {
+    type Err = String;
+
+    /// Parse a variant name: `"chip8"`, `"schip"` or `"xochip"`
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+            "chip8" => Ok(Variant::Chip8),
+            "schip" | "superchip" => Ok(Variant::SuperChip),
+            "xochip" => Ok(Variant::XoChip),
+            _ => Err(format!("Unknown variant: {} (expected one of: chip8, schip, xochip)", name)),
+        }
+    }
+}
 
 
 /// General chip 8 struct
 pub struct Chip8 {
     registers: [u8; 16],
-    memory: [u8; 4096],
+    // 4 KiB, or 64 KiB on XO-CHIP
+    memory: Vec<u8>,
     index_register: u16,
     program_counter: u16,
     stack: [u16; 16],
@@ -26,38 +95,75 @@ pub struct Chip8 {
     sound_timer: u8,
     pub keypad: [u8; 16],
     recent_presses: VecDeque<u8>,
-    pub video: [u32; 64 * 32],
+    /// One entry per pixel, nonzero = ON. Only the first `width() * height()` entries are in use.
+    /// Bit 0 is the first bitplane and bit 1 the second one, only XO-CHIP draws on the second plane
+    pub video: [u32; VIDEO_SIZE],
     op_code: u16,
-    table: [fn(&mut Chip8); 0xF+1],
-    table0: [fn(&mut Chip8); 0xE+1],
-    table8: [fn(&mut Chip8); 0xE+1],
-    tableE: [fn(&mut Chip8); 0xE+1],
-    tableF: [fn(&mut Chip8); 0x65+1],
-    debug_mode: bool,
     last_opcode: u16,
+    // address of `last_opcode`
+    last_pc: u16,
+    // set when a step fails, the machine stays halted until the next reset
+    fault: Option<Chip8Error>,
     rng_state: u32,
+    quirks: Quirks,
+    // set by DXYN when the display wait quirk is on, cleared at the next vertical blank
+    vblank_wait: bool,
+    // set by FX0A, PC stays on it until the key comes, see `poll_key_wait`
+    key_wait: Option<KeyWait>,
+    // instructions per frame, used by run_frame
+    ipf: u32,
+    // instructions run so far in the current frame, a breakpoint can stop run_frame halfway
+    frame_steps: u32,
+    variant: Variant,
+    // SUPER-CHIP 128x64 mode
+    hires: bool,
+    // set by 00FD, the machine stops executing until the next reset
+    exited: bool,
+    // SUPER-CHIP RPL user flags, saved and loaded by FX75/FX85
+    rpl_flags: [u8; 16],
+    // XO-CHIP bitplanes selected by FN01, bit 0 is the first plane
+    plane: u8,
+    // XO-CHIP 1-bit audio samples loaded by F002
+    audio_pattern: [u8; 16],
+    // XO-CHIP playback rate of the audio pattern, set by FX3A
+    pitch: u8,
+    // states at the start of recent frames, not part of save states
+    rewind: RewindBuffer,
+    // breakpoints and watchpoints, not part of save states either
+    hooks: Hooks,
+    // execution trace, a host setting like the breakpoints
+    tracer: Option<Tracer>,
+    // sound of the last frames for fill_audio, and the beep it plays
+    audio: AudioQueue,
+    beep: Beep,
 }
 
 
+impl Default for Chip8 {
+    fn default() -> Self {
+        Chip8::new()
+    }
+}
+
 impl Chip8 {
     /// Create a new chip8 instance with an empty `rom`, ready for use
     ///
     /// # Example:
     ///
     /// ```
+    /// # use chip8_rs::chip8::Chip8;
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
     /// let mut chip8: Chip8 = Chip8::new();
     ///
-    /// chip.load_rom(path);
+    /// // point I at the font sprite of V0, draw it at (V0, V0), then loop forever
+    /// chip8.reset_and_load_bytes(&[0xF0, 0x29, 0xD0, 0x05, 0x12, 0x04])?;
     ///
-    /// let rom: PathBuf = '...';
-    /// let speed: u64 = 30;
-    ///
-    /// loop {
-    ///
-    ///     chip8.Cycle();
-    ///     chip8.pretty_print_video(rom);
-    ///     thread::sleep(time::Duration::from_millis(speed);
+    /// for _ in 0..3 {
+    ///     chip8.run_frame()?;
     /// }
+    /// assert!(chip8.export_video().iter().any(|&pixel| pixel != 0));
+    /// # Ok(())
+    /// # }
     /// ```
     ///
     /// ## Values
@@ -74,11 +180,39 @@ impl Chip8 {
     /// * `video`: all zeroes
     /// * `op_code`: 0
     /// * `fontset_size`: 80
+    /// * `quirks`: `Quirks::COSMAC_VIP`
+    /// * `variant`: `Variant::Chip8`
     pub fn new() -> Self {
+        Chip8::with_quirks(Quirks::default())
+    }
+
+    /// Create a new chip8 instance running the given instruction set, with its default quirks
+    ///
+    /// ```
+    /// # use chip8_rs::chip8::{Chip8, Variant};
+    /// # use chip8_rs::quirks::Quirks;
+    /// let chip8: Chip8 = Chip8::with_variant(Variant::SuperChip);
+    /// assert_eq!(chip8.quirks(), Quirks::SUPER_CHIP);
+    /// ```
+    pub fn with_variant(variant: Variant) -> Self {
+        let mut chip8 = Chip8::with_quirks(variant.default_quirks());
+        chip8.set_variant(variant);
+        chip8
+    }
+
+    /// Create a new chip8 instance that interprets the ambiguous opcodes according to `quirks`
+    ///
+    /// ```
+    /// # use chip8_rs::chip8::Chip8;
+    /// # use chip8_rs::quirks::Quirks;
+    /// let chip8: Chip8 = Chip8::with_quirks(Quirks::SUPER_CHIP);
+    /// assert_eq!(chip8.quirks(), Quirks::SUPER_CHIP);
+    /// ```
+    pub fn with_quirks(quirks: Quirks) -> Self {
 
         let mut chip8: Chip8 = Chip8 {
             registers: [0; 16],
-            memory: [0; 4096],
+            memory: vec![0; Variant::Chip8.memory_size()],
             index_register: 0,
             program_counter: 0x200,
             stack: [0; 16],
@@ -87,121 +221,139 @@ impl Chip8 {
             sound_timer: 0,
             keypad: [0; 16],
             recent_presses: VecDeque::new(),
-            video: [0; 2048],
+            video: [0; VIDEO_SIZE],
             op_code: 0,
-            table: [Chip8::OP_ERR; 0xF+1],
-            table0: [Chip8::OP_ERR; 0xE+1],
-            table8: [Chip8::OP_ERR; 0xE+1],
-            tableE: [Chip8::OP_ERR; 0xE+1],
-            tableF: [Chip8::OP_ERR; 0x65+1],
-            debug_mode: false,
             last_opcode: 0,
+            last_pc: 0,
+            fault: None,
             rng_state: 77,
+            quirks,
+            vblank_wait: false,
+            key_wait: None,
+            ipf: DEFAULT_IPF,
+            frame_steps: 0,
+            variant: Variant::Chip8,
+            hires: false,
+            exited: false,
+            rpl_flags: [0; 16],
+            plane: 1,
+            audio_pattern: [0; 16],
+            pitch: 64,
+            rewind: RewindBuffer::new(DEFAULT_REWIND_BUDGET),
+            hooks: Hooks::default(),
+            tracer: None,
+            audio: AudioQueue::default(),
+            beep: Beep::default(),
         };
 
         chip8.load_fonts();
-        chip8.add_table();
 
-        return chip8;
+        chip8
     }
 
     pub fn last_opcode(&self) -> u16 { self.last_opcode }
-    /// Runs the CHIP8 Machine forever with the currently loaded ROM.
-    /// The clock speed is determined by the passed in `speed` parameter.
-    ///
-    /// ```
-    /// loop {
-    ///     self.Cycle();
-    ///     self.pretty_print_video();
-    ///     thread::sleep(cycle_wait_time);
-    /// }
-    /// ```
-    pub fn play(&mut self, speed: u64) {
-        let cycle_wait_time = time::Duration::from_millis(speed);
 
-        loop {
-            self.Cycle();
-            self.pretty_print_video();
-            thread::sleep(cycle_wait_time);
-        }
+    /// Address the last opcode was fetched from
+    pub fn last_pc(&self) -> u16 { self.last_pc }
+
+    /// The error that halted the machine, if any
+    pub fn fault(&self) -> Option<&Chip8Error> { self.fault.as_ref() }
+
+    pub fn quirks(&self) -> Quirks { self.quirks }
+
+    /// Change the quirks used by the following instructions, the machine state is left untouched
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
     }
 
-    /// Adds the correct function pointer tables to the newly created Chip8 object
-    pub fn add_table(&mut self) {
-        if (self.debug_mode) {
-            eprintln!("Loading tables");
-        }
+    /// Instructions executed by every `run_frame`
+    pub fn ipf(&self) -> u32 { self.ipf }
 
-        let table: [fn(&mut Chip8); 0xF+1] = [
-            Chip8::Table0,
-            Chip8::OP_1nnn,
-            Chip8::OP_2nnn,
-            Chip8::OP_3xkk,
-            Chip8::OP_4xkk,
-            Chip8::OP_5xy0,
-            Chip8::OP_6xkk,
-            Chip8::OP_7xkk,
-            Chip8::Table8,
-            Chip8::OP_9xy0,
-            Chip8::OP_Annn,
-            Chip8::OP_Bnnn,
-            Chip8::OP_Cxkk,
-            Chip8::OP_Dxyn,
-            Chip8::TableE,
-            Chip8::TableF
-        ];
+    /// Set the instructions executed by every `run_frame`, at least one
+    pub fn set_ipf(&mut self, ipf: u32) {
+        self.ipf = ipf.max(1);
+    }
 
-        // Chip8::OP_ERR here is just a placeholder, technically the array is already filled with
-        // OP_ERR
-        let mut table0: [fn(&mut Chip8); 0xE+1] = [Chip8::OP_ERR; 0xE+1];
-        let mut table8: [fn(&mut Chip8); 0xE+1] = [Chip8::OP_ERR; 0xE+1];
-        let mut tableE: [fn(&mut Chip8); 0xE+1] = [Chip8::OP_ERR; 0xE+1];
-        let mut tableF: [fn(&mut Chip8); 0x65+1] = [Chip8::OP_ERR; 0x65+1];
-
-        table0[0x0] = Chip8::OP_00E0;
-        table0[0xE] = Chip8::OP_00EE;
-
-        table8[0x0] = Chip8::OP_8xy0;
-        table8[0x1] = Chip8::OP_8xy1;
-        table8[0x2] = Chip8::OP_8xy2;
-        table8[0x3] = Chip8::OP_8xy3;
-        table8[0x4] = Chip8::OP_8xy4;
-        table8[0x5] = Chip8::OP_8xy5;
-        table8[0x6] = Chip8::OP_8xy6;
-        table8[0x7] = Chip8::OP_8xy7;
-        table8[0xE] = Chip8::OP_8xyE;
-
-        tableE[0x1] = Chip8::OP_ExA1;
-        tableE[0xE] = Chip8::OP_Ex9E;
-
-        tableF[0x07] = Chip8::OP_Fx07;
-        tableF[0x0A] = Chip8::OP_Fx0A;
-        tableF[0x15] = Chip8::OP_Fx15;
-        tableF[0x18] = Chip8::OP_Fx18;
-        tableF[0x1E] = Chip8::OP_Fx1E;
-        tableF[0x29] = Chip8::OP_Fx29;
-        tableF[0x33] = Chip8::OP_Fx33;
-        tableF[0x55] = Chip8::OP_Fx55;
-        tableF[0x65] = Chip8::OP_Fx65;
-
-
-        // Apply the newly generated tables
-        self.table = table;
-        self.table0 = table0;
-        self.table8 = table8;
-        self.tableE = tableE;
-        self.tableF = tableF;
-
-        if (self.debug_mode) {
-            eprintln!("Tables loaded");
-        }
+    /// V0 to VF
+    pub fn registers(&self) -> &[u8; 16] { &self.registers }
+
+    /// The I register
+    pub fn index_register(&self) -> u16 { self.index_register }
+
+    /// Address of the next instruction
+    pub fn program_counter(&self) -> u16 { self.program_counter }
+
+    /// Return addresses of the calls in progress, the innermost one last
+    pub fn stack(&self) -> &[u16] { &self.stack[..(self.stack_pointer as usize).min(self.stack.len())] }
+
+    pub fn stack_pointer(&self) -> u8 { self.stack_pointer }
+
+    /// The whole address space, 4 KiB or 64 KiB on XO-CHIP
+    pub fn memory(&self) -> &[u8] { &self.memory }
+
+    /// Writable memory, for debuggers patching the running program
+    pub fn memory_mut(&mut self) -> &mut [u8] { &mut self.memory }
+
+    pub fn delay_timer(&self) -> u8 { self.delay_timer }
+
+    pub fn sound_timer(&self) -> u8 { self.sound_timer }
+
+    pub fn variant(&self) -> Variant { self.variant }
+
+    /// Switch the instruction set, the quirks are left untouched.
+    /// Memory is grown or truncated to the size of the new address space
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+        self.memory.resize(variant.memory_size(), 0);
+    }
+
+    /// Width of the screen in the current resolution
+    pub fn width(&self) -> usize { if self.hires { 128 } else { 64 } }
+
+    /// Height of the screen in the current resolution
+    pub fn height(&self) -> usize { if self.hires { 64 } else { 32 } }
+
+    pub fn hires(&self) -> bool { self.hires }
+
+    /// Whether the ROM quit the interpreter with 00FD
+    pub fn exited(&self) -> bool { self.exited }
+
+    /// Bitplanes selected for drawing, bit 0 is the first plane
+    pub fn plane(&self) -> u8 { self.plane }
+
+    /// The XO-CHIP audio pattern buffer, 128 1-bit samples played from the most significant bit
+    pub fn audio_pattern(&self) -> &[u8; 16] { &self.audio_pattern }
+
+    /// The XO-CHIP pitch register
+    pub fn pitch(&self) -> u8 { self.pitch }
+
+    /// Playback rate of the audio pattern buffer in samples per second: `4000 * 2^((pitch - 64) / 48)`
+    pub fn pattern_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
     }
 
-    /// Toggle debug mode for current chip8 instance
-    pub fn debug(&mut self) {
-        // bitwise xor
-        self.debug_mode ^= true;
-        eprintln!("Debug mode activated");
+    /// Runs the CHIP8 Machine with the currently loaded ROM until it exits or faults,
+    /// 60 frames per second of `ipf` instructions each.
+    ///
+    /// ```no_run
+    /// # use chip8_rs::chip8::Chip8;
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut chip8 = Chip8::new();
+    /// chip8.load_rom("roms/IBM-Logo.ch8".into())?;
+    /// chip8.play()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn play(&mut self) -> Result<(), Chip8Error> {
+        let frame_time = time::Duration::from_secs(1) / 60;
+
+        loop {
+            if self.run_frame()? == StepOutcome::Exited {
+                return Ok(());
+            }
+            self.pretty_print_video();
+            thread::sleep(frame_time);
+        }
     }
 
     /// needed for wasm
@@ -215,6 +367,19 @@ impl Chip8 {
         (x & 0xFF) as u8
     }
 
+    /// Error for a memory access at `address` by the instruction being executed
+    fn mem_oob(&self, address: usize) -> Chip8Error {
+        Chip8Error::MemOob { pc: self.last_pc, opcode: self.op_code, address }
+    }
+
+    /// Check that the `len` bytes starting at `address` are all in memory
+    fn check_range(&self, address: usize, len: usize) -> Result<(), Chip8Error> {
+        if address + len > self.memory.len() {
+            return Err(self.mem_oob(address + len - 1));
+        }
+        Ok(())
+    }
+
     pub fn reset_hard(&mut self) {
         // keep fonts if you store them in low memory; reload them here if needed
         self.memory.fill(0);
@@ -226,21 +391,34 @@ impl Chip8 {
         self.stack.fill(0);
 
         self.video.fill(0);
+        self.hires = false;
         self.delay_timer = 0;
         self.sound_timer = 0;
 
-        self.video.fill(0);
         self.keypad.fill(0);
+        self.exited = false;
+        self.vblank_wait = false;
+        self.key_wait = None;
+
+        self.plane = 1;
+        self.audio_pattern.fill(0);
+        self.pitch = 64;
 
         self.rng_state = 0x1234_5678; // fixed seed for determinism
         self.last_opcode = 0;
+        self.last_pc = 0;
+        self.fault = None;
+        self.frame_steps = 0;
+        self.hooks.interrupt();
+        self.rewind.clear();
+        self.audio.clear();
     }
 
     pub fn reset_and_load_bytes(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
         self.reset_hard();
         const START: usize = 0x200;
         if START + rom.len() > self.memory.len() {
-            return Err(Chip8Error::MemOob(START + rom.len()));
+            return Err(Chip8Error::RomTooLarge { size: rom.len() });
         }
         self.memory[START..START + rom.len()].copy_from_slice(rom);
         Ok(())
@@ -249,7 +427,7 @@ impl Chip8 {
     /// Loads a given rom into memory, starting from memory address 0x200
     pub fn load_rom(&mut self, path: PathBuf) -> Result<(), std::io::Error> {
         let bytes = std::fs::read(path)?;
-        self.reset_and_load_bytes(&bytes).map_err(|_| std::io::ErrorKind::InvalidData.into())
+        self.reset_and_load_bytes(&bytes).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))
     }
 
     pub fn load_rom_bytes(&mut self, rom: &[u8]) {
@@ -264,28 +442,31 @@ impl Chip8 {
         self.memory[START..START + copy_len].copy_from_slice(&rom[..copy_len]);
     }
 
-    /// Returns `byte_number` amount of bytes after the `pointer` in rom
+    /// Returns `byte_number` amount of bytes after the `pointer` in rom, stopping at the end of memory
     pub fn dump_rom(&self, start_address: usize, byte_number: usize) -> Vec<u8> {
         let mut rom: Vec<u8> = Vec::new();
+        let end = start_address.saturating_add(byte_number).min(self.memory.len());
         // for each memory address after the pointer add that address to the buffer, then return the buffer
-        for i in start_address..start_address + byte_number {
+        for i in start_address..end {
             rom.push(self.memory[i]);
         }
-        return rom;
+        rom
     }
 
-    pub fn export_video(&mut self) -> &[u32; 64 * 32] {
-        &self.video
+    /// The pixels of the current resolution, row by row
+    pub fn export_video(&self) -> &[u32] {
+        &self.video[..self.width() * self.height()]
     }
 
     pub fn pretty_print_video(&mut self) {
         // █ &(0xFFFFFFFF as u32)
         //print!("\x1B[2J\x1B[1;1H");
         print!("\x1B[2J\x1B[1;1H");
-        let mut new_vec = self.video.iter().peekable();
+        let width = self.width();
+        let mut new_vec = self.export_video().iter().peekable();
         let mut rows: Vec<Vec<_>> = vec![];
         while new_vec.peek().is_some() {
-            let chunk: Vec<_> = new_vec.by_ref().take(64).collect();
+            let chunk: Vec<_> = new_vec.by_ref().take(width).collect();
             rows.push(chunk);
         }
 
@@ -293,7 +474,7 @@ impl Chip8 {
             let mut current_row = "".to_string();
 
             for pixel in row {
-                if (pixel == &(0xFFFFFFFF as u32)) {
+                if (*pixel != 0) {
                     current_row.push('█');
                 } else {
                     current_row.push(' ');
@@ -324,19 +505,28 @@ impl Chip8 {
             0xF0, 0x80, 0xF0, 0x80, 0x80, // F];
         ];
 
-        let fontset_start_address = 0x50;
-
-        if (self.debug_mode) {
-            eprintln!("Loading fontset");
-        }
-
-        for i in 0..fontset.len() {
-            self.memory[fontset_start_address + i as usize] = fontset[i as usize];
-        }
+        // SUPER-CHIP 8x10 digits, A-F come from XO-CHIP
+        let big_fontset: [u8; 160] = [
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+        ];
 
-        if (self.debug_mode) {
-            eprintln!("Fontset loaded");
-        }
+        self.memory[FONTSET_START_ADDRESS..FONTSET_START_ADDRESS + fontset.len()].copy_from_slice(&fontset);
+        self.memory[BIG_FONTSET_START_ADDRESS..BIG_FONTSET_START_ADDRESS + big_fontset.len()].copy_from_slice(&big_fontset);
     }
 
     #[inline]
@@ -359,7 +549,7 @@ impl Chip8 {
         self.keypad[k as usize] != 0
     }
 
-    /// Use this in FX0A (wait for key): returns one press if available.
+    /// Returns the oldest key press not taken yet. FX0A takes its keys from the same queue
     pub fn take_recent_press(&mut self) -> Option<u8> {
         self.recent_presses.pop_front()
     }
@@ -373,649 +563,779 @@ impl Chip8 {
     //
 
     /// OPCODE 00E0 - Clear Screen
-    #[named]
-    pub fn OP_00E0(&mut self) {
-        // set video buffer to zero
-        self.video = [0; 2048];
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+    /// Only the selected bitplanes are cleared
+    fn OP_00E0(&mut self) -> Result<(), Chip8Error> {
+        let planes = self.plane as u32;
+        for pixel in self.video.iter_mut() {
+            *pixel &= !planes;
+        }
+        Ok(())
+    }
+
+    /// Move the pixels of the selected bitplanes by `dx` and `dy`, pixels scrolled in are off
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let planes = self.plane as u32;
+        let old = self.video;
+
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&src_x) && (0..height).contains(&src_y) {
+                    old[(src_y * width + src_x) as usize] & planes
+                } else {
+                    0
+                };
+
+                let pixel = &mut self.video[(y * width + x) as usize];
+                *pixel = (*pixel & !planes) | moved;
+            }
         }
     }
 
+    /// OPCODE 00CN - Scroll the display down by N pixels (SUPER-CHIP)
+    fn OP_00Cn(&mut self, n: u8) -> Result<(), Chip8Error> {
+        self.scroll(0, n as isize);
+        Ok(())
+    }
+
+    /// OPCODE 00DN - Scroll the display up by N pixels (XO-CHIP)
+    fn OP_00Dn(&mut self, n: u8) -> Result<(), Chip8Error> {
+        self.scroll(0, -(n as isize));
+        Ok(())
+    }
+
+    /// OPCODE 00FB - Scroll the display right by 4 pixels (SUPER-CHIP)
+    fn OP_00FB(&mut self) -> Result<(), Chip8Error> {
+        self.scroll(4, 0);
+        Ok(())
+    }
+
+    /// OPCODE 00FC - Scroll the display left by 4 pixels (SUPER-CHIP)
+    fn OP_00FC(&mut self) -> Result<(), Chip8Error> {
+        self.scroll(-4, 0);
+        Ok(())
+    }
+
+    /// OPCODE 00FD - Exit the interpreter (SUPER-CHIP)
+    fn OP_00FD(&mut self) -> Result<(), Chip8Error> {
+        self.exited = true;
+        Ok(())
+    }
+
+    /// OPCODE 00FE - Switch to the 64x32 low resolution mode and clear the screen (SUPER-CHIP)
+    fn OP_00FE(&mut self) -> Result<(), Chip8Error> {
+        self.hires = false;
+        self.video.fill(0);
+        Ok(())
+    }
+
+    /// OPCODE 00FF - Switch to the 128x64 high resolution mode and clear the screen (SUPER-CHIP)
+    fn OP_00FF(&mut self) -> Result<(), Chip8Error> {
+        self.hires = true;
+        self.video.fill(0);
+        Ok(())
+    }
+
     /// OPCODE 00EE - Return from subroutine
-    #[named]
-    fn OP_00EE(&mut self) {
-        self.stack_pointer = self.stack_pointer - 1;
+    fn OP_00EE(&mut self) -> Result<(), Chip8Error> {
+        if self.stack_pointer == 0 {
+            return Err(Chip8Error::StackUnderflow { pc: self.last_pc, opcode: self.op_code });
+        }
+        self.stack_pointer -= 1;
 
         self.program_counter = self.stack[self.stack_pointer as usize];
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+        Ok(())
     }
 
     /// OPCODE 1NNN - Jump to location NNN(set program counter to nnn)
-    #[named]
-    fn OP_1nnn(&mut self) {
-        // using 0x0FFF I can take the NNN from the opcode while leaving the one
-        let address: u16 = self.op_code & 0x0FFF;
-
+    fn OP_1nnn(&mut self, address: u16) -> Result<(), Chip8Error> {
         self.program_counter = address;
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+        Ok(())
     }
 
     /// OPCODE 2NNN - Call subroutine at location NNN
-    #[named]
-    fn OP_2nnn(&mut self) {
-        let address: u16 = self.op_code & 0x0FFF;
-
+    fn OP_2nnn(&mut self, address: u16) -> Result<(), Chip8Error> {
+        if self.stack_pointer as usize == self.stack.len() {
+            return Err(Chip8Error::StackOverflow { pc: self.last_pc, opcode: self.op_code });
+        }
         self.stack[self.stack_pointer as usize] = self.program_counter;
-        self.stack_pointer = self.stack_pointer + 1;
+        self.stack_pointer += 1;
         self.program_counter = address;
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+        Ok(())
     }
 
-    /// OPCODE 3XKK - Skip next instruction if Vx = kk
-    /// Since our PC has already been incremented by 2 in Cycle(), we can just increment by 2 again to skip the next instruction.
-    #[named]
-    fn OP_3xkk(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-        let byte: u16 = self.op_code & 0x00FF;
-
-        let byte: u8 = match u8::try_from(byte) {
-            Ok(number) => number,
-            Err(error) => panic!(
-                "Could not turn u16 into u8 in OPCODE: 3XKK. Error: {}",
-                error
-            ),
-        };
+    /// Skip the next instruction. On XO-CHIP the 4 byte `F000 NNNN` is skipped as a whole
+    fn skip_next(&mut self) {
+        let pc = self.program_counter as usize;
+        let long_load = self.variant == Variant::XoChip
+            && self.memory.get(pc) == Some(&0xF0)
+            && self.memory.get(pc + 1) == Some(&0x00);
 
-        if self.registers[Vx as usize] == byte {
-            self.program_counter += 2;
-        }
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+        self.program_counter = self.program_counter.wrapping_add(if long_load { 4 } else { 2 });
+    }
+
+    /// OPCODE 3XKK - Skip next instruction if Vx = kk
+    /// Since our PC has already been incremented by 2 in `execute`, we can just increment by 2 again to skip the next instruction.
+    fn OP_3xkk(&mut self, x: usize, byte: u8) -> Result<(), Chip8Error> {
+        if self.registers[x] == byte {
+            self.skip_next();
         }
+        Ok(())
     }
 
     /// OPCODE 4XKK - Skip next instruction if Vx != kk
-    #[named]
-    fn OP_4xkk(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        let byte: u16 = self.op_code & 0x00FF;
-
-        let byte: u8 = match u8::try_from(byte) {
-            Ok(number) => number,
-            Err(error) => panic!(
-                "Could not turn u16 into u8 in OPCODE: 3XKK. Error: {}",
-                error
-            ),
-        };
-
-        // this != is the onlh difference from the function above
-        if self.registers[Vx as usize] != byte {
-            self.program_counter += 2;
-        }
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+    fn OP_4xkk(&mut self, x: usize, byte: u8) -> Result<(), Chip8Error> {
+        if self.registers[x] != byte {
+            self.skip_next();
         }
+        Ok(())
     }
 
     /// OPCODE 5XY0 - Skip next instruction if Vx = Vy.
-    #[named]
-    fn OP_5xy0(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
+    fn OP_5xy0(&mut self, x: usize, y: usize) -> Result<(), Chip8Error> {
+        if self.registers[x] == self.registers[y] {
+            self.skip_next();
+        }
+        Ok(())
+    }
 
-        let Vy: u16 = (self.op_code & 0x00F0).checked_shr(4).unwrap_or(0);
+    /// OPCODE 5XY2 - Store registers Vx through Vy in memory starting at location I (XO-CHIP)
+    /// The registers are stored in reverse order when x > y, I is left untouched
+    fn OP_5xy2(&mut self, x: usize, y: usize) -> Result<(), Chip8Error> {
+        let count = x.abs_diff(y) + 1;
+        self.check_range(self.index_register as usize, count)?;
+        self.watch(self.index_register as usize, count, Access::Write);
 
-        if self.registers[Vx as usize] == self.registers[Vy as usize] {
-            self.program_counter += 2;
-        }
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+        for i in 0..count {
+            let register = if x <= y { x + i } else { x - i };
+            self.memory[self.index_register as usize + i] = self.registers[register];
         }
+        Ok(())
     }
 
-    /// OPCODE 6XKK - Set Vx = kk.
-    #[named]
-    fn OP_6xkk(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        let byte: u16 = self.op_code & 0x00FF;
-
-        let byte: u8 = match u8::try_from(byte) {
-            Ok(number) => number,
-            Err(error) => panic!(
-                "Could not turn u16 into u8 in OPCODE 6XKK. Error: {}",
-                error
-            ),
-        };
+    /// OPCODE 5XY3 - Read registers Vx through Vy from memory starting at location I (XO-CHIP)
+    /// The registers are loaded in reverse order when x > y, I is left untouched
+    fn OP_5xy3(&mut self, x: usize, y: usize) -> Result<(), Chip8Error> {
+        let count = x.abs_diff(y) + 1;
+        self.check_range(self.index_register as usize, count)?;
+        self.watch(self.index_register as usize, count, Access::Read);
 
-        self.registers[Vx as usize] = byte;
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+        for i in 0..count {
+            let register = if x <= y { x + i } else { x - i };
+            self.registers[register] = self.memory[self.index_register as usize + i];
         }
+        Ok(())
+    }
 
+    /// OPCODE 6XKK - Set Vx = kk.
+    fn OP_6xkk(&mut self, x: usize, byte: u8) -> Result<(), Chip8Error> {
+        self.registers[x] = byte;
+        Ok(())
     }
 
     /// OPCODE 7XKK - Set Vx = Vx + kk.
-    #[named]
-    fn OP_7xkk(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        let byte: u8 = (self.op_code as u8) & 0x00FF;
-
-        self.registers[Vx as usize] = (((self.registers[Vx as usize] as u16) + byte as u16) % 256) as u8;
-
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+    fn OP_7xkk(&mut self, x: usize, byte: u8) -> Result<(), Chip8Error> {
+        self.registers[x] = self.registers[x].wrapping_add(byte);
+        Ok(())
     }
 
     /// OPCODE 8XY0 - Set Vx = Vy.
-    #[named]
-    fn OP_8xy0(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        let Vy: u16 = (self.op_code & 0x00F0).checked_shr(4).unwrap_or(0);
-
-        self.registers[Vx as usize] = self.registers[Vy as usize];
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+    fn OP_8xy0(&mut self, x: usize, y: usize) -> Result<(), Chip8Error> {
+        self.registers[x] = self.registers[y];
+        Ok(())
     }
 
     /// OPCODE 8XY1 - Set Vx = Vx OR Vy.
-    #[named]
-    fn OP_8xy1(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        let Vy: u16 = (self.op_code & 0x00F0).checked_shr(4).unwrap_or(0);
+    fn OP_8xy1(&mut self, x: usize, y: usize) -> Result<(), Chip8Error> {
+        self.registers[x] |= self.registers[y];
 
-        self.registers[Vx as usize] |= self.registers[Vy as usize];
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
         }
+        Ok(())
     }
 
     /// OPCODE 8XY2 - Set Vx = Vx AND Vy
-    #[named]
-    fn OP_8xy2(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        let Vy: u16 = (self.op_code & 0x00F0).checked_shr(4).unwrap_or(0);
+    fn OP_8xy2(&mut self, x: usize, y: usize) -> Result<(), Chip8Error> {
+        self.registers[x] &= self.registers[y];
 
-        self.registers[Vx as usize] &= self.registers[Vy as usize];
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
         }
+        Ok(())
     }
 
     /// OPCODE 8XY3 - Set Vx = Vx XOR Vy
-    #[named]
-    fn OP_8xy3(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        let Vy: u16 = (self.op_code & 0x00F0).checked_shr(4).unwrap_or(0);
+    fn OP_8xy3(&mut self, x: usize, y: usize) -> Result<(), Chip8Error> {
+        self.registers[x] ^= self.registers[y];
 
-        self.registers[Vx as usize] ^= self.registers[Vy as usize];
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
         }
+        Ok(())
     }
 
     /// OPCODE 8XY4 - Set Vx = Vx + Vy, set VF = carry.
     /// The values of Vx and Vy are added together. If the result is greater than 8 bits (i.e., > 255,) VF is set to 1, otherwise 0. Only the lowest 8 bits of the result are kept, and stored in Vx.
-    #[named]
-    fn OP_8xy4(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        let Vy: u16 = (self.op_code & 0x00F0).checked_shr(4).unwrap_or(0);
+    /// The flag is written last, so it wins when Vx is VF.
+    fn OP_8xy4(&mut self, x: usize, y: usize) -> Result<(), Chip8Error> {
+        let sum: u16 = self.registers[x] as u16 + self.registers[y] as u16;
 
-        let sum: u16 = self.registers[Vx as usize] as u16 + self.registers[Vy as usize] as u16;
-
-        if sum > 255 {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
-        }
-
-        self.registers[Vx as usize] = (sum & 0xFF) as u8;
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+        self.registers[x] = (sum & 0xFF) as u8;
+        self.registers[0xF] = (sum > 255) as u8;
+        Ok(())
     }
 
     /// OPCODE 8XY5 - Set Vx = Vx - Vy, set VF = NOT borrow.
-    /// If Vx > Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx, and the results stored in Vx.
-    #[named]
-    fn OP_8xy5(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        let Vy: u16 = (self.op_code & 0x00F0).checked_shr(4).unwrap_or(0);
+    /// If Vx >= Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx, and the results stored in Vx.
+    /// The flag is written last, so it wins when Vx is VF.
+    fn OP_8xy5(&mut self, x: usize, y: usize) -> Result<(), Chip8Error> {
+        let no_borrow = self.registers[x] >= self.registers[y];
 
-        if self.registers[Vx as usize] > self.registers[Vy as usize] {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
-        }
-
-
-        self.registers[Vx as usize] = self.registers[Vx as usize].wrapping_sub(self.registers[Vy as usize]);
-
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+        self.registers[x] = self.registers[x].wrapping_sub(self.registers[y]);
+        self.registers[0xF] = no_borrow as u8;
+        Ok(())
     }
 
     /// OPCODE 8XY6 - Set Vx = Vx SHR 1.
     /// If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
-    #[named]
-    fn OP_8xy6(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-
-        // Save LSB in VF
-        self.registers[0xF] = self.registers[Vx as usize] & 0x1;
-
-        self.registers[Vx as usize].shr_assign(1);
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+    /// Without the shift quirk Vy is shifted instead, and the result is stored in Vx.
+    fn OP_8xy6(&mut self, x: usize, y: usize) -> Result<(), Chip8Error> {
+        if !self.quirks.shift {
+            self.registers[x] = self.registers[y];
         }
+
+        // Save LSB in VF, after the shift so the flag wins when Vx is VF
+        let lsb = self.registers[x] & 0x1;
+        self.registers[x] >>= 1;
+        self.registers[0xF] = lsb;
+        Ok(())
     }
 
     /// OPCODE 8XY7 - SUBN Vx, Vy
-    /// If Vy > Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and the results stored in Vx.
-    #[named]
-    fn OP_8xy7(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        let Vy: u16 = (self.op_code & 0x00F0).checked_shr(4).unwrap_or(0);
+    /// If Vy >= Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and the results stored in Vx.
+    /// The flag is written last, so it wins when Vx is VF.
+    fn OP_8xy7(&mut self, x: usize, y: usize) -> Result<(), Chip8Error> {
+        let no_borrow = self.registers[y] >= self.registers[x];
 
-        if self.registers[Vy as usize] > self.registers[Vx as usize] {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
-        }
-
-        //self.registers[Vx as usize] = self.registers[Vy as usize] - self.registers[Vx as usize];
-        self.registers[Vx as usize] = self.registers[Vy as usize].wrapping_sub(self.registers[Vx as usize]);
-
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+        self.registers[x] = self.registers[y].wrapping_sub(self.registers[x]);
+        self.registers[0xF] = no_borrow as u8;
+        Ok(())
     }
 
     /// OPCODE 8XYE - Set Vx = Vx SHL 1.
     /// If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
-    #[named]
-    fn OP_8xyE(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-
-        // save MSB in VF
-        self.registers[0xF] = (self.registers[Vx as usize] & 0x80).checked_shr(7).unwrap_or(0);
-
-        self.registers[Vx as usize].shl_assign(1);
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+    /// Without the shift quirk Vy is shifted instead, and the result is stored in Vx.
+    fn OP_8xyE(&mut self, x: usize, y: usize) -> Result<(), Chip8Error> {
+        if !self.quirks.shift {
+            self.registers[x] = self.registers[y];
         }
+
+        // save MSB in VF, after the shift so the flag wins when Vx is VF
+        let msb = self.registers[x] >> 7;
+        self.registers[x] <<= 1;
+        self.registers[0xF] = msb;
+        Ok(())
     }
 
     /// OPCODE 9XY0 - Skip next instruction if Vx != Vy
-    #[named]
-    fn OP_9xy0(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        let Vy: u16 = (self.op_code & 0x00F0).checked_shr(4).unwrap_or(0);
-
-        if self.registers[Vx as usize] != self.registers[Vy as usize] {
-            self.program_counter += 2;
-        }
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+    fn OP_9xy0(&mut self, x: usize, y: usize) -> Result<(), Chip8Error> {
+        if self.registers[x] != self.registers[y] {
+            self.skip_next();
         }
+        Ok(())
     }
 
     /// OPCODE ANNN - set I = nnn
-    #[named]
-    fn OP_Annn(&mut self) {
-        let address: u16 = self.op_code & 0x0FFF;
-
+    fn OP_Annn(&mut self, address: u16) -> Result<(), Chip8Error> {
         self.index_register = address;
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+        Ok(())
     }
 
     /// OPCODE BNNN - Jump to location nnn + V0
-    #[named]
-    fn OP_Bnnn(&mut self) {
-        let address: u16 = self.op_code & 0x0FFF;
+    /// With the jump quirk this is BXNN instead: jump to location xnn + Vx
+    fn OP_Bnnn(&mut self, address: u16) -> Result<(), Chip8Error> {
+        let x = if self.quirks.jump { (address >> 8) as usize } else { 0 };
 
-        self.program_counter = self.registers[0] as u16 + address;
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+        self.program_counter = self.registers[x] as u16 + address;
+        Ok(())
     }
 
     /// OPCODE CXKK - Set Vx = random byte AND kk.
-    #[named]
-    fn OP_Cxkk(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        let byte: u16 = self.op_code & 0x00FF;
-
-        let byte: u8 = match u8::try_from(byte) {
-            Ok(number) => number,
-            Err(error) => panic!(
-                "Could not turn u16 into u8 in OPCODE CXKK. Error: {}",
-                error
-            ),
-        };
-
-        self.registers[Vx as usize] = self.rand_byte() & byte;
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+    fn OP_Cxkk(&mut self, x: usize, byte: u8) -> Result<(), Chip8Error> {
+        self.registers[x] = self.rand_byte() & byte;
+        Ok(())
     }
 
     /// OPCODE DXYN - Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
-    #[named]
-    fn OP_Dxyn(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-        let Vy = (self.op_code & 0x00F0).checked_shr(4).unwrap_or(0);
-        let height = self.op_code & 0x000F;
-        let VIDEO_WIDTH: u8 = 64;
-        let VIDEO_HEIGHT: u8 = 32;
+    /// The starting position always wraps around the screen, the quirks decide whether the
+    /// sprite itself is clipped at the edges or wraps, and whether we wait for the vertical blank.
+    /// On SUPER-CHIP, DXY0 draws a 16x16 sprite made of 32 bytes instead.
+    /// On XO-CHIP the sprite is drawn on every selected bitplane, when both planes are selected
+    /// the data for the second plane follows the data for the first one.
+    fn OP_Dxyn(&mut self, x: usize, y: usize, n: u8) -> Result<(), Chip8Error> {
+        let (width, height) = (self.width(), self.height());
+
+        let (sprite_width, sprite_height) = if n == 0 && self.variant != Variant::Chip8 { (16, 16) } else { (8, n as usize) };
+        let bytes_per_row = sprite_width / 8;
 
         // wrap if going over boundaries
-        let x_pos: u8 = self.registers[Vx as usize] % VIDEO_WIDTH;
-        let y_pos: u8 = self.registers[Vy as usize] % VIDEO_HEIGHT;
+        let x_pos = self.registers[x] as usize % width;
+        let y_pos = self.registers[y] as usize % height;
+
+        let planes = (self.plane & 0x1) + ((self.plane & 0x2) >> 1);
+        self.check_range(self.index_register as usize, planes as usize * sprite_height * bytes_per_row)?;
+        self.watch(self.index_register as usize, planes as usize * sprite_height * bytes_per_row, Access::Read);
 
         self.registers[0xF] = 0;
 
-        for row in 0..height {
-            let sprite_byte: u8 = self.memory[(self.index_register + row) as usize];
-
-            for col in 0..8 {
-                let sprite_pixel = sprite_byte & ((0x80 as u16).checked_shr(col as u32).unwrap_or(0) as u8);
-                // casting without error checking here is fine because col and raw wil alwyays be lower than 255(they are 64 and 32)
-                //let mut screen_pixel = self.video[(((y_pos as u16 + row) * (VIDEO_WIDTH as u16) + (x_pos as u16) + col)) as usize];
-
-                // old: 
-                //
-                // ```
-                // let video_index = ((y_pos as u16 + row) * (VIDEO_WIDTH as u16) + (x_pos as u16) + col) as usize;
-                // ```
-                //
-                // new:
-                let video_index = ((y_pos as u16).wrapping_add(row) * (VIDEO_WIDTH as u16))
-                    .wrapping_add(x_pos as u16)
-                    .wrapping_add(col) as usize;
-
-
-                // sprite pixel is on
-                if sprite_pixel != 0 {
-                    // screen pixel also on - collision
-                    if self.video[video_index] == 0xFFFFFFFF {
-                        self.registers[0xF] = 1;
+        let mut sprite_address = self.index_register as usize;
+        for plane in 0..2 {
+            let plane_bit: u32 = 1 << plane;
+            if self.plane as u32 & plane_bit == 0 {
+                continue;
+            }
+
+            for row in 0..sprite_height {
+                let y = y_pos + row;
+                if y >= height && self.quirks.clipping {
+                    break;
+                }
+
+                for col in 0..sprite_width {
+                    let x = x_pos + col;
+                    if x >= width && self.quirks.clipping {
+                        break;
                     }
 
-                    // Effectively XOR with the sprite pixel
-                    self.video[video_index] ^= 0xFFFFFFFF;
+                    let sprite_byte: u8 = self.memory[sprite_address + row * bytes_per_row + col / 8];
+                    let sprite_pixel = sprite_byte & (0x80u8 >> (col % 8));
+                    let video_index = (y % height) * width + (x % width);
+
+                    // sprite pixel is on
+                    if sprite_pixel != 0 {
+                        // screen pixel also on - collision
+                        if self.video[video_index] & plane_bit != 0 {
+                            self.registers[0xF] = 1;
+                        }
+
+                        // Effectively XOR with the sprite pixel
+                        self.video[video_index] ^= plane_bit;
+                    }
                 }
             }
+
+            sprite_address += sprite_height * bytes_per_row;
         }
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+
+        if self.quirks.display_wait {
+            self.vblank_wait = true;
         }
+        Ok(())
     }
 
     /// OPCODE EX9E - Skip next instruction if key with the value of Vx is pressed.
-    #[named]
-    fn OP_Ex9E(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-        let key: u8 = self.registers[Vx as usize];
+    fn OP_Ex9E(&mut self, x: usize) -> Result<(), Chip8Error> {
+        // only the low nibble selects a key
+        let key = self.registers[x] & 0xF;
 
         if self.keypad[key as usize] != 0 {
-            self.program_counter += 2;
-        }
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+            self.skip_next();
         }
+        Ok(())
     }
 
     /// OPCODE EXA1 - Skip next instruction if key with the value of Vx is not pressed
-    #[named]
-    fn OP_ExA1(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-        let key: u8 = self.registers[Vx as usize];
+    fn OP_ExA1(&mut self, x: usize) -> Result<(), Chip8Error> {
+        // only the low nibble selects a key
+        let key = self.registers[x] & 0xF;
 
         if self.keypad[key as usize] == 0 {
-            self.program_counter += 2;
+            self.skip_next();
         }
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+        Ok(())
+    }
+
+    /// OPCODE F000 NNNN - Set I = NNNN, the address is the word following the opcode (XO-CHIP)
+    fn OP_F000(&mut self) -> Result<(), Chip8Error> {
+        let pc = self.program_counter as usize;
+        if pc + 1 >= self.memory.len() {
+            return Err(self.mem_oob(pc + 1));
         }
+        self.index_register = (self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16;
+        self.program_counter = self.program_counter.wrapping_add(2);
+        Ok(())
+    }
+
+    /// OPCODE FN01 - Select the bitplanes N used for drawing, scrolling and clearing (XO-CHIP)
+    fn OP_Fn01(&mut self, n: u8) -> Result<(), Chip8Error> {
+        self.plane = n & 0x3;
+        Ok(())
+    }
+
+    /// OPCODE F002 - Load the 16 bytes starting at location I into the audio pattern buffer (XO-CHIP)
+    fn OP_F002(&mut self) -> Result<(), Chip8Error> {
+        let start = self.index_register as usize;
+        self.check_range(start, 16)?;
+        self.watch(start, 16, Access::Read);
+        self.audio_pattern.copy_from_slice(&self.memory[start..start + 16]);
+        Ok(())
+    }
+
+    /// OPCODE FX3A - Set the audio pattern pitch = Vx (XO-CHIP)
+    fn OP_Fx3A(&mut self, x: usize) -> Result<(), Chip8Error> {
+        self.pitch = self.registers[x];
+        Ok(())
     }
 
     /// OPCODE FX07 - Set Vx = delay timer value
-    #[named]
-    fn OP_Fx07(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
+    fn OP_Fx07(&mut self, x: usize) -> Result<(), Chip8Error> {
+        self.registers[x] = self.delay_timer;
+        Ok(())
+    }
 
-        self.registers[Vx as usize] = self.delay_timer;
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+    /// OPCODE FX0A - Wait for a key, store the value of the key in Vx.
+    /// The CPU blocks on the instruction until `poll_key_wait` sees the key come
+    fn OP_Fx0A(&mut self, x: usize) -> Result<(), Chip8Error> {
+        // only presses from now on count, older ones would register the same key twice
+        self.recent_presses.clear();
+        self.key_wait = Some(KeyWait { x: x as u8, key: None });
+        self.program_counter = self.program_counter.wrapping_sub(2);
+        Ok(())
     }
 
-    /// OPCODE FX0A - Wait for a key press, store the value of the key in Vx.
-    #[named]
-    fn OP_Fx0A(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        if self.keypad[0] != 0 {
-            self.registers[Vx as usize] = 0;
-        } else if self.keypad[1] != 0 {
-            self.registers[Vx as usize] = 1;
-        } else if self.keypad[2] != 0 {
-            self.registers[Vx as usize] = 2;
-        } else if self.keypad[3] != 0 {
-            self.registers[Vx as usize] = 3;
-        } else if self.keypad[4] != 0 {
-            self.registers[Vx as usize] = 4;
-        } else if self.keypad[5] != 0 {
-            self.registers[Vx as usize] = 5;
-        } else if self.keypad[6] != 0 {
-            self.registers[Vx as usize] = 6;
-        } else if self.keypad[7] != 0 {
-            self.registers[Vx as usize] = 7;
-        } else if self.keypad[8] != 0 {
-            self.registers[Vx as usize] = 8;
-        } else if self.keypad[9] != 0 {
-            self.registers[Vx as usize] = 9;
-        } else if self.keypad[10] != 0 {
-            self.registers[Vx as usize] = 10;
-        } else if self.keypad[11] != 0 {
-            self.registers[Vx as usize] = 11;
-        } else if self.keypad[12] != 0 {
-            self.registers[Vx as usize] = 12;
-        } else if self.keypad[13] != 0 {
-            self.registers[Vx as usize] = 13;
-        } else if self.keypad[14] != 0 {
-            self.registers[Vx as usize] = 14;
-        } else if self.keypad[15] != 0 {
-            self.registers[Vx as usize] = 15;
-        } else {
-            self.program_counter -= 2;
+    /// Finish the FX0A at PC once its key came: pressed with the `key_press` quirk, otherwise
+    /// pressed and released. A key already held when FX0A started counts once it is released
+    fn poll_key_wait(&mut self, mut wait: KeyWait) -> StepOutcome {
+        if wait.key.is_none() {
+            wait.key = self.take_recent_press();
+            if !self.quirks.key_press {
+                let held = self.keypad.iter().position(|key| *key != 0);
+                wait.key = wait.key.or(held.map(|key| key as u8));
+            }
         }
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+        let key = match wait.key {
+            Some(key) if self.quirks.key_press || !self.is_key_down(key) => key,
+            _ => {
+                self.key_wait = Some(wait);
+                return StepOutcome::WaitingKey;
+            }
+        };
+
+        let (pc, opcode) = (self.program_counter, 0xF00A | (wait.x as u16) << 8);
+        let (registers, index) = (self.registers, self.index_register);
+        self.key_wait = None;
+        self.registers[wait.x as usize] = key;
+        self.program_counter = pc.wrapping_add(2);
+        self.trace(pc, opcode, &registers, index);
+
+        match self.break_after(pc, &registers) {
+            Some(hit) => StepOutcome::Break(hit),
+            None => StepOutcome::Executed { pc, opcode },
         }
     }
 
     /// OPCODE FX15 - Set delay timer = Vx.
-    #[named]
-    fn OP_Fx15(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        self.delay_timer = self.registers[Vx as usize];
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+    fn OP_Fx15(&mut self, x: usize) -> Result<(), Chip8Error> {
+        self.delay_timer = self.registers[x];
+        Ok(())
     }
 
     /// OPCODE FX18 - Set sound timer = Vx.
-    #[named]
-    fn OP_Fx18(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        self.sound_timer = self.registers[Vx as usize];
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+    fn OP_Fx18(&mut self, x: usize) -> Result<(), Chip8Error> {
+        self.sound_timer = self.registers[x];
+        Ok(())
     }
 
     /// OPCODE FX1E - Set I = I + Vx.
-    #[named]
-    fn OP_Fx1E(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-
-        self.index_register += self.registers[Vx as usize] as u16;
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+    fn OP_Fx1E(&mut self, x: usize) -> Result<(), Chip8Error> {
+        self.index_register = self.index_register.wrapping_add(self.registers[x] as u16);
+        Ok(())
     }
 
     /// OPCODE FX29 - Set I = location of sprite for digit Vx.
-    #[named]
-    fn OP_Fx29(&mut self) {
-        // TODO this Vx has to stay u16 bc I have to cast it either way back into index register
-        let Vx: u16 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0);
-        let digit = self.registers[Vx as usize];
-        let fontset_start_address = 0x50;
+    fn OP_Fx29(&mut self, x: usize) -> Result<(), Chip8Error> {
+        let digit = self.registers[x] & 0xF;
 
-        self.index_register = (fontset_start_address + (5*digit)) as u16;
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+        self.index_register = (FONTSET_START_ADDRESS + 5 * digit as usize) as u16;
+        Ok(())
+    }
+
+    /// OPCODE FX30 - Set I = location of the big 8x10 sprite for digit Vx (SUPER-CHIP)
+    fn OP_Fx30(&mut self, x: usize) -> Result<(), Chip8Error> {
+        let digit = self.registers[x] & 0xF;
+
+        self.index_register = (BIG_FONTSET_START_ADDRESS + 10 * digit as usize) as u16;
+        Ok(())
     }
 
     /// OPCODE FX33 - Store BCD representation of Vx in memory locations I, I+1, and I+2.
     /// The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.
-    #[named]
-    fn OP_Fx33(&mut self) {
-        let Vx: u8 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
-        let mut value: u8 = self.registers[Vx as usize];
+    fn OP_Fx33(&mut self, x: usize) -> Result<(), Chip8Error> {
+        let mut value: u8 = self.registers[x];
+        let i = self.index_register as usize;
+        self.check_range(i, 3)?;
+        self.watch(i, 3, Access::Write);
 
         // ones place
-        self.memory[(self.index_register + 2) as usize] = value % 10;
+        self.memory[i + 2] = value % 10;
         value /= 10;
 
         // tens place
-        self.memory[(self.index_register + 1) as usize] = value % 10;
+        self.memory[i + 1] = value % 10;
         value /= 10;
 
         // hundreds place
-        self.memory[self.index_register as usize] = value % 10;
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+        self.memory[i] = value % 10;
+        Ok(())
     }
 
     /// OPCODE FX55 -- Store registers V0 to VX in memory starting at location X
-    #[named]
-    fn OP_Fx55(&mut self) {
-        // TODO: this has to stay a u16 bc it needs to get added into index register
-        let Vx: u16 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0);
+    /// Without the load/store quirk I is left pointing past the last stored register
+    fn OP_Fx55(&mut self, x: usize) -> Result<(), Chip8Error> {
+        let i = self.index_register as usize;
+        self.check_range(i, x + 1)?;
+        self.watch(i, x + 1, Access::Write);
 
-        for i in 0..(Vx +1) {
-            self.memory[(self.index_register + i) as usize] = self.registers[i as usize];
-        }
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+        self.memory[i..=i + x].copy_from_slice(&self.registers[..=x]);
+
+        if !self.quirks.load_store {
+            self.index_register = self.index_register.wrapping_add(x as u16 + 1);
         }
+        Ok(())
     }
 
     /// OPCODE FX65 - Read registers V0 through Vx from memory starting at location I.
-    #[named]
-    fn OP_Fx65(&mut self) {
-        // TODO: has to stay u16, bc we need to recast it anyways
-        let Vx: u16 = (self.op_code & 0x0F00).checked_shr(8).unwrap_or(0).try_into().unwrap();
+    /// Without the load/store quirk I is left pointing past the last loaded register
+    fn OP_Fx65(&mut self, x: usize) -> Result<(), Chip8Error> {
+        let i = self.index_register as usize;
+        self.check_range(i, x + 1)?;
+        self.watch(i, x + 1, Access::Read);
 
-        for i in 0..(Vx +1) {
-            self.registers[i as usize] = self.memory[(self.index_register + i) as usize];
-        }
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
+        self.registers[..=x].copy_from_slice(&self.memory[i..=i + x]);
+
+        if !self.quirks.load_store {
+            self.index_register = self.index_register.wrapping_add(x as u16 + 1);
         }
+        Ok(())
     }
 
-    /// Fallback OPCODE
-    #[named]
-    fn OP_ERR(&mut self) {
-        eprintln!("[ERROR]: Opcode {} not valid", self.op_code); 
-        if self.debug_mode {
-            eprintln!("Ran opcode: {}", function_name!());
-        }
+    /// OPCODE FX75 - Store registers V0 through Vx in the RPL user flags (SUPER-CHIP)
+    fn OP_Fx75(&mut self, x: usize) -> Result<(), Chip8Error> {
+        self.rpl_flags[..=x].copy_from_slice(&self.registers[..=x]);
+        Ok(())
     }
 
+    /// OPCODE FX85 - Read registers V0 through Vx from the RPL user flags (SUPER-CHIP)
+    fn OP_Fx85(&mut self, x: usize) -> Result<(), Chip8Error> {
+        self.registers[..=x].copy_from_slice(&self.rpl_flags[..=x]);
+        Ok(())
+    }
 
-    #[named]
-    fn Table0(&mut self) {
-        if self.debug_mode {
-            eprintln!("Running table: {}", function_name!());
+    /// Decode `opcode` for the variant this machine runs. Opcodes the variant doesn't have,
+    /// including 0NNN, are `UnknownOpcode` at the current PC
+    ///
+    /// ```
+    /// # use chip8_rs::chip8::{Chip8, Chip8Error, Variant};
+    /// # use chip8_rs::disasm::Instruction;
+    /// let chip8 = Chip8::new();
+    /// assert_eq!(chip8.decode(0x8124), Ok(Instruction::AddReg { x: 1, y: 2 }));
+    /// assert_eq!(chip8.decode(0x00FF), Err(Chip8Error::UnknownOpcode { pc: 0x200, opcode: 0x00FF }));
+    /// assert!(Chip8::with_variant(Variant::SuperChip).decode(0x00FF).is_ok());
+    /// ```
+    pub fn decode(&self, opcode: u16) -> Result<Instruction, Chip8Error> {
+        let instruction = Instruction::decode(opcode);
+        if !instruction.supported_by(self.variant) {
+            return Err(Chip8Error::UnknownOpcode { pc: self.program_counter, opcode });
         }
-        self.table0[(self.op_code & 0x000F) as usize](self);
+        Ok(instruction)
     }
 
-    #[named]
-    fn Table8(&mut self) {
-        if self.debug_mode {
-            eprintln!("Running table: {}", function_name!());
+    /// Execute `instruction` as if it had been fetched from the current PC: PC moves past it,
+    /// then it runs. A failing instruction leaves the machine as it was.
+    ///
+    /// Unlike `step` this doesn't check breakpoints, doesn't trace and doesn't halt on a fault.
+    pub fn execute(&mut self, instruction: Instruction) -> Result<(), Chip8Error> {
+        use Instruction::*;
+
+        let pc = self.program_counter;
+        self.op_code = instruction.encode();
+        self.last_opcode = self.op_code;
+        self.last_pc = pc;
+        if !instruction.supported_by(self.variant) {
+            return Err(Chip8Error::UnknownOpcode { pc, opcode: self.op_code });
         }
-        self.table8[(self.op_code & 0x000F) as usize](self);
+        // increment pc before we do anything
+        self.program_counter = pc.wrapping_add(2);
+
+        let r = |register: u8| register as usize;
+        let result = match instruction {
+            Cls => self.OP_00E0(),
+            Ret => self.OP_00EE(),
+            ScrollDown { n } => self.OP_00Cn(n),
+            ScrollUp { n } => self.OP_00Dn(n),
+            ScrollRight => self.OP_00FB(),
+            ScrollLeft => self.OP_00FC(),
+            Exit => self.OP_00FD(),
+            Lores => self.OP_00FE(),
+            Hires => self.OP_00FF(),
+            Jump { addr } => self.OP_1nnn(addr),
+            Call { addr } => self.OP_2nnn(addr),
+            SkipEqByte { x, byte } => self.OP_3xkk(r(x), byte),
+            SkipNeByte { x, byte } => self.OP_4xkk(r(x), byte),
+            SkipEqReg { x, y } => self.OP_5xy0(r(x), r(y)),
+            SaveRange { x, y } => self.OP_5xy2(r(x), r(y)),
+            LoadRange { x, y } => self.OP_5xy3(r(x), r(y)),
+            LoadByte { x, byte } => self.OP_6xkk(r(x), byte),
+            AddByte { x, byte } => self.OP_7xkk(r(x), byte),
+            Move { x, y } => self.OP_8xy0(r(x), r(y)),
+            Or { x, y } => self.OP_8xy1(r(x), r(y)),
+            And { x, y } => self.OP_8xy2(r(x), r(y)),
+            Xor { x, y } => self.OP_8xy3(r(x), r(y)),
+            AddReg { x, y } => self.OP_8xy4(r(x), r(y)),
+            Sub { x, y } => self.OP_8xy5(r(x), r(y)),
+            ShiftRight { x, y } => self.OP_8xy6(r(x), r(y)),
+            SubReverse { x, y } => self.OP_8xy7(r(x), r(y)),
+            ShiftLeft { x, y } => self.OP_8xyE(r(x), r(y)),
+            SkipNeReg { x, y } => self.OP_9xy0(r(x), r(y)),
+            LoadI { addr } => self.OP_Annn(addr),
+            JumpV0 { addr } => self.OP_Bnnn(addr),
+            Random { x, byte } => self.OP_Cxkk(r(x), byte),
+            Draw { x, y, n } => self.OP_Dxyn(r(x), r(y), n),
+            SkipKey { x } => self.OP_Ex9E(r(x)),
+            SkipNotKey { x } => self.OP_ExA1(r(x)),
+            LoadILong => self.OP_F000(),
+            Plane { n } => self.OP_Fn01(n),
+            Audio => self.OP_F002(),
+            GetDelay { x } => self.OP_Fx07(r(x)),
+            WaitKey { x } => self.OP_Fx0A(r(x)),
+            SetDelay { x } => self.OP_Fx15(r(x)),
+            SetSound { x } => self.OP_Fx18(r(x)),
+            AddI { x } => self.OP_Fx1E(r(x)),
+            Font { x } => self.OP_Fx29(r(x)),
+            BigFont { x } => self.OP_Fx30(r(x)),
+            Bcd { x } => self.OP_Fx33(r(x)),
+            Pitch { x } => self.OP_Fx3A(r(x)),
+            Store { x } => self.OP_Fx55(r(x)),
+            Load { x } => self.OP_Fx65(r(x)),
+            SaveFlags { x } => self.OP_Fx75(r(x)),
+            LoadFlags { x } => self.OP_Fx85(r(x)),
+            Sys { .. } | Unknown(_) => Err(Chip8Error::UnknownOpcode { pc, opcode: self.op_code }),
+        };
+        if result.is_err() {
+            self.program_counter = pc;
+        }
+        result
     }
 
-    #[named]
-    fn TableE(&mut self) {
-        if self.debug_mode {
-            eprintln!("Running table: {}", function_name!());
+    // the opcodes are stored in memory starting from index 512, i need to decode them and map each opcode to one of my functions
+    // The CHIP-8 Architecture uses big-endian (0x00 0xe0 -> 0x00e0)
+
+    /// Fetch, decode and execute one instruction. The timers are left alone, they tick at 60 Hz
+    /// in `tick_timers`, see `run_frame`.
+    ///
+    /// When the instruction fails the machine is left as it was before the instruction and stays
+    /// halted: every following step returns the same error until the next reset.
+    ///
+    /// FX0A blocks the CPU: steps return `StepOutcome::WaitingKey` until its key came, and the
+    /// step that sees the key finishes the instruction.
+    ///
+    /// A PC or opcode breakpoint returns `StepOutcome::Break` without running the instruction, the
+    /// next step runs it. Register conditions and watchpoints return it after the instruction ran.
+    pub fn step(&mut self) -> Result<StepOutcome, Chip8Error> {
+        if let Some(error) = &self.fault {
+            return Err(error.clone());
+        }
+        if self.exited {
+            return Ok(StepOutcome::Exited);
+        }
+        if self.vblank_wait {
+            return Ok(StepOutcome::WaitingVblank);
+        }
+        if let Some(wait) = self.key_wait {
+            return Ok(self.poll_key_wait(wait));
         }
-        self.tableE[(self.op_code & 0x000F) as usize](self);
-    }
 
-    #[named]
-    fn TableF(&mut self) {
-        if self.debug_mode {
-            eprintln!("Running table: {}", function_name!());
+        let pc = self.program_counter;
+        if pc as usize + 1 >= self.memory.len() {
+            return Err(self.halt(Chip8Error::PcOob { pc }));
         }
-        self.tableF[(self.op_code & 0x00FF) as usize](self);
+
+        // Fetch opcode
+        let opcode = (self.memory[pc as usize] as u16) << 8 | self.memory[pc as usize + 1] as u16;
+        if let Some(hit) = self.break_before(pc, opcode) {
+            return Ok(StepOutcome::Break(hit));
+        }
+        // decode and execute, opcodes this variant doesn't have fail in `execute`
+        let (registers, index) = (self.registers, self.index_register);
+        if let Err(error) = self.execute(Instruction::decode(opcode)) {
+            self.hooks.interrupt();
+            if let Some(tracer) = &mut self.tracer {
+                tracer.interrupt();
+            }
+            return Err(self.halt(error));
+        }
+        // FX0A runs once its key came, the step that finishes the wait traces it
+        if self.key_wait.is_some() {
+            return Ok(StepOutcome::WaitingKey);
+        }
+        self.trace(pc, opcode, &registers, index);
+
+        if let Some(hit) = self.break_after(pc, &registers) {
+            return Ok(StepOutcome::Break(hit));
+        }
+        Ok(StepOutcome::Executed { pc, opcode: self.op_code })
     }
 
+    /// Run one 60 Hz frame: up to `ipf` instructions, then tick the timers.
+    /// The frame ends early when DXYN waits for the vertical blank, FX0A waits for a key or the
+    /// ROM exits, the outcome of the last step is returned. The state before the frame is kept for `rewind`.
+    ///
+    /// A breakpoint stops the frame without ticking the timers, the next call runs the rest of it.
+    pub fn run_frame(&mut self) -> Result<StepOutcome, Chip8Error> {
+        if self.frame_steps == 0 {
+            self.record_rewind();
+        }
+        let outcome = loop {
+            let outcome = self.step()?;
+            match outcome {
+                StepOutcome::Executed { .. } => self.frame_steps += 1,
+                StepOutcome::Break(hit) => {
+                    if hit.executed() {
+                        self.frame_steps += 1;
+                    }
+                    if self.frame_steps < self.ipf {
+                        return Ok(outcome);
+                    }
+                    break outcome;
+                }
+                _ => break outcome,
+            }
+            if self.frame_steps >= self.ipf {
+                break outcome;
+            }
+        };
 
-    // the opcodes are stored in memory starting from index 512, i need to decode them and map each opcode to one of my functions
-    // The CHIP-8 Architecture uses big-endian (0x00 0xe0 -> 0x00e0)
+        self.frame_steps = 0;
+        self.tick_timers();
+        Ok(outcome)
+    }
 
+    /// Runs one `step`, ignoring its outcome. A failed step still halts the machine, see `fault`
     pub fn Cycle(&mut self) {
-        // Fetch opcode
-        //let _: () = self.memory[self.program_counter as usize].checked_shl(8).unwrap_or(0);
-        self.op_code = ((self.memory[self.program_counter as usize] as u16).checked_shl(8).unwrap_or(0)) | self.memory[(self.program_counter + 1) as usize] as u16;
-        if (self.debug_mode) { 
-            eprintln!("Opcode: {}", &self.op_code);
-        }
-        self.last_opcode = self.op_code;
-        // increment pc before we do anything
-        self.program_counter += 2;
+        let _ = self.step();
+    }
+
+    fn halt(&mut self, error: Chip8Error) -> Chip8Error {
+        self.fault = Some(error.clone());
+        error
+    }
 
-        // decode and execute
-        self.table[((self.op_code & 0xF000).checked_shr(12).unwrap_or(0)) as usize](self);
+    /// Decrement both timers and signal the vertical blank, call this 60 times per second.
+    /// The sound of the frame is queued for `fill_audio`
+    pub fn tick_timers(&mut self) {
+        self.queue_frame_sound();
 
         // Decrement delay timer if it exists
         if (self.delay_timer > 0) {
@@ -1027,18 +1347,45 @@ impl Chip8 {
             self.sound_timer -= 1;
         }
 
+        self.vblank_wait = false;
     }
 
 }
 
-#[derive(Debug, Clone)]
+/// What a successful `Chip8::step` did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The instruction `opcode` at `pc` was executed
+    Executed { pc: u16, opcode: u16 },
+    /// Nothing was executed, DXYN is waiting for the vertical blank in `tick_timers`
+    WaitingVblank,
+    /// Nothing was executed yet, FX0A is waiting for a key, see `Quirks::key_press`
+    WaitingKey,
+    /// Nothing was executed, the ROM quit with 00FD
+    Exited,
+    /// A breakpoint or watchpoint fired, `Break::executed` tells whether its instruction ran
+    Break(Break),
+}
+
+/// The register FX0A loads and, once pressed, the key it waits on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KeyWait {
+    x: u8,
+    key: Option<u8>,
+}
+
+/// Errors returned by `Chip8::step` carry the address and the opcode of the faulting instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Chip8Error {
-    UnknownOpcode(u16),
-    PcOob(u16),
-    MemOob(usize),
-    StackOverflow,
-    StackUnderflow,
+    UnknownOpcode { pc: u16, opcode: u16 },
+    /// The program counter points past the end of memory, so no opcode could be fetched
+    PcOob { pc: u16 },
+    MemOob { pc: u16, opcode: u16, address: usize },
+    StackOverflow { pc: u16, opcode: u16 },
+    StackUnderflow { pc: u16, opcode: u16 },
     BadRegisterIndex(u8),
+    /// The ROM doesn't fit in memory, `size` is its length in bytes
+    RomTooLarge { size: usize },
 }
 
 use std::fmt;
@@ -1046,22 +1393,26 @@ use std::fmt;
 impl fmt::Display for Chip8Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Chip8Error::UnknownOpcode(op) =>
-                write!(f, "Unknown opcode: {:04X}", op),
-            Chip8Error::PcOob(pc) =>
+            Chip8Error::UnknownOpcode { pc, opcode } =>
+                write!(f, "Unknown opcode: {:04X} at {:04X}", opcode, pc),
+            Chip8Error::PcOob { pc } =>
                 write!(f, "PC out of bounds: {:04X}", pc),
-            Chip8Error::MemOob(addr) =>
-                write!(f, "Memory access out of bounds: {:#X}", addr),
-            Chip8Error::StackOverflow =>
-                write!(f, "Stack overflow"),
-            Chip8Error::StackUnderflow =>
-                write!(f, "Stack underflow"),
+            Chip8Error::MemOob { pc, opcode, address } =>
+                write!(f, "Memory access out of bounds: {:#X} by {:04X} at {:04X}", address, opcode, pc),
+            Chip8Error::StackOverflow { pc, opcode } =>
+                write!(f, "Stack overflow: {:04X} at {:04X}", opcode, pc),
+            Chip8Error::StackUnderflow { pc, opcode } =>
+                write!(f, "Stack underflow: {:04X} at {:04X}", opcode, pc),
             Chip8Error::BadRegisterIndex(r) =>
                 write!(f, "Invalid register index: {}", r),
+            Chip8Error::RomTooLarge { size } =>
+                write!(f, "ROM too large: {} bytes", size),
         }
     }
 }
 
+impl std::error::Error for Chip8Error {}
+
 
 /*
 *
@@ -1071,7 +1422,9 @@ impl fmt::Display for Chip8Error {
 
 #[cfg(test)]
 mod tests {
-    use crate::Chip8;
+    use crate::chip8::{Chip8, Chip8Error, StepOutcome, Variant};
+    use crate::disasm::Instruction;
+    use crate::quirks::Quirks;
 
     #[test]
     fn add_two() {
@@ -1079,6 +1432,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::unnecessary_cast)]
     fn check_fontset() {
         let fontset: [u8; 80] = [
             0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -1108,4 +1462,350 @@ mod tests {
         }
 
     }
+
+    fn run(quirks: Quirks, rom: &[u8], cycles: usize) -> Chip8 {
+        let mut chip8 = Chip8::with_quirks(quirks);
+        chip8.reset_and_load_bytes(rom).unwrap();
+        for _ in 0..cycles {
+            chip8.step().unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn shift_quirk() {
+        // V0 = 0x01, V1 = 0x06, V0 >>= V1
+        let rom = [0x60, 0x01, 0x61, 0x06, 0x80, 0x16];
+
+        let chip8 = run(Quirks::COSMAC_VIP, &rom, 3);
+        assert_eq!(chip8.registers[0], 0x03);
+        assert_eq!(chip8.registers[0xF], 0);
+
+        let chip8 = run(Quirks::SUPER_CHIP, &rom, 3);
+        assert_eq!(chip8.registers[0], 0x00);
+        assert_eq!(chip8.registers[0xF], 1);
+    }
+
+    #[test]
+    fn load_store_quirk() {
+        // I = 0x300, store V0..V2
+        let rom = [0xA3, 0x00, 0xF2, 0x55];
+
+        assert_eq!(run(Quirks::COSMAC_VIP, &rom, 2).index_register, 0x303);
+        assert_eq!(run(Quirks::CHIP_48, &rom, 2).index_register, 0x300);
+    }
+
+    #[test]
+    fn jump_quirk() {
+        // V0 = 0x10, V3 = 0x20, jump to 0x300 (+ V0 or V3)
+        let rom = [0x60, 0x10, 0x63, 0x20, 0xB3, 0x00];
+
+        assert_eq!(run(Quirks::COSMAC_VIP, &rom, 3).program_counter, 0x310);
+        assert_eq!(run(Quirks::SUPER_CHIP, &rom, 3).program_counter, 0x320);
+    }
+
+    #[test]
+    fn vf_reset_quirk() {
+        // VF = 0x01, V0 |= V1
+        let rom = [0x6F, 0x01, 0x80, 0x11];
+
+        assert_eq!(run(Quirks::COSMAC_VIP, &rom, 2).registers[0xF], 0);
+        assert_eq!(run(Quirks::XO_CHIP, &rom, 2).registers[0xF], 1);
+    }
+
+    #[test]
+    fn clipping_quirk() {
+        // V0 = 60, V1 = 30, I = font sprite for "0", draw 5 rows at (60, 30)
+        let rom = [0x60, 60, 0x61, 30, 0xA0, 0x50, 0xD0, 0x15];
+
+        let clipped = run(Quirks::COSMAC_VIP, &rom, 4);
+        let wrapped = run(Quirks::XO_CHIP, &rom, 4);
+
+        // the top left corner of the sprite is drawn either way
+        assert_ne!(clipped.video[30 * 64 + 60], 0);
+        assert_ne!(wrapped.video[30 * 64 + 60], 0);
+
+        // the last row wraps around to the top of the screen
+        assert_eq!(clipped.video[2 * 64 + 60], 0);
+        assert_ne!(wrapped.video[2 * 64 + 60], 0);
+    }
+
+    fn run_variant(variant: Variant, rom: &[u8], cycles: usize) -> Chip8 {
+        let mut chip8 = Chip8::with_variant(variant);
+        chip8.reset_and_load_bytes(rom).unwrap();
+        for _ in 0..cycles {
+            chip8.step().unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn schip_hires_and_big_sprite() {
+        // hires, V0 = 120, V1 = 60, I = 0x300, draw a 16x16 sprite at (120, 60)
+        let mut rom = vec![0x00, 0xFF, 0x60, 120, 0x61, 60, 0xA3, 0x00, 0xD0, 0x10];
+        rom.resize(0x100, 0);
+        rom.extend([0xFF; 32]);
+
+        let chip8 = run_variant(Variant::SuperChip, &rom, 5);
+        assert!(chip8.hires());
+        assert_eq!((chip8.width(), chip8.height()), (128, 64));
+        assert_eq!(chip8.export_video().len(), 128 * 64);
+
+        // clipped to the 8x4 pixels still on screen
+        let lit = chip8.export_video().iter().filter(|p| **p != 0).count();
+        assert_eq!(lit, 8 * 4);
+        assert_ne!(chip8.video[63 * 128 + 127], 0);
+
+        // plain CHIP-8 doesn't know 00FF
+        let mut chip8 = Chip8::with_variant(Variant::Chip8);
+        chip8.reset_and_load_bytes(&rom).unwrap();
+        assert_eq!(chip8.step(), Err(Chip8Error::UnknownOpcode { pc: 0x200, opcode: 0x00FF }));
+        assert!(!chip8.hires());
+    }
+
+    #[test]
+    fn schip_scrolling() {
+        // V0 = 8, V1 = 0, I = 0x300, draw a single pixel at (8, 0), scroll down 3, right, left, left
+        let mut rom = vec![0x60, 8, 0x61, 0, 0xA3, 0x00, 0xD0, 0x11, 0x00, 0xC3, 0x00, 0xFB];
+        rom.extend([0x00, 0xFC, 0x00, 0xFC]);
+        rom.resize(0x100, 0);
+        rom.push(0x80);
+
+        let chip8 = run_variant(Variant::SuperChip, &rom, 5);
+        assert_ne!(chip8.video[3 * 64 + 8], 0);
+        let chip8 = run_variant(Variant::SuperChip, &rom, 6);
+        assert_ne!(chip8.video[3 * 64 + 12], 0);
+        let chip8 = run_variant(Variant::SuperChip, &rom, 8);
+        assert_ne!(chip8.video[3 * 64 + 4], 0);
+        assert_eq!(chip8.export_video().iter().filter(|p| **p != 0).count(), 1);
+    }
+
+    #[test]
+    fn schip_rpl_flags_and_exit() {
+        // V0 = 1, V1 = 2, save to flags, clear, restore, exit
+        let rom = [0x60, 1, 0x61, 2, 0xF1, 0x75, 0x60, 0, 0x61, 0, 0xF1, 0x85, 0x00, 0xFD, 0x60, 9];
+
+        let chip8 = run_variant(Variant::SuperChip, &rom, 10);
+        assert_eq!(chip8.registers[..2], [1, 2]);
+        assert!(chip8.exited());
+        assert_eq!(chip8.program_counter, 0x20E);
+    }
+
+    #[test]
+    fn schip_big_font() {
+        // V0 = 7, I = big sprite for 7
+        let rom = [0x60, 7, 0xF0, 0x30];
+
+        let chip8 = run_variant(Variant::SuperChip, &rom, 2);
+        assert_eq!(chip8.index_register, 0xA0 + 70);
+        assert_eq!(chip8.memory[0xA0 + 70..0xA0 + 72], [0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn xochip_long_load_and_skip() {
+        // skip the whole F000 NNNN when V0 == 0, then I = 0xABCD
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0xF0, 0x00, 0xAB, 0xCD];
+
+        let chip8 = run_variant(Variant::XoChip, &rom, 2);
+        assert_eq!(chip8.index_register, 0xABCD);
+        assert_eq!(chip8.program_counter, 0x20A);
+        assert_eq!(chip8.memory.len(), 0x10000);
+    }
+
+    #[test]
+    fn xochip_register_ranges() {
+        // V1 = 1, V2 = 2, V3 = 3, I = 0x300, store V3..V1, load them back into V4..V6
+        let rom = [0x61, 1, 0x62, 2, 0x63, 3, 0xA3, 0x00, 0x53, 0x12, 0x54, 0x63];
+
+        let chip8 = run_variant(Variant::XoChip, &rom, 6);
+        assert_eq!(chip8.memory[0x300..0x303], [3, 2, 1]);
+        assert_eq!(chip8.registers[4..7], [3, 2, 1]);
+        assert_eq!(chip8.index_register, 0x300);
+    }
+
+    #[test]
+    fn xochip_bitplanes() {
+        // select both planes, I = 0x300, draw a 1 row sprite: plane 1 gets 0x80, plane 2 gets 0xC0
+        let mut rom = vec![0xF3, 0x01, 0xA3, 0x00, 0xD0, 0x01, 0xF2, 0x01, 0x00, 0xE0];
+        rom.resize(0x100, 0);
+        rom.extend([0x80, 0xC0]);
+
+        let chip8 = run_variant(Variant::XoChip, &rom, 3);
+        assert_eq!(chip8.plane(), 3);
+        assert_eq!(chip8.video[..3], [3, 2, 0]);
+
+        // clearing only the second plane leaves the first one alone
+        let chip8 = run_variant(Variant::XoChip, &rom, 5);
+        assert_eq!(chip8.video[..3], [1, 0, 0]);
+    }
+
+    #[test]
+    fn xochip_audio() {
+        // I = 0x300, load the pattern, V0 = 112, set the pitch
+        let mut rom = vec![0xA3, 0x00, 0xF0, 0x02, 0x60, 112, 0xF0, 0x3A];
+        rom.resize(0x100, 0);
+        rom.extend(0..16);
+
+        let chip8 = run_variant(Variant::XoChip, &rom, 4);
+        assert_eq!(chip8.audio_pattern()[15], 15);
+        assert_eq!(chip8.pitch(), 112);
+        assert_eq!(chip8.pattern_rate(), 8000.0);
+    }
+
+    #[test]
+    fn step_errors() {
+        let mut chip8 = Chip8::new();
+
+        chip8.reset_and_load_bytes(&[0x00, 0xEE]).unwrap();
+        assert_eq!(chip8.step(), Err(Chip8Error::StackUnderflow { pc: 0x200, opcode: 0x00EE }));
+
+        // call itself forever
+        chip8.reset_and_load_bytes(&[0x22, 0x00]).unwrap();
+        for _ in 0..16 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.step(), Err(Chip8Error::StackOverflow { pc: 0x200, opcode: 0x2200 }));
+
+        chip8.reset_and_load_bytes(&[0x01, 0x23]).unwrap();
+        assert_eq!(chip8.step(), Err(Chip8Error::UnknownOpcode { pc: 0x200, opcode: 0x0123 }));
+
+        // I = 0xFFE, store V0..V2
+        chip8.reset_and_load_bytes(&[0xAF, 0xFE, 0xF2, 0x55]).unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.step(), Err(Chip8Error::MemOob { pc: 0x202, opcode: 0xF255, address: 0x1000 }));
+        assert_eq!(chip8.memory[0xFFE..], [0, 0]);
+
+        // jump to the last byte of memory
+        chip8.reset_and_load_bytes(&[0x1F, 0xFF]).unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.step(), Err(Chip8Error::PcOob { pc: 0xFFF }));
+    }
+
+    #[test]
+    fn rom_loading() {
+        let mut chip8 = Chip8::new();
+        let rom = std::env::temp_dir().join(format!("chip8-rom-{}.ch8", std::process::id()));
+        std::fs::write(&rom, vec![0; 0x1000]).unwrap();
+        let error = chip8.load_rom(rom.clone()).unwrap_err();
+        std::fs::remove_file(rom).unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), Chip8Error::RomTooLarge { size: 0x1000 }.to_string());
+
+        // dumping stops at the end of memory
+        chip8.reset_and_load_bytes(&[0x12, 0x34]).unwrap();
+        assert_eq!(chip8.dump_rom(0x200, 2), [0x12, 0x34]);
+        assert_eq!(chip8.dump_rom(0xFFE, 16).len(), 2);
+        assert!(chip8.dump_rom(0x2000, 16).is_empty());
+        assert_eq!(chip8.dump_rom(0xFFE, usize::MAX).len(), 2);
+    }
+
+    #[test]
+    fn decode_and_execute() {
+        let mut chip8 = Chip8::new();
+        assert_eq!(chip8.decode(0x7105), Ok(Instruction::AddByte { x: 1, byte: 5 }));
+        assert_eq!(chip8.decode(0x5123), Err(Chip8Error::UnknownOpcode { pc: 0x200, opcode: 0x5123 }));
+        assert_eq!(chip8.decode(0xF000), Err(Chip8Error::UnknownOpcode { pc: 0x200, opcode: 0xF000 }));
+        assert!(Chip8::with_variant(Variant::XoChip).decode(0xF000).is_ok());
+
+        // executing moves PC past the instruction, without anything in memory
+        chip8.execute(Instruction::AddByte { x: 1, byte: 5 }).unwrap();
+        assert_eq!((chip8.registers[1], chip8.program_counter), (5, 0x202));
+        chip8.execute(Instruction::Jump { addr: 0x300 }).unwrap();
+        assert_eq!(chip8.program_counter, 0x300);
+
+        // instructions of later variants fail like unknown opcodes, and change nothing
+        assert_eq!(chip8.execute(Instruction::Hires), Err(Chip8Error::UnknownOpcode { pc: 0x300, opcode: 0x00FF }));
+        assert_eq!(chip8.execute(Instruction::Sys { addr: 0x123 }), Err(Chip8Error::UnknownOpcode { pc: 0x300, opcode: 0x0123 }));
+        assert_eq!(chip8.execute(Instruction::Ret), Err(Chip8Error::StackUnderflow { pc: 0x300, opcode: 0x00EE }));
+        assert_eq!(chip8.program_counter, 0x300);
+        assert!(chip8.fault().is_none());
+    }
+
+    #[test]
+    fn fault_halts_until_reset() {
+        let mut chip8 = Chip8::new();
+        chip8.reset_and_load_bytes(&[0x60, 0x01, 0xFF, 0xFF]).unwrap();
+
+        assert_eq!(chip8.step(), Ok(StepOutcome::Executed { pc: 0x200, opcode: 0x6001 }));
+        let error = chip8.step().unwrap_err();
+        assert_eq!(chip8.step(), Err(error.clone()));
+        assert_eq!(chip8.fault(), Some(&error));
+        assert_eq!(chip8.program_counter, 0x202);
+
+        chip8.reset_and_load_bytes(&[0x60, 0x01]).unwrap();
+        assert!(chip8.fault().is_none());
+        assert!(chip8.step().is_ok());
+    }
+
+    #[test]
+    fn timers_tick_per_frame() {
+        // V0 = 60, delay timer = V0, then loop forever
+        let rom = [0x60, 60, 0xF0, 0x15, 0x12, 0x04];
+
+        for ipf in [1, 10, 1000] {
+            let mut chip8 = Chip8::new();
+            chip8.set_ipf(ipf);
+            chip8.reset_and_load_bytes(&rom).unwrap();
+
+            // the timer is loaded in the first frame, or in the second one with a single instruction per frame
+            for _ in 0..3 {
+                chip8.run_frame().unwrap();
+            }
+            assert_eq!(chip8.delay_timer(), if ipf == 1 { 58 } else { 57 });
+        }
+
+        // stepping alone never touches the timers
+        let mut chip8 = Chip8::new();
+        chip8.reset_and_load_bytes(&rom).unwrap();
+        for _ in 0..100 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.delay_timer(), 60);
+    }
+
+    #[test]
+    fn display_wait_ends_frame() {
+        // draw, then V0 += 1, forever
+        let rom = [0xD0, 0x01, 0x70, 0x01, 0x12, 0x00];
+
+        let mut vip = Chip8::with_quirks(Quirks::COSMAC_VIP);
+        vip.reset_and_load_bytes(&rom).unwrap();
+        assert_eq!(vip.run_frame(), Ok(StepOutcome::WaitingVblank));
+        assert_eq!(vip.registers[0], 0);
+        vip.run_frame().unwrap();
+        assert_eq!(vip.registers[0], 1);
+
+        let mut schip = Chip8::with_quirks(Quirks::SUPER_CHIP);
+        schip.reset_and_load_bytes(&rom).unwrap();
+        schip.run_frame().unwrap();
+        assert_eq!(schip.registers[0], 3);
+    }
+
+    #[test]
+    fn quirks_from_name() {
+        assert_eq!("vip".parse::<Quirks>().unwrap(), Quirks::COSMAC_VIP);
+        assert_eq!("SUPER-CHIP".parse::<Quirks>().unwrap(), Quirks::SUPER_CHIP);
+        assert_eq!("xo_chip".parse::<Quirks>().unwrap(), Quirks::XO_CHIP);
+        assert!("gameboy".parse::<Quirks>().is_err());
+
+        let vip = "vip+key_press".parse::<Quirks>().unwrap();
+        assert_eq!(vip, Quirks { key_press: true, ..Quirks::COSMAC_VIP });
+        let schip = "schip + key-press + display_wait".parse::<Quirks>().unwrap();
+        assert_eq!(schip, Quirks { key_press: true, display_wait: true, ..Quirks::SUPER_CHIP });
+        assert!("vip+turbo".parse::<Quirks>().is_err());
+    }
+
+    #[test]
+    fn debugger_accessors() {
+        // I = 0x300, V3 = 7, call 0x208 which calls 0x20A
+        let rom = [0xA3, 0x00, 0x63, 0x07, 0x22, 0x08, 0x00, 0x00, 0x22, 0x0A, 0x00, 0xEE];
+        let chip8 = run(Quirks::default(), &rom, 4);
+
+        assert_eq!(chip8.registers()[3], 7);
+        assert_eq!(chip8.index_register(), 0x300);
+        assert_eq!(chip8.program_counter(), 0x20A);
+        assert_eq!(chip8.stack_pointer(), 2);
+        assert_eq!(chip8.stack(), [0x206, 0x20A]);
+        assert_eq!(&chip8.memory()[0x200..0x202], [0xA3, 0x00]);
+    }
 }
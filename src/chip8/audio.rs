@@ -0,0 +1,373 @@
+//! Sound: the beeper, rendered into samples by `Chip8::fill_audio`.
+//!
+//! Every timer tick queues the sound of the frame that just ran: whether the sound timer was
+//! running and, for XO-CHIP, the pattern buffer and its playback rate. `fill_audio` plays the
+//! queued frames back at 1/60 s each, so the tone starts and stops on frame boundaries however
+//! the host splits its buffers. Without a queued frame the output is silent. The steps of the
+//! square wave and of the pattern are smoothed with PolyBLEP so high pitches don't alias, and
+//! the beep fades in and out over `RAMP_SECONDS` so it doesn't click.
+
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use super::{Chip8, Variant};
+
+/// Frames kept for `fill_audio`, the oldest are dropped when a host doesn't play them
+const MAX_QUEUED_FRAMES: usize = 10;
+
+/// Length of the fade in and out of the beep
+const RAMP_SECONDS: f32 = 0.002;
+
+/// Shape of the beep of machines without a pattern buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sawtooth" | "saw" => Ok(Waveform::Sawtooth),
+            "sine" => Ok(Waveform::Sine),
+            _ => Err(format!("unknown waveform {} (expected square, triangle, sawtooth or sine)", name)),
+        }
+    }
+}
+
+/// The beep of machines without a pattern buffer: its frequency in Hz and its shape.
+/// A host setting like the breakpoints, not part of save states
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Beep {
+    pub pitch: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for Beep {
+    fn default() -> Self {
+        Beep { pitch: 440.0, waveform: Waveform::Square }
+    }
+}
+
+/// What one frame sounded like
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FrameSound {
+    on: bool,
+    // XO-CHIP pattern buffer and its rate in bits per second, None plays the beep
+    pattern: Option<([u8; 16], f32)>,
+}
+
+/// Playback state of `fill_audio`
+#[derive(Debug, Default)]
+pub(crate) struct AudioQueue {
+    frames: VecDeque<FrameSound>,
+    // the frame being played and how much of it was played, in 1/60 samples: a frame is `sample_rate` long
+    current: Option<FrameSound>,
+    position: u64,
+    // the last frame that beeped, a fading beep keeps its shape
+    last: Option<FrameSound>,
+    // periods of the beep, or passes through the pattern, played so far
+    phase: f64,
+    // fade from 0 (silent) to 1
+    level: f32,
+}
+
+impl AudioQueue {
+    pub(crate) fn clear(&mut self) {
+        *self = AudioQueue::default();
+    }
+}
+
+/// PolyBLEP correction for a step of height 2 at `t = 0`, `t` and `dt` in periods
+fn blep(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let x = t / dt;
+        2.0 * x - x * x - 1.0
+    } else if t > 1.0 - dt {
+        let x = (t - 1.0) / dt;
+        x * x + 2.0 * x + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// Bit `index` of the pattern as -1 or 1, the most significant bit of the first byte comes first
+fn pattern_bit(pattern: &[u8; 16], index: usize) -> f64 {
+    let index = index % 128;
+    if pattern[index / 8] & (0x80 >> (index % 8)) != 0 { 1.0 } else { -1.0 }
+}
+
+impl Chip8 {
+    /// Queue the sound of the frame that just ran, called by `tick_timers` before the sound timer counts down
+    pub(crate) fn queue_frame_sound(&mut self) {
+        let pattern = (self.variant == Variant::XoChip && self.audio_pattern.iter().any(|byte| *byte != 0))
+            .then(|| (self.audio_pattern, self.pattern_rate()));
+        let frames = &mut self.audio.frames;
+        if frames.len() == MAX_QUEUED_FRAMES {
+            frames.pop_front();
+        }
+        frames.push_back(FrameSound { on: self.sound_timer > 0, pattern });
+    }
+
+    /// The beep of machines without a pattern buffer
+    pub fn beep(&self) -> Beep { self.beep }
+
+    /// Change the beep, the frames already queued for `fill_audio` play with the new one too
+    pub fn set_beep(&mut self, beep: Beep) {
+        self.beep = beep;
+    }
+
+    /// Fill `buf` with the sound of the frames that ran since the last call, between -1 and 1
+    /// at `sample_rate` samples per second. Each frame lasts 1/60 s, call this once per frame
+    /// with that many samples or once per host buffer, the result is the same. The machine
+    /// keeps the last few frames for it, older ones are dropped.
+    ///
+    /// The sound timer plays the beep, 440 Hz square wave unless `set_beep` changes it.
+    /// XO-CHIP plays its pattern buffer at `pattern_rate` instead, once F002 loads a pattern.
+    ///
+    /// ```
+    /// # use chip8_rs::chip8::Chip8;
+    /// let mut chip8 = Chip8::new();
+    /// // V0 = 2, sound timer = V0
+    /// chip8.reset_and_load_bytes(&[0x60, 0x02, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+    /// let mut frame = [0.0; 800];
+    /// for _ in 0..2 {
+    ///     chip8.run_frame().unwrap();
+    ///     chip8.fill_audio(&mut frame, 48000);
+    ///     assert!(frame.iter().any(|sample| *sample > 0.5));
+    /// }
+    /// chip8.run_frame().unwrap();
+    /// chip8.fill_audio(&mut frame, 48000);
+    /// assert!(frame[100..].iter().all(|sample| *sample == 0.0));
+    /// ```
+    pub fn fill_audio(&mut self, buf: &mut [f32], sample_rate: u32) {
+        let rate = sample_rate as f64;
+        let frame_len = sample_rate as u64;
+        let ramp_step = 1.0 / (RAMP_SECONDS * sample_rate as f32);
+        let beep = self.beep;
+        let audio = &mut self.audio;
+
+        for sample in buf.iter_mut() {
+            if audio.current.is_none() || audio.position >= frame_len {
+                audio.current = audio.frames.pop_front();
+                audio.position = if audio.current.is_some() { audio.position.saturating_sub(frame_len) } else { 0 };
+            }
+            let frame = audio.current.filter(|frame| frame.on);
+            if frame.is_some() {
+                audio.last = frame;
+            }
+
+            // a beep that starts from silence starts at the beginning of its period
+            if frame.is_some() && audio.level == 0.0 {
+                audio.phase = 0.0;
+            }
+            let target = if frame.is_some() { 1.0 } else { 0.0 };
+            audio.level = if audio.level < target {
+                (audio.level + ramp_step).min(target)
+            } else {
+                (audio.level - ramp_step).max(target)
+            };
+
+            let shape = audio.last.map(|frame| frame.pattern);
+            let value = match shape {
+                _ if audio.level == 0.0 => 0.0,
+                Some(Some((pattern, bit_rate))) => {
+                    let dt = bit_rate as f64 / rate;
+                    let position = audio.phase * 128.0;
+                    let index = position as usize;
+                    let t = position.fract();
+                    let bit = pattern_bit(&pattern, index);
+                    // the steps into and out of this bit
+                    let mut value = bit;
+                    if t < dt {
+                        value += (bit - pattern_bit(&pattern, index + 127)) / 2.0 * blep(t, dt);
+                    } else if t > 1.0 - dt {
+                        value += (pattern_bit(&pattern, index + 1) - bit) / 2.0 * blep(t, dt);
+                    }
+                    audio.phase = (audio.phase + dt / 128.0).fract();
+                    value
+                }
+                _ => {
+                    let dt = beep.pitch as f64 / rate;
+                    let t = audio.phase;
+                    let value = match beep.waveform {
+                        Waveform::Square => {
+                            let naive = if t < 0.5 { 1.0 } else { -1.0 };
+                            naive + blep(t, dt) - blep((t + 0.5).fract(), dt)
+                        }
+                        Waveform::Sawtooth => 2.0 * t - 1.0 - blep(t, dt),
+                        Waveform::Triangle => 1.0 - 4.0 * (t - 0.5).abs(),
+                        Waveform::Sine => (2.0 * PI * t).sin(),
+                    };
+                    audio.phase = (audio.phase + dt).fract();
+                    value
+                }
+            };
+            *sample = (value as f32 * audio.level).clamp(-1.0, 1.0);
+
+            if audio.current.is_some() {
+                audio.position += 60;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Beep, Waveform};
+    use crate::chip8::{Chip8, Variant};
+
+    const RATE: u32 = 48000;
+    const FRAME: usize = 800;
+
+    /// Sets the sound timer to `frames` and loops
+    fn beeping(frames: u8) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.reset_and_load_bytes(&[0x60, frames, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+        chip8
+    }
+
+    /// Run `frames` frames and return their sound
+    fn render(chip8: &mut Chip8, frames: usize) -> Vec<f32> {
+        let mut out = vec![0.0; frames * FRAME];
+        for frame in out.chunks_mut(FRAME) {
+            chip8.run_frame().unwrap();
+            chip8.fill_audio(frame, RATE);
+        }
+        out
+    }
+
+    fn crossings(samples: &[f32]) -> usize {
+        samples.windows(2).filter(|pair| (pair[0] > 0.0) != (pair[1] > 0.0)).count()
+    }
+
+    #[test]
+    fn silent_without_frames() {
+        let mut chip8 = Chip8::new();
+        let mut buf = [1.0; 100];
+        chip8.fill_audio(&mut buf, RATE);
+        assert!(buf.iter().all(|sample| *sample == 0.0));
+
+        // the sound timer is running but no frame ran yet
+        let mut chip8 = beeping(5);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        chip8.fill_audio(&mut buf, RATE);
+        assert!(buf.iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn beeps_for_as_many_frames_as_the_timer() {
+        let mut chip8 = beeping(3);
+        let sound = render(&mut chip8, 5);
+        // fading in, then full volume
+        assert_eq!(sound[0], 0.0);
+        for frame in sound[..3 * FRAME].chunks(FRAME) {
+            assert!(frame.contains(&1.0));
+            assert!(frame.contains(&-1.0));
+        }
+        // 2 ms to fade out
+        assert!(sound[3 * FRAME..3 * FRAME + 96].iter().any(|sample| *sample != 0.0));
+        assert!(sound[3 * FRAME + 96..].iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn buffers_dont_change_the_sound() {
+        let mut chip8 = beeping(4);
+        let per_frame = render(&mut chip8, 6);
+
+        let mut chip8 = beeping(4);
+        for _ in 0..6 {
+            chip8.run_frame().unwrap();
+        }
+        let mut split = vec![0.0; 6 * FRAME];
+        for buf in split.chunks_mut(128) {
+            chip8.fill_audio(buf, RATE);
+        }
+        assert_eq!(per_frame, split);
+    }
+
+    #[test]
+    fn square_wave() {
+        let mut chip8 = beeping(10);
+        let sound = render(&mut chip8, 10);
+        // 440 Hz crosses zero 880 times a second, 8.8 times per 1/100 s
+        let count = crossings(&sound[FRAME..FRAME + 480]);
+        assert!((8..=9).contains(&count), "{} crossings", count);
+        // half the time up and half down
+        let up = sound[FRAME..9 * FRAME].iter().filter(|sample| **sample > 0.0).count();
+        assert!((up as f32 / (8 * FRAME) as f32 - 0.5).abs() < 0.01);
+        // band-limited: the edges take more than one sample
+        let largest_step = sound.windows(2).map(|pair| (pair[1] - pair[0]).abs()).fold(0.0, f32::max);
+        assert!(largest_step < 1.5, "step {}", largest_step);
+        assert!(sound.iter().all(|sample| (-1.0..=1.0).contains(sample)));
+
+        let mut chip8 = beeping(10);
+        chip8.set_beep(Beep { pitch: 880.0, waveform: Waveform::Square });
+        let higher = render(&mut chip8, 10);
+        assert!((17..=18).contains(&crossings(&higher[FRAME..FRAME + 480])));
+    }
+
+    #[test]
+    fn waveforms() {
+        for waveform in [Waveform::Triangle, Waveform::Sawtooth, Waveform::Sine] {
+            let mut chip8 = beeping(10);
+            chip8.set_beep(Beep { pitch: 440.0, waveform });
+            let sound = render(&mut chip8, 3);
+            assert!((8..=9).contains(&crossings(&sound[FRAME..FRAME + 480])), "{:?}", waveform);
+            assert!(sound.iter().all(|sample| (-1.0..=1.0).contains(sample)));
+        }
+        assert_eq!("SAW".parse(), Ok(Waveform::Sawtooth));
+        assert!("noise".parse::<Waveform>().is_err());
+    }
+
+    #[test]
+    fn xo_chip_pattern() {
+        let mut chip8 = Chip8::with_variant(Variant::XoChip);
+        let mut rom = vec![
+            0xA2, 0x10, // 200: I = 0x210
+            0xF0, 0x02, // 202: load the pattern
+            0x60, 0x70, // 204: V0 = 112, 8000 bits per second
+            0xF0, 0x3A, // 206: pitch = V0
+            0x60, 0x0A, // 208: V0 = 10
+            0xF0, 0x18, // 20A: sound timer = V0
+            0x12, 0x0C, // 20C: loop
+            0x00, 0x00, // 20E
+        ];
+        // 8 bits up, 8 bits down: 500 Hz at 8000 bits per second
+        rom.extend([0xFF, 0x00].repeat(8));
+        chip8.reset_and_load_bytes(&rom).unwrap();
+
+        let sound = render(&mut chip8, 10);
+        // 500 Hz crosses zero 1000 times a second, 10 times per 1/100 s
+        assert_eq!(crossings(&sound[FRAME..FRAME + 480]), 10);
+        // 96 samples per period, 48 up and 48 down
+        let period = &sound[FRAME..FRAME + 96];
+        assert_eq!(period.iter().filter(|sample| **sample > 0.0).count(), 48);
+        assert!(sound.iter().all(|sample| (-1.0..=1.0).contains(sample)));
+
+        // without a pattern XO-CHIP beeps like the others
+        let mut chip8 = Chip8::with_variant(Variant::XoChip);
+        chip8.reset_and_load_bytes(&[0x60, 0x0A, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+        let sound = render(&mut chip8, 3);
+        assert!((8..=9).contains(&crossings(&sound[FRAME..FRAME + 480])));
+    }
+
+    #[test]
+    fn reset_drops_queued_frames() {
+        let mut chip8 = beeping(10);
+        chip8.run_frame().unwrap();
+        chip8.reset_hard();
+        let mut buf = [0.0; FRAME];
+        chip8.fill_audio(&mut buf, RATE);
+        assert!(buf.iter().all(|sample| *sample == 0.0));
+    }
+}
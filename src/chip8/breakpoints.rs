@@ -0,0 +1,578 @@
+//! Breakpoints and watchpoints, the hooks debuggers stop the machine with.
+//!
+//! PC and opcode breakpoints are checked by `step` before an instruction runs, register
+//! conditions and watchpoints once it ran. A step that hits one returns `StepOutcome::Break`
+//! and `run_frame` stops there, the rest of the frame runs on the next call.
+//!
+//! Step over, step out and run to cursor share one temporary target, see `Chip8::run_to`, so
+//! every frontend steps the same way.
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::{Chip8, StepOutcome};
+use crate::disasm::Instruction;
+
+/// Handle returned when adding a breakpoint or a watchpoint, used to remove it again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BreakpointId(pub u32);
+
+impl fmt::Display for BreakpointId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Where or when to stop, parsed from strings like `0x2A4`, `0x2A4 if VF == 1`, `VF == 1` or `Dxyn`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Before the instruction at this address
+    Pc(u16),
+    /// Before the instruction at `pc`, if `V[register] == value` at that point
+    PcIf { pc: u16, register: u8, value: u8 },
+    /// After any instruction that makes `V[register] == value`, it doesn't fire again while it stays true
+    Register { register: u8, value: u8 },
+    /// Before any instruction with `opcode & mask == pattern`, `Dxyn` is mask 0xF000 and pattern 0xD000
+    Opcode { mask: u16, pattern: u16 },
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let spec = spec.trim();
+        if let Some((pc, condition)) = spec.split_once(" if ") {
+            let pc = parse_address(pc.trim())?;
+            let (register, value) = parse_condition(condition)?;
+            return Ok(Breakpoint::PcIf { pc, register, value });
+        }
+        if spec.contains("==") {
+            let (register, value) = parse_condition(spec)?;
+            return Ok(Breakpoint::Register { register, value });
+        }
+        if spec.starts_with("0x") || spec.starts_with("0X") {
+            return parse_address(spec).map(Breakpoint::Pc);
+        }
+        if let Some((mask, pattern)) = parse_opcode_pattern(spec) {
+            return Ok(Breakpoint::Opcode { mask, pattern });
+        }
+        Err(format!("Bad breakpoint: {} (expected an address like 0x2A4, a condition like VF == 1 or an opcode like Dxyn)", spec))
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Breakpoint::Pc(pc) =>
+                write!(f, "{:#05X}", pc),
+            Breakpoint::PcIf { pc, register, value } =>
+                write!(f, "{:#05X} if V{:X} == {:#04X}", pc, register, value),
+            Breakpoint::Register { register, value } =>
+                write!(f, "V{:X} == {:#04X}", register, value),
+            Breakpoint::Opcode { mask, pattern } => {
+                for (i, wildcard) in ['x', 'x', 'y', 'n'].iter().enumerate() {
+                    let shift = 12 - 4 * i;
+                    if mask >> shift & 0xF == 0xF {
+                        write!(f, "{:X}", pattern >> shift & 0xF)?;
+                    } else {
+                        write!(f, "{}", wildcard)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// An opcode pattern like `Dxyn` or `8xy4` into its mask and pattern: hex digits have to match,
+/// the letters x, y, n and k are wildcard nibbles. `None` for anything else
+pub(crate) fn parse_opcode_pattern(spec: &str) -> Option<(u16, u16)> {
+    if spec.chars().count() != 4 || !spec.chars().all(|c| c.is_ascii_hexdigit() || "xynkXYNK".contains(c)) {
+        return None;
+    }
+    let (mut mask, mut pattern) = (0, 0);
+    for c in spec.chars() {
+        mask <<= 4;
+        pattern <<= 4;
+        if let Some(digit) = c.to_digit(16) {
+            mask |= 0xF;
+            pattern |= digit as u16;
+        }
+    }
+    Some((mask, pattern))
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    let hex = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    u16::from_str_radix(hex, 16).map_err(|_| format!("Bad address: {}", text))
+}
+
+/// `VF == 1` into (0xF, 1), values are decimal or 0x hex
+fn parse_condition(text: &str) -> Result<(u8, u8), String> {
+    let bad = || format!("Bad condition: {} (expected e.g. VF == 1)", text.trim());
+    let (register, value) = text.split_once("==").ok_or_else(bad)?;
+    let register = register.trim();
+    let register = register.strip_prefix(['V', 'v']).ok_or_else(bad)?;
+    let register = u8::from_str_radix(register, 16).ok().filter(|r| *r < 16).ok_or_else(bad)?;
+    let value = value.trim();
+    let value = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    Ok((register, value.map_err(|_| bad())?))
+}
+
+/// The kind of memory access a watchpoint catches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn catches(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::ReadWrite => "access",
+        })
+    }
+}
+
+/// Stops after an instruction that reads or writes the `len` bytes at `start`.
+/// Caught accesses are the ones made through I: FX33, FX55, FX65, DXYN and the XO-CHIP
+/// 5XY2, 5XY3 and F002, instruction fetches are not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub len: u16,
+    pub access: Access,
+}
+
+/// What made `step` stop, see `StepOutcome::Break`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Break {
+    /// A PC or opcode breakpoint, the instruction at `pc` has not run yet
+    Breakpoint { id: BreakpointId, pc: u16 },
+    /// The target of `run_to`, `step_over` or `step_out`, the instruction at `pc` has not run yet
+    Target { pc: u16 },
+    /// A register condition became true, the instruction at `pc` made it so
+    Condition { id: BreakpointId, pc: u16 },
+    /// The instruction at `pc` accessed `address`, a watched byte
+    Watchpoint { id: BreakpointId, pc: u16, address: u16, access: Access },
+}
+
+impl Break {
+    /// The breakpoint or watchpoint that fired, `None` for a reached `run_to` target
+    pub fn id(&self) -> Option<BreakpointId> {
+        match *self {
+            Break::Breakpoint { id, .. } | Break::Condition { id, .. } | Break::Watchpoint { id, .. } => Some(id),
+            Break::Target { .. } => None,
+        }
+    }
+
+    /// Address of the instruction that hit the breakpoint
+    pub fn pc(&self) -> u16 {
+        match *self {
+            Break::Breakpoint { pc, .. }
+            | Break::Target { pc }
+            | Break::Condition { pc, .. }
+            | Break::Watchpoint { pc, .. } => pc,
+        }
+    }
+
+    /// Whether that instruction ran, only PC and opcode breakpoints and targets stop before it
+    pub fn executed(&self) -> bool {
+        !matches!(self, Break::Breakpoint { .. } | Break::Target { .. })
+    }
+}
+
+impl fmt::Display for Break {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Break::Breakpoint { id, pc } =>
+                write!(f, "Breakpoint {} at {:04X}", id, pc),
+            Break::Target { pc } =>
+                write!(f, "Reached {:04X}", pc),
+            Break::Condition { id, pc } =>
+                write!(f, "Breakpoint {} condition met at {:04X}", id, pc),
+            Break::Watchpoint { id, pc, address, access } =>
+                write!(f, "Watchpoint {}: {} of {:04X} at {:04X}", id, access, address, pc),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Hooks {
+    next_id: u32,
+    breakpoints: Vec<(BreakpointId, Breakpoint)>,
+    watchpoints: Vec<(BreakpointId, Watchpoint)>,
+    // address of the instruction a breakpoint stopped before, the next step runs it unchecked
+    resume: Option<u16>,
+    // first watchpoint hit by the instruction being executed
+    hit: Option<Break>,
+    // where `run_to` stops, and the most calls that may be active there
+    target: Option<(u16, Option<u8>)>,
+}
+
+impl Hooks {
+    /// Forget where execution stopped, the breakpoints themselves are kept
+    pub(crate) fn interrupt(&mut self) {
+        self.resume = None;
+        self.hit = None;
+    }
+
+    fn next_id(&mut self) -> BreakpointId {
+        self.next_id += 1;
+        BreakpointId(self.next_id)
+    }
+}
+
+impl Chip8 {
+    /// Add a breakpoint, it stays until removed, resets and loaded states keep it
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> BreakpointId {
+        let id = self.hooks.next_id();
+        self.hooks.breakpoints.push((id, breakpoint));
+        id
+    }
+
+    /// Add a watchpoint, breakpoints and watchpoints share their ids
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> BreakpointId {
+        let id = self.hooks.next_id();
+        self.hooks.watchpoints.push((id, watchpoint));
+        id
+    }
+
+    /// Remove a breakpoint or a watchpoint, false if there was none with that id
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+        let count = self.hooks.breakpoints.len() + self.hooks.watchpoints.len();
+        self.hooks.breakpoints.retain(|(other, _)| *other != id);
+        self.hooks.watchpoints.retain(|(other, _)| *other != id);
+        count != self.hooks.breakpoints.len() + self.hooks.watchpoints.len()
+    }
+
+    /// Remove all breakpoints and watchpoints
+    pub fn clear_breakpoints(&mut self) {
+        self.hooks.breakpoints.clear();
+        self.hooks.watchpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (BreakpointId, &Breakpoint)> {
+        self.hooks.breakpoints.iter().map(|(id, breakpoint)| (*id, breakpoint))
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (BreakpointId, &Watchpoint)> {
+        self.hooks.watchpoints.iter().map(|(id, watchpoint)| (*id, watchpoint))
+    }
+
+    /// Resume up to the instruction at `pc`: `step` returns `Break::Target` before running it, once
+    /// at most `max_depth` calls are active, or at any depth when `None`. Only one target is kept,
+    /// and any breakpoint or watchpoint that fires first cancels it.
+    pub fn run_to(&mut self, pc: u16, max_depth: Option<u8>) {
+        self.hooks.target = Some((pc, max_depth));
+    }
+
+    /// Run the whole call when PC is on a CALL, stopping at the instruction after it. Recursive
+    /// calls that come back to that instruction don't stop. False when PC is on anything else,
+    /// a plain `debug_step` does then.
+    pub fn step_over(&mut self) -> bool {
+        let pc = self.program_counter;
+        let call = match self.memory.get(pc as usize..pc as usize + 2) {
+            Some(bytes) => matches!(Instruction::decode((bytes[0] as u16) << 8 | bytes[1] as u16), Instruction::Call { .. }),
+            None => false,
+        };
+        if call {
+            self.run_to(pc.wrapping_add(2), Some(self.stack_pointer));
+        }
+        call
+    }
+
+    /// Run until the current call returns, false outside of calls
+    pub fn step_out(&mut self) -> bool {
+        let Some(depth) = self.stack_pointer.checked_sub(1) else {
+            return false;
+        };
+        self.run_to(self.stack[depth as usize], Some(depth));
+        true
+    }
+
+    /// Drop the `run_to` target, e.g. when the user pauses
+    pub fn cancel_run_to(&mut self) {
+        self.hooks.target = None;
+    }
+
+    /// Where `run_to`, `step_over` or `step_out` stops next
+    pub fn run_to_target(&self) -> Option<u16> {
+        self.hooks.target.map(|(pc, _)| pc)
+    }
+
+    /// Run up to `frames` frames and return the first breakpoint or watchpoint hit, `None` when
+    /// the frames ran out or the ROM exited. Calling it again after a hit carries on from there.
+    pub fn run_until_break(&mut self, frames: u32) -> Result<Option<Break>, super::Chip8Error> {
+        for _ in 0..frames {
+            match self.run_frame()? {
+                StepOutcome::Break(hit) => return Ok(Some(hit)),
+                StepOutcome::Exited => break,
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Execute one instruction the way a debugger's single step expects: going through the
+    /// vertical blank if DXYN is waiting for it, and running the instruction even if a breakpoint
+    /// is set on it. Watchpoints and register conditions still return `StepOutcome::Break`.
+    pub fn debug_step(&mut self) -> Result<StepOutcome, super::Chip8Error> {
+        let mut outcome = self.step()?;
+        if outcome == StepOutcome::WaitingVblank {
+            self.tick_timers();
+            outcome = self.step()?;
+        }
+        match outcome {
+            StepOutcome::Break(hit) if !hit.executed() => self.step(),
+            outcome => Ok(outcome),
+        }
+    }
+
+    /// PC and opcode breakpoints for the instruction at `pc`, checked by `step` before running it
+    pub(crate) fn break_before(&mut self, pc: u16, opcode: u16) -> Option<Break> {
+        // a step right after a breakpoint runs the instruction it stopped before
+        if self.hooks.resume.take() == Some(pc) {
+            return None;
+        }
+        let registers = &self.registers;
+        let id = self.hooks.breakpoints.iter().find_map(|(id, breakpoint)| {
+            let hit = match *breakpoint {
+                Breakpoint::Pc(address) => address == pc,
+                Breakpoint::PcIf { pc: address, register, value } =>
+                    address == pc && registers[register as usize & 0xF] == value,
+                Breakpoint::Opcode { mask, pattern } => opcode & mask == pattern,
+                Breakpoint::Register { .. } => false,
+            };
+            hit.then_some(*id)
+        });
+        let hit = match id {
+            Some(id) => Break::Breakpoint { id, pc },
+            None => {
+                let (target, depth) = self.hooks.target?;
+                if target != pc || depth.is_some_and(|depth| self.stack_pointer > depth) {
+                    return None;
+                }
+                Break::Target { pc }
+            }
+        };
+        self.hooks.target = None;
+        self.hooks.resume = Some(pc);
+        Some(hit)
+    }
+
+    /// Watchpoints hit and register conditions met by the instruction at `pc`, `before` holds the
+    /// registers it started with
+    pub(crate) fn break_after(&mut self, pc: u16, before: &[u8; 16]) -> Option<Break> {
+        let registers = &self.registers;
+        let hit = self.hooks.hit.take().or_else(|| {
+            self.hooks.breakpoints.iter().find_map(|(id, breakpoint)| match *breakpoint {
+                Breakpoint::Register { register, value } => {
+                    let register = register as usize & 0xF;
+                    (registers[register] == value && before[register] != value).then_some(Break::Condition { id: *id, pc })
+                }
+                _ => None,
+            })
+        })?;
+        self.hooks.target = None;
+        Some(hit)
+    }
+
+    /// Report an access of the `len` bytes at `address` by the instruction being executed
+    pub(crate) fn watch(&mut self, address: usize, len: usize, access: Access) {
+        self.trace_access(address, len, access);
+        if self.hooks.hit.is_some() {
+            return;
+        }
+        let pc = self.last_pc;
+        self.hooks.hit = self.hooks.watchpoints.iter().find_map(|(id, watchpoint)| {
+            let start = address.max(watchpoint.start as usize);
+            let end = (address + len).min(watchpoint.start as usize + watchpoint.len as usize);
+            (start < end && watchpoint.access.catches(access))
+                .then_some(Break::Watchpoint { id: *id, pc, address: start as u16, access })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, Break, Breakpoint, Watchpoint};
+    use crate::chip8::{Chip8, StepOutcome};
+
+    // V1 counts frames, its digit is drawn from the font and stored as BCD at 0x300
+    const ROM: [u8; 14] = [
+        0x71, 0x01, // 200: V1 += 1
+        0xF1, 0x29, // 202: I = font(V1)
+        0xD0, 0x05, // 204: draw
+        0xA3, 0x00, // 206: I = 0x300
+        0xF1, 0x33, // 208: BCD V1
+        0x6F, 0x00, // 20A: VF = 0
+        0x12, 0x00, // 20C: jump 0x200
+    ];
+
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.reset_and_load_bytes(&ROM).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn parse_and_display() {
+        let cases = [
+            ("0x2A4", Breakpoint::Pc(0x2A4)),
+            ("0x2A4 if VF == 1", Breakpoint::PcIf { pc: 0x2A4, register: 0xF, value: 1 }),
+            ("v3 == 0x10", Breakpoint::Register { register: 3, value: 0x10 }),
+            ("Dxyn", Breakpoint::Opcode { mask: 0xF000, pattern: 0xD000 }),
+            ("Fx55", Breakpoint::Opcode { mask: 0xF0FF, pattern: 0xF055 }),
+        ];
+        for (spec, breakpoint) in cases {
+            assert_eq!(spec.parse::<Breakpoint>(), Ok(breakpoint));
+            assert_eq!(breakpoint.to_string().parse::<Breakpoint>(), Ok(breakpoint));
+        }
+        assert_eq!(Breakpoint::Opcode { mask: 0xF000, pattern: 0xD000 }.to_string(), "Dxyn");
+        assert!("VG == 1".parse::<Breakpoint>().is_err());
+        assert!("0x2A4 if V1".parse::<Breakpoint>().is_err());
+        assert!("somewhere".parse::<Breakpoint>().is_err());
+        // only x, y, n and k are wildcards
+        assert!("zzzz".parse::<Breakpoint>().is_err());
+        assert!("main".parse::<Breakpoint>().is_err());
+        assert_eq!("8xyk".parse::<Breakpoint>(), Ok(Breakpoint::Opcode { mask: 0xF000, pattern: 0x8000 }));
+    }
+
+    #[test]
+    fn pc_breakpoints_stop_before_the_instruction() {
+        let mut chip8 = machine();
+        let id = chip8.add_breakpoint(Breakpoint::Pc(0x208));
+
+        let hit = chip8.run_until_break(10).unwrap();
+        assert_eq!(hit, Some(Break::Breakpoint { id, pc: 0x208 }));
+        assert_eq!(chip8.program_counter(), 0x208);
+        assert_eq!(chip8.memory()[0x302], 0);
+
+        // continuing runs the instruction and stops there again one frame later
+        assert_eq!(chip8.step().unwrap(), StepOutcome::Executed { pc: 0x208, opcode: 0xF133 });
+        assert_eq!(chip8.memory()[0x302], 1);
+        assert_eq!(chip8.run_until_break(10).unwrap(), hit);
+        assert_eq!(chip8.registers()[1], 2);
+
+        assert!(chip8.remove_breakpoint(id));
+        assert!(!chip8.remove_breakpoint(id));
+        assert_eq!(chip8.run_until_break(10).unwrap(), None);
+    }
+
+    #[test]
+    fn conditions_and_opcode_classes() {
+        let mut chip8 = machine();
+        let draw = chip8.add_breakpoint("Dxyn".parse().unwrap());
+        assert_eq!(chip8.run_until_break(1).unwrap(), Some(Break::Breakpoint { id: draw, pc: 0x204 }));
+        chip8.clear_breakpoints();
+
+        // V1 reaches 3 on the third frame
+        let three = chip8.add_breakpoint("V1 == 3".parse().unwrap());
+        assert_eq!(chip8.run_until_break(10).unwrap(), Some(Break::Condition { id: three, pc: 0x200 }));
+        assert_eq!(chip8.registers()[1], 3);
+        // it doesn't fire again while V1 stays 3
+        assert_eq!(chip8.run_until_break(1).unwrap(), None);
+
+        chip8.remove_breakpoint(three);
+        let collision = chip8.add_breakpoint("0x206 if VF == 1".parse().unwrap());
+        let hit = chip8.run_until_break(20).unwrap().unwrap();
+        assert_eq!(hit, Break::Breakpoint { id: collision, pc: 0x206 });
+        assert_eq!(chip8.registers()[0xF], 1);
+    }
+
+    #[test]
+    fn watchpoints_catch_reads_and_writes() {
+        let mut chip8 = machine();
+        let bcd = chip8.add_watchpoint(Watchpoint { start: 0x301, len: 2, access: Access::Write });
+        // the first frame ends at the draw, the BCD runs on the second one
+        let hit = chip8.run_until_break(2).unwrap();
+        assert_eq!(hit, Some(Break::Watchpoint { id: bcd, pc: 0x208, address: 0x301, access: Access::Write }));
+        // the write already happened
+        assert_eq!(chip8.program_counter(), 0x20A);
+        assert_eq!(chip8.memory()[0x302], 1);
+        chip8.clear_breakpoints();
+
+        // the glyph of 2 is read by the draw on the second frame
+        let glyph = 0x50 + 2 * 5;
+        let font = chip8.add_watchpoint(Watchpoint { start: glyph, len: 1, access: Access::ReadWrite });
+        let hit = chip8.run_until_break(10).unwrap();
+        assert_eq!(hit, Some(Break::Watchpoint { id: font, pc: 0x204, address: glyph, access: Access::Read }));
+        assert_eq!(chip8.registers()[1], 2);
+    }
+
+    #[test]
+    fn step_over_and_out() {
+        // V0 counts the calls of a routine that calls itself until V0 == 3
+        let mut chip8 = Chip8::new();
+        chip8
+            .reset_and_load_bytes(&[
+                0x22, 0x08, // 200: call 0x208
+                0x71, 0x01, // 202: V1 += 1
+                0x12, 0x04, // 204: jump 0x204
+                0x00, 0x00, // 206
+                0x70, 0x01, // 208: V0 += 1
+                0x30, 0x03, // 20A: skip if V0 == 3
+                0x22, 0x08, // 20C: call 0x208
+                0x00, 0xEE, // 20E: return
+            ])
+            .unwrap();
+        assert!(!chip8.step_out());
+
+        chip8.run_to(0x20C, None);
+        assert_eq!(chip8.run_until_break(10).unwrap(), Some(Break::Target { pc: 0x20C }));
+        assert_eq!((chip8.stack_pointer(), chip8.run_to_target()), (1, None));
+
+        // the recursive calls come back to 0x20E first, one call deeper
+        assert!(chip8.step_over());
+        assert_eq!(chip8.run_to_target(), Some(0x20E));
+        assert_eq!(chip8.run_until_break(10).unwrap(), Some(Break::Target { pc: 0x20E }));
+        assert_eq!((chip8.stack_pointer(), chip8.registers()[0]), (1, 3));
+
+        assert!(!chip8.step_over());
+        assert!(chip8.step_out());
+        assert_eq!(chip8.run_until_break(10).unwrap(), Some(Break::Target { pc: 0x202 }));
+        assert_eq!(chip8.stack_pointer(), 0);
+
+        // a breakpoint on the way cancels the target
+        let mut chip8 = machine();
+        let id = chip8.add_breakpoint(Breakpoint::Pc(0x206));
+        chip8.run_to(0x20A, None);
+        assert_eq!(chip8.run_until_break(10).unwrap(), Some(Break::Breakpoint { id, pc: 0x206 }));
+        assert_eq!(chip8.run_to_target(), None);
+        chip8.run_to(0x20A, None);
+        chip8.cancel_run_to();
+        chip8.remove_breakpoint(id);
+        assert_eq!(chip8.run_until_break(2).unwrap(), None);
+    }
+
+    #[test]
+    fn broken_frames_are_finished_later() {
+        // same number of instructions and timer ticks with and without stopping
+        let mut stopped = machine();
+        let mut plain = machine();
+        stopped.add_breakpoint(Breakpoint::Pc(0x20A));
+        for chip8 in [&mut stopped, &mut plain] {
+            chip8.memory[0x20A] = 0xF1;
+            chip8.memory[0x20B] = 0x15;
+        }
+
+        for _ in 0..5 {
+            plain.run_frame().unwrap();
+            while let StepOutcome::Break(_) = stopped.run_frame().unwrap() {}
+        }
+        assert_eq!(stopped.save_state(), plain.save_state());
+    }
+}
@@ -0,0 +1,285 @@
+//! Rewind: a ring buffer holding the machine state at the start of each recent frame.
+//!
+//! The states are `save_state` blobs. Every `KEYFRAME_INTERVAL` frames one is kept whole, the
+//! frames in between only keep the byte runs that differ from that keyframe, so a second of
+//! emulation costs little more than a single full state. Once the buffer grows past its byte
+//! budget the oldest frames are dropped.
+
+use std::collections::VecDeque;
+
+use super::Chip8;
+
+/// Bytes kept for rewinding unless `set_rewind_budget` is called, minutes of CHIP-8 frames
+pub const DEFAULT_REWIND_BUDGET: usize = 4 << 20;
+
+/// Frames between two full states
+const KEYFRAME_INTERVAL: usize = 60;
+
+enum Snapshot {
+    Keyframe(Vec<u8>),
+    /// (unchanged bytes to skip, changed run length, changed bytes) triples against the last keyframe
+    Delta(Vec<u8>),
+}
+
+impl Snapshot {
+    fn bytes(&self) -> usize {
+        match self {
+            Snapshot::Keyframe(data) | Snapshot::Delta(data) => data.len(),
+        }
+    }
+}
+
+pub(crate) struct RewindBuffer {
+    budget: usize,
+    frames: VecDeque<Snapshot>,
+    // sum of the snapshot sizes
+    bytes: usize,
+}
+
+impl RewindBuffer {
+    pub(crate) fn new(budget: usize) -> Self {
+        RewindBuffer { budget, frames: VecDeque::new(), bytes: 0 }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.frames.clear();
+        self.bytes = 0;
+    }
+
+    fn keyframe(&self) -> Option<&[u8]> {
+        self.frames.iter().rev().take(KEYFRAME_INTERVAL).find_map(|snapshot| match snapshot {
+            Snapshot::Keyframe(data) => Some(data.as_slice()),
+            Snapshot::Delta(_) => None,
+        })
+    }
+
+    fn push(&mut self, state: Vec<u8>) {
+        if self.budget == 0 {
+            return;
+        }
+
+        // a new keyframe is also needed when the state size changed, e.g. after switching variant
+        let snapshot = match self.keyframe() {
+            Some(keyframe) if keyframe.len() == state.len() => {
+                let mut delta = encode_delta(keyframe, &state);
+                delta.shrink_to_fit();
+                Snapshot::Delta(delta)
+            }
+            _ => Snapshot::Keyframe(state),
+        };
+        self.bytes += snapshot.bytes();
+        self.frames.push_back(snapshot);
+        self.fit_budget();
+    }
+
+    /// Drop the oldest frames until the buffer fits its budget
+    fn fit_budget(&mut self) {
+        while self.bytes > self.budget {
+            self.pop_front();
+            // the deltas that relied on the dropped keyframe are useless without it
+            while let Some(Snapshot::Delta(_)) = self.frames.front() {
+                self.pop_front();
+            }
+        }
+    }
+
+    fn pop_front(&mut self) {
+        if let Some(snapshot) = self.frames.pop_front() {
+            self.bytes -= snapshot.bytes();
+        }
+    }
+
+    /// Remove the most recent state and return it whole
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        let snapshot = self.frames.pop_back()?;
+        self.bytes -= snapshot.bytes();
+        match snapshot {
+            Snapshot::Keyframe(state) => Some(state),
+            Snapshot::Delta(delta) => {
+                let keyframe = self.keyframe().expect("deltas always follow a keyframe");
+                Some(decode_delta(keyframe, &delta))
+            }
+        }
+    }
+}
+
+impl Chip8 {
+    /// Bytes of memory the rewind buffer may use, 0 turns rewinding off.
+    /// Frames are dropped right away if the buffer is already bigger.
+    pub fn set_rewind_budget(&mut self, bytes: usize) {
+        self.rewind.budget = bytes;
+        self.rewind.fit_budget();
+    }
+
+    /// Number of frames `rewind` can currently go back
+    pub fn rewind_len(&self) -> usize {
+        self.rewind.frames.len()
+    }
+
+    /// Record the state at the start of a frame, called by `run_frame`
+    pub(crate) fn record_rewind(&mut self) {
+        if self.rewind.budget == 0 || self.fault.is_some() || self.exited {
+            return;
+        }
+        let state = self.save_state();
+        self.rewind.push(state);
+    }
+
+    /// Go back to the state at the start of the frame `frames` frames ago. A fault is cleared, so
+    /// this also brings back a machine that halted. Returns how many frames were rewound, fewer
+    /// than asked when the buffer doesn't reach that far.
+    pub fn rewind(&mut self, frames: usize) -> usize {
+        let mut state = None;
+        let mut rewound = 0;
+        while rewound < frames {
+            match self.rewind.pop() {
+                Some(popped) => state = Some(popped),
+                None => break,
+            }
+            rewound += 1;
+        }
+
+        if let Some(state) = state {
+            self.load_state(&state).expect("rewind buffer holds states made by save_state");
+        }
+        rewound
+    }
+}
+
+fn encode_delta(keyframe: &[u8], state: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut i = 0;
+    while i < state.len() {
+        let unchanged = i;
+        while i < state.len() && state[i] == keyframe[i] {
+            i += 1;
+        }
+        if i == state.len() {
+            break;
+        }
+        let changed = i;
+        while i < state.len() && state[i] != keyframe[i] {
+            i += 1;
+        }
+        put_varint(&mut delta, changed - unchanged);
+        put_varint(&mut delta, i - changed);
+        delta.extend_from_slice(&state[changed..i]);
+    }
+    delta
+}
+
+fn decode_delta(keyframe: &[u8], mut delta: &[u8]) -> Vec<u8> {
+    let mut state = keyframe.to_vec();
+    let mut i = 0;
+    while !delta.is_empty() {
+        i += take_varint(&mut delta);
+        let len = take_varint(&mut delta);
+        state[i..i + len].copy_from_slice(&delta[..len]);
+        delta = &delta[len..];
+        i += len;
+    }
+    state
+}
+
+// LEB128, 7 bits per byte starting from the least significant ones
+fn put_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn take_varint(data: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[0];
+        *data = &data[1..];
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::Chip8;
+
+    // counts frames in V0 and draws the count, the display wait quirk ends each frame at the draw
+    const COUNTER: [u8; 10] = [
+        0x70, 0x01, // 200: V0 += 1
+        0x00, 0xE0, // 202: CLS
+        0xF0, 0x29, // 204: I = font(V0)
+        0xD1, 0x15, // 206: draw
+        0x12, 0x00, // 208: jump 0x200
+    ];
+
+    fn counter() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.reset_and_load_bytes(&COUNTER).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn rewind_restores_earlier_frames() {
+        let mut chip8 = counter();
+        let mut states = Vec::new();
+        for _ in 0..150 {
+            states.push(chip8.save_state());
+            chip8.run_frame().unwrap();
+        }
+        assert_eq!(chip8.rewind_len(), 150);
+
+        // 30 frames back is the state at the start of frame 120
+        assert_eq!(chip8.rewind(30), 30);
+        assert_eq!(chip8.save_state(), states[120]);
+
+        // rewinding again keeps going back, across keyframes
+        assert_eq!(chip8.rewind(100), 100);
+        assert_eq!(chip8.save_state(), states[20]);
+
+        assert_eq!(chip8.rewind(1000), 20);
+        assert_eq!(chip8.save_state(), states[0]);
+        assert_eq!(chip8.rewind(1), 0);
+    }
+
+    #[test]
+    fn rewind_stays_within_budget() {
+        let mut chip8 = counter();
+        let keyframe = chip8.save_state().len();
+        chip8.set_rewind_budget(keyframe * 2);
+        let mut states = Vec::new();
+        for _ in 0..500 {
+            states.push(chip8.save_state());
+            chip8.run_frame().unwrap();
+        }
+
+        assert!(chip8.rewind.bytes <= keyframe * 2);
+        let frames = chip8.rewind_len();
+        assert!(frames > 0 && frames < 500);
+        assert_eq!(chip8.rewind(frames), frames);
+        assert_eq!(chip8.save_state(), states[500 - frames]);
+
+        chip8.set_rewind_budget(0);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.rewind_len(), 0);
+    }
+
+    #[test]
+    fn rewind_clears_faults() {
+        let mut chip8 = counter();
+        for _ in 0..5 {
+            chip8.run_frame().unwrap();
+        }
+        // the next frame starts at 0x208, replace the jump with an invalid opcode
+        chip8.memory[0x208] = 0x00;
+        chip8.memory[0x209] = 0x01;
+        assert!(chip8.run_frame().is_err());
+
+        assert_eq!(chip8.rewind(1), 1);
+        assert!(chip8.fault().is_none());
+    }
+}
@@ -0,0 +1,388 @@
+//! Save states: the whole machine serialized into a versioned binary blob.
+//!
+//! Layout (big-endian, like the CHIP-8 itself):
+//!
+//! * magic `C8ST` and a version byte
+//! * variant and quirks
+//! * registers, I, PC, stack, SP and both timers
+//! * keypad, the queue of recent presses and the key FX0A waits on
+//! * resolution, the video buffer (one byte per pixel) and the drawing flags
+//! * RNG state and the last executed opcode
+//! * SUPER-CHIP RPL flags and the XO-CHIP plane, audio pattern and pitch
+//! * memory, prefixed by its length
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use super::{Chip8, KeyWait, Variant, VIDEO_SIZE};
+use crate::quirks::Quirks;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 2;
+
+/// Errors returned by `Chip8::load_state`, the machine is left untouched when one is returned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data doesn't start with the save state magic bytes
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// The data ended before the whole state was read
+    Truncated,
+    /// A field holds a value the machine can't be in, e.g. an unknown variant
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic =>
+                write!(f, "Not a save state"),
+            StateError::UnsupportedVersion(version) =>
+                write!(f, "Unsupported save state version: {}", version),
+            StateError::Truncated =>
+                write!(f, "Save state is truncated"),
+            StateError::Invalid(field) =>
+                write!(f, "Invalid save state field: {}", field),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl Chip8 {
+    /// Serialize the whole machine, restore it later with `load_state`
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(MAGIC.len() + VIDEO_SIZE + self.memory.len() + 256);
+
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+
+        out.push(variant_to_byte(self.variant));
+        out.push(quirks_to_byte(self.quirks));
+
+        out.extend_from_slice(&self.registers);
+        put_u16(&mut out, self.index_register);
+        put_u16(&mut out, self.program_counter);
+        for address in self.stack.iter() {
+            put_u16(&mut out, *address);
+        }
+        out.push(self.stack_pointer);
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+
+        out.extend_from_slice(&self.keypad);
+        // keep the most recent presses if the queue grew past what a length byte can hold
+        let skipped = self.recent_presses.len().saturating_sub(u8::MAX as usize);
+        out.push((self.recent_presses.len() - skipped) as u8);
+        out.extend(self.recent_presses.iter().skip(skipped));
+        let wait = self.key_wait.map_or(0, |wait| wait.x | wait.key.unwrap_or(0) << 4);
+        out.push(wait);
+
+        let key_wait = self.key_wait.map_or(0, |wait| 0x8 | (wait.key.is_some() as u8) << 4);
+        out.push(self.hires as u8 | (self.exited as u8) << 1 | (self.vblank_wait as u8) << 2 | key_wait);
+        out.extend(self.video.iter().map(|pixel| (*pixel & 0x3) as u8));
+
+        put_u32(&mut out, self.rng_state);
+        put_u16(&mut out, self.op_code);
+        put_u16(&mut out, self.last_opcode);
+        put_u16(&mut out, self.last_pc);
+
+        out.extend_from_slice(&self.rpl_flags);
+        out.push(self.plane);
+        out.extend_from_slice(&self.audio_pattern);
+        out.push(self.pitch);
+
+        put_u32(&mut out, self.memory.len() as u32);
+        out.extend_from_slice(&self.memory);
+
+        out
+    }
+
+    /// Restore a state produced by `save_state`. The tracer, the instructions per frame and the
+    /// breakpoints are host settings and are kept, a fault that halted the machine is cleared.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = Reader { data };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        // read everything before touching the machine, so a bad state leaves it untouched
+        let variant = variant_from_byte(reader.u8()?)?;
+        let quirks = quirks_from_byte(reader.u8()?);
+
+        let registers = reader.array::<16>()?;
+        let index_register = reader.u16()?;
+        let program_counter = reader.u16()?;
+        let mut stack = [0u16; 16];
+        for address in stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        let stack_pointer = reader.u8()?;
+        if stack_pointer as usize > stack.len() {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+
+        let keypad = reader.array::<16>()?;
+        if keypad.iter().any(|key| *key > 1) {
+            return Err(StateError::Invalid("keypad"));
+        }
+        let presses = reader.u8()? as usize;
+        let recent_presses: VecDeque<u8> = reader.take(presses)?.iter().copied().collect();
+        if recent_presses.iter().any(|key| *key > 0xF) {
+            return Err(StateError::Invalid("key press"));
+        }
+        let wait = reader.u8()?;
+
+        let flags = reader.u8()?;
+        let key_wait = (flags & 0x8 != 0).then(|| KeyWait {
+            x: wait & 0xF,
+            key: (flags & 0x10 != 0).then_some(wait >> 4),
+        });
+        let video = reader.take(VIDEO_SIZE)?;
+
+        let rng_state = reader.u32()?;
+        let op_code = reader.u16()?;
+        let last_opcode = reader.u16()?;
+        let last_pc = reader.u16()?;
+
+        let rpl_flags = reader.array::<16>()?;
+        let plane = reader.u8()?;
+        let audio_pattern = reader.array::<16>()?;
+        let pitch = reader.u8()?;
+
+        let memory_size = reader.u32()? as usize;
+        if memory_size != variant.memory_size() {
+            return Err(StateError::Invalid("memory size"));
+        }
+        let memory = reader.take(memory_size)?;
+
+        self.set_variant(variant);
+        self.quirks = quirks;
+
+        self.registers = registers;
+        self.index_register = index_register;
+        self.program_counter = program_counter;
+        self.stack = stack;
+        self.stack_pointer = stack_pointer;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+
+        self.keypad = keypad;
+        self.recent_presses = recent_presses;
+
+        self.hires = flags & 0x1 != 0;
+        self.exited = flags & 0x2 != 0;
+        self.vblank_wait = flags & 0x4 != 0;
+        self.key_wait = key_wait;
+        for (pixel, saved) in self.video.iter_mut().zip(video) {
+            *pixel = *saved as u32;
+        }
+
+        self.rng_state = rng_state;
+        self.op_code = op_code;
+        self.last_opcode = last_opcode;
+        self.last_pc = last_pc;
+        self.fault = None;
+        // the sound queued before the load belongs to another timeline
+        self.audio.clear();
+        // states are taken between frames, and a stop before an instruction doesn't carry over
+        self.frame_steps = 0;
+        self.hooks.interrupt();
+
+        self.rpl_flags = rpl_flags;
+        self.plane = plane;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+
+        self.memory.copy_from_slice(memory);
+
+        Ok(())
+    }
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn variant_to_byte(variant: Variant) -> u8 {
+    match variant {
+        Variant::Chip8 => 0,
+        Variant::SuperChip => 1,
+        Variant::XoChip => 2,
+    }
+}
+
+fn variant_from_byte(byte: u8) -> Result<Variant, StateError> {
+    match byte {
+        0 => Ok(Variant::Chip8),
+        1 => Ok(Variant::SuperChip),
+        2 => Ok(Variant::XoChip),
+        _ => Err(StateError::Invalid("variant")),
+    }
+}
+
+fn quirks_to_byte(quirks: Quirks) -> u8 {
+    quirks.shift as u8
+        | (quirks.load_store as u8) << 1
+        | (quirks.jump as u8) << 2
+        | (quirks.vf_reset as u8) << 3
+        | (quirks.clipping as u8) << 4
+        | (quirks.display_wait as u8) << 5
+        | (quirks.key_press as u8) << 6
+}
+
+fn quirks_from_byte(byte: u8) -> Quirks {
+    Quirks {
+        shift: byte & 0x01 != 0,
+        load_store: byte & 0x02 != 0,
+        jump: byte & 0x04 != 0,
+        vf_reset: byte & 0x08 != 0,
+        clipping: byte & 0x10 != 0,
+        display_wait: byte & 0x20 != 0,
+        key_press: byte & 0x40 != 0,
+    }
+}
+
+/// Reads the state front to back
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_be_bytes(self.array::<2>()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_be_bytes(self.array::<4>()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StateError;
+    use crate::chip8::{Chip8, Variant};
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn round_trip() {
+        // hires, V0 = 5, I = 0x300, draw, call 0x210
+        let mut rom = vec![0x00, 0xFF, 0x60, 5, 0xA3, 0x00, 0xD0, 0x15, 0x22, 0x10];
+        rom.resize(0x10, 0);
+        rom.extend([0xC1, 0xFF, 0x12, 0x12]);
+
+        let mut chip8 = Chip8::with_variant(Variant::SuperChip);
+        chip8.reset_and_load_bytes(&rom).unwrap();
+        chip8.key_down(0xA);
+        for _ in 0..3 {
+            chip8.run_frame().unwrap();
+        }
+        let state = chip8.save_state();
+
+        let mut restored = Chip8::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.variant(), Variant::SuperChip);
+        assert_eq!(restored.quirks(), Quirks::SUPER_CHIP);
+        assert_eq!(restored.save_state(), state);
+
+        // both machines carry on the same way, random numbers included
+        for _ in 0..10 {
+            chip8.run_frame().unwrap();
+            restored.run_frame().unwrap();
+        }
+        assert_eq!(restored.save_state(), chip8.save_state());
+        assert_eq!(restored.take_recent_press(), Some(0xA));
+    }
+
+    #[test]
+    fn key_wait_is_kept() {
+        // V3 = wait for a key, then loop
+        let mut chip8 = Chip8::new();
+        chip8.reset_and_load_bytes(&[0xF3, 0x0A, 0x12, 0x02]).unwrap();
+        chip8.run_frame().unwrap();
+        chip8.key_down(4);
+        chip8.run_frame().unwrap();
+
+        let mut restored = Chip8::new();
+        restored.load_state(&chip8.save_state()).unwrap();
+        assert_eq!(restored.save_state(), chip8.save_state());
+        // the key was taken before the state, so releasing it is all that's left
+        restored.key_up(4);
+        restored.run_frame().unwrap();
+        assert_eq!((restored.registers()[3], restored.program_counter()), (4, 0x202));
+    }
+
+    #[test]
+    fn bad_states_are_rejected() {
+        let mut chip8 = Chip8::new();
+        let state = chip8.save_state();
+
+        assert_eq!(chip8.load_state(b"nope"), Err(StateError::BadMagic));
+        assert_eq!(chip8.load_state(&state[..state.len() - 1]), Err(StateError::Truncated));
+
+        let mut newer = state.clone();
+        newer[4] = 99;
+        assert_eq!(chip8.load_state(&newer), Err(StateError::UnsupportedVersion(99)));
+
+        let mut bad_variant = state.clone();
+        bad_variant[5] = 7;
+        assert_eq!(chip8.load_state(&bad_variant), Err(StateError::Invalid("variant")));
+
+        // the keypad follows the registers, I, PC, the stack, SP and both timers
+        let keypad = 7 + 16 + 2 + 2 + 32 + 3;
+        let mut bad_keypad = state.clone();
+        bad_keypad[keypad + 3] = 2;
+        assert_eq!(chip8.load_state(&bad_keypad), Err(StateError::Invalid("keypad")));
+
+        // a queued press of key 0x20 with FX0A waiting would index past the keypad
+        let mut bad_press = state[..keypad + 16].to_vec();
+        bad_press.extend([1, 0x20]);
+        bad_press.extend(&state[keypad + 17..]);
+        let flags = keypad + 16 + 3;
+        bad_press[flags] |= 0x8;
+        assert_eq!(chip8.load_state(&bad_press), Err(StateError::Invalid("key press")));
+    }
+
+    #[test]
+    fn loading_drops_queued_sound() {
+        let mut chip8 = Chip8::new();
+        let state = chip8.save_state();
+        chip8.sound_timer = 10;
+        chip8.tick_timers();
+        chip8.load_state(&state).unwrap();
+
+        let mut buffer = [0.0; 1470];
+        chip8.fill_audio(&mut buffer, 44100);
+        assert!(buffer.iter().all(|sample| *sample == 0.0));
+    }
+}
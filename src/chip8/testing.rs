@@ -0,0 +1,532 @@
+//! Test support: a machine with its registers, memory and keys set up for one instruction,
+//! which is assembled from Cowgod syntax, run, and then looked at. `Shared` catches what the
+//! machine writes to its tracer or an audio output.
+//!
+//! ```ignore
+//! let chip8 = Setup::new().v(0, 0xFF).v(1, 1).run("ADD V0, V1");
+//! assert_eq!((chip8.registers[0], chip8.registers[0xF]), (0, 1));
+//! ```
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use super::{Chip8, Chip8Error, StepOutcome, Variant};
+use crate::asm;
+use crate::quirks::Quirks;
+
+/// A writer the test can still read after handing it over, e.g. to a `Tracer`
+#[derive(Clone, Default)]
+pub(crate) struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Shared {
+    /// Everything written so far
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.bytes()).unwrap().lines().map(str::to_string).collect()
+    }
+}
+
+/// A machine about to run one instruction at 0x200. CHIP-8 with the COSMAC VIP quirks unless
+/// told otherwise, everything else starts out zero
+pub(crate) struct Setup {
+    chip8: Chip8,
+}
+
+impl Setup {
+    pub fn new() -> Setup {
+        Setup { chip8: Chip8::new() }
+    }
+
+    /// Switch the instruction set, and the quirks to the ones matching it
+    pub fn variant(mut self, variant: Variant) -> Setup {
+        self.chip8.set_variant(variant);
+        self.chip8.set_quirks(variant.default_quirks());
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Setup {
+        self.chip8.set_quirks(quirks);
+        self
+    }
+
+    pub fn v(mut self, x: usize, value: u8) -> Setup {
+        self.chip8.registers[x] = value;
+        self
+    }
+
+    pub fn i(mut self, address: u16) -> Setup {
+        self.chip8.index_register = address;
+        self
+    }
+
+    pub fn memory(mut self, address: usize, bytes: &[u8]) -> Setup {
+        self.chip8.memory[address..address + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    /// Hold `key` down
+    pub fn key(mut self, key: u8) -> Setup {
+        self.chip8.key_down(key);
+        self
+    }
+
+    /// Push a return address, as if a CALL had run
+    pub fn call(mut self, return_address: u16) -> Setup {
+        self.chip8.stack[self.chip8.stack_pointer as usize] = return_address;
+        self.chip8.stack_pointer += 1;
+        self
+    }
+
+    pub fn timers(mut self, delay: u8, sound: u8) -> Setup {
+        self.chip8.delay_timer = delay;
+        self.chip8.sound_timer = sound;
+        self
+    }
+
+    /// Light the pixel at `x`, `y` on the first bitplane
+    pub fn pixel(mut self, x: usize, y: usize) -> Setup {
+        let width = self.chip8.width();
+        self.chip8.video[y * width + x] |= 1;
+        self
+    }
+
+    /// Anything else, e.g. the XO-CHIP bitplanes or the SUPER-CHIP resolution
+    pub fn with(mut self, set_up: impl FnOnce(&mut Chip8)) -> Setup {
+        set_up(&mut self.chip8);
+        self
+    }
+
+    /// Assemble `source` at 0x200 and run its first instruction
+    pub fn step(mut self, source: &str) -> (Chip8, Result<StepOutcome, Chip8Error>) {
+        let rom = asm::assemble(source).unwrap_or_else(|e| panic!("{}: {}", source, e));
+        self.chip8.memory[0x200..0x200 + rom.len()].copy_from_slice(&rom);
+        let outcome = self.chip8.step();
+        (self.chip8, outcome)
+    }
+
+    /// Run the instruction, which has to succeed
+    pub fn run(self, source: &str) -> Chip8 {
+        let (chip8, outcome) = self.step(source);
+        match outcome {
+            Ok(StepOutcome::Executed { .. }) => chip8,
+            outcome => panic!("{}: {:?}", source, outcome),
+        }
+    }
+
+    /// Run the instruction, which has to fail
+    pub fn fault(self, source: &str) -> Chip8Error {
+        match self.step(source).1 {
+            Err(error) => error,
+            outcome => panic!("{}: expected a fault, got {:?}", source, outcome),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Setup;
+    use crate::chip8::{Chip8, Chip8Error, StepOutcome, Variant, BIG_FONTSET_START_ADDRESS, FONTSET_START_ADDRESS};
+    use crate::quirks::Quirks;
+
+    fn v(chip8: &Chip8) -> [u8; 16] {
+        chip8.registers
+    }
+
+    fn lit(chip8: &Chip8) -> usize {
+        chip8.export_video().iter().filter(|pixel| **pixel != 0).count()
+    }
+
+    #[test]
+    fn op_00e0_cls() {
+        let chip8 = Setup::new().pixel(0, 0).pixel(63, 31).run("CLS");
+        assert_eq!(lit(&chip8), 0);
+
+        // XO-CHIP only clears the selected planes
+        let chip8 = Setup::new()
+            .variant(Variant::XoChip)
+            .with(|chip8| {
+                chip8.video[0] = 3;
+                chip8.plane = 2;
+            })
+            .run("CLS");
+        assert_eq!(chip8.video[0], 1);
+    }
+
+    #[test]
+    fn op_00ee_ret() {
+        let chip8 = Setup::new().call(0x204).call(0x346).run("RET");
+        assert_eq!((chip8.program_counter, chip8.stack()), (0x346, &[0x204][..]));
+
+        assert_eq!(Setup::new().fault("RET"), Chip8Error::StackUnderflow { pc: 0x200, opcode: 0x00EE });
+    }
+
+    #[test]
+    fn op_1nnn_jp() {
+        assert_eq!(Setup::new().run("JP 0x345").program_counter, 0x345);
+        // jumping to itself is how most ROMs stop
+        assert_eq!(Setup::new().run("JP 0x200").program_counter, 0x200);
+    }
+
+    #[test]
+    fn op_2nnn_call() {
+        let chip8 = Setup::new().run("CALL 0x400");
+        assert_eq!((chip8.program_counter, chip8.stack()), (0x400, &[0x202][..]));
+
+        let full = (0..16).fold(Setup::new(), |setup, _| setup.call(0x202));
+        assert_eq!(full.fault("CALL 0x400"), Chip8Error::StackOverflow { pc: 0x200, opcode: 0x2400 });
+    }
+
+    #[test]
+    fn op_3xkk_4xkk_skips() {
+        assert_eq!(Setup::new().v(1, 5).run("SE V1, 5").program_counter, 0x204);
+        assert_eq!(Setup::new().v(1, 6).run("SE V1, 5").program_counter, 0x202);
+        assert_eq!(Setup::new().v(1, 5).run("SNE V1, 5").program_counter, 0x202);
+        assert_eq!(Setup::new().v(1, 6).run("SNE V1, 5").program_counter, 0x204);
+
+        // XO-CHIP skips the 4 byte F000 NNNN as a whole, CHIP-8 only its first half
+        let long_load = "SE V0, 0\nLD I, LONG 0x1234";
+        assert_eq!(Setup::new().variant(Variant::XoChip).run(long_load).program_counter, 0x206);
+        assert_eq!(Setup::new().memory(0x202, &[0xF0, 0x00]).run("SE V0, 0").program_counter, 0x204);
+    }
+
+    #[test]
+    fn op_5xy0_9xy0_skips() {
+        assert_eq!(Setup::new().v(1, 7).v(2, 7).run("SE V1, V2").program_counter, 0x204);
+        assert_eq!(Setup::new().v(1, 7).v(2, 8).run("SE V1, V2").program_counter, 0x202);
+        assert_eq!(Setup::new().v(1, 7).v(2, 7).run("SNE V1, V2").program_counter, 0x202);
+        assert_eq!(Setup::new().v(1, 7).v(2, 8).run("SNE V1, V2").program_counter, 0x204);
+    }
+
+    #[test]
+    fn op_5xy2_5xy3_ranges() {
+        let xo = || Setup::new().variant(Variant::XoChip).v(1, 1).v(2, 2).v(3, 3).i(0x300);
+        let chip8 = xo().run("SAVE V1-V3");
+        assert_eq!((&chip8.memory[0x300..0x303], chip8.index_register), (&[1, 2, 3][..], 0x300));
+        assert_eq!(&xo().run("SAVE V3-V1").memory[0x300..0x303], [3, 2, 1]);
+        assert_eq!(&xo().run("SAVE V2-V2").memory[0x300..0x302], [2, 0]);
+
+        let chip8 = xo().memory(0x300, &[7, 8, 9]).run("LOAD V6-V4");
+        assert_eq!((v(&chip8)[4..7].to_vec(), chip8.index_register), (vec![9, 8, 7], 0x300));
+
+        let end = Setup::new().variant(Variant::XoChip).i(0xFFFF);
+        assert_eq!(end.fault("SAVE V0-V1"), Chip8Error::MemOob { pc: 0x200, opcode: 0x5012, address: 0x10000 });
+        // plain CHIP-8 doesn't have them
+        assert_eq!(Setup::new().fault("SAVE V1-V3"), Chip8Error::UnknownOpcode { pc: 0x200, opcode: 0x5132 });
+    }
+
+    #[test]
+    fn op_6xkk_7xkk_bytes() {
+        assert_eq!(v(&Setup::new().v(3, 9).run("LD V3, 0xAB"))[3], 0xAB);
+
+        // ADD wraps and leaves VF alone
+        let chip8 = Setup::new().v(0, 2).v(0xF, 5).run("ADD V0, 0xFF");
+        assert_eq!((v(&chip8)[0], v(&chip8)[0xF]), (1, 5));
+        let chip8 = Setup::new().v(0xF, 0xFE).run("ADD VF, 3");
+        assert_eq!(v(&chip8)[0xF], 1);
+    }
+
+    #[test]
+    fn op_8xy0_to_8xy3_logic() {
+        assert_eq!(v(&Setup::new().v(2, 0x42).run("LD V1, V2"))[1], 0x42);
+
+        let setup = || Setup::new().v(1, 0b1100).v(2, 0b1010).v(0xF, 7);
+        for (source, result) in [("OR V1, V2", 0b1110), ("AND V1, V2", 0b1000), ("XOR V1, V2", 0b0110)] {
+            // the VIP resets VF, later interpreters leave it alone
+            let chip8 = setup().run(source);
+            assert_eq!((v(&chip8)[1], v(&chip8)[0xF]), (result, 0), "{}", source);
+            let chip8 = setup().quirks(Quirks::CHIP_48).run(source);
+            assert_eq!((v(&chip8)[1], v(&chip8)[0xF]), (result, 7), "{}", source);
+        }
+    }
+
+    #[test]
+    fn op_8xy4_add_carry() {
+        let add = |x: u8, y: u8| {
+            let chip8 = Setup::new().v(1, x).v(2, y).v(0xF, 9).run("ADD V1, V2");
+            (v(&chip8)[1], v(&chip8)[0xF])
+        };
+        assert_eq!(add(1, 2), (3, 0));
+        assert_eq!(add(0x7F, 0x80), (0xFF, 0));
+        assert_eq!(add(0xFF, 1), (0, 1));
+        assert_eq!(add(0xFF, 0xFF), (0xFE, 1));
+
+        // adding a register to itself
+        assert_eq!(v(&Setup::new().v(1, 0x81).run("ADD V1, V1"))[..2], [0, 2]);
+        // the flag wins over the sum in VF
+        assert_eq!(v(&Setup::new().v(0xF, 0x80).v(1, 0x80).run("ADD VF, V1"))[0xF], 1);
+        assert_eq!(v(&Setup::new().v(0xF, 2).v(1, 1).run("ADD VF, V1"))[0xF], 0);
+    }
+
+    #[test]
+    fn op_8xy5_8xy7_sub_borrow() {
+        let sub = |x: u8, y: u8| {
+            let chip8 = Setup::new().v(1, x).v(2, y).v(0xF, 9).run("SUB V1, V2");
+            (v(&chip8)[1], v(&chip8)[0xF])
+        };
+        assert_eq!(sub(5, 3), (2, 1));
+        assert_eq!(sub(3, 5), (0xFE, 0));
+        assert_eq!(sub(5, 5), (0, 1));
+        assert_eq!(sub(0, 0xFF), (1, 0));
+
+        let subn = |x: u8, y: u8| {
+            let chip8 = Setup::new().v(1, x).v(2, y).v(0xF, 9).run("SUBN V1, V2");
+            (v(&chip8)[1], v(&chip8)[0xF])
+        };
+        assert_eq!(subn(3, 5), (2, 1));
+        assert_eq!(subn(5, 3), (0xFE, 0));
+        assert_eq!(subn(5, 5), (0, 1));
+
+        // the flag wins over the difference in VF
+        assert_eq!(v(&Setup::new().v(0xF, 5).v(1, 3).run("SUB VF, V1"))[0xF], 1);
+        assert_eq!(v(&Setup::new().v(0xF, 3).v(1, 5).run("SUBN VF, V1"))[0xF], 1);
+        assert_eq!(v(&Setup::new().v(0xF, 5).v(1, 3).run("SUBN VF, V1"))[0xF], 0);
+    }
+
+    #[test]
+    fn op_8xy6_8xye_shifts() {
+        // the VIP shifts Vy into Vx, later interpreters shift Vx in place
+        let chip8 = Setup::new().v(0, 0xF0).v(1, 0x05).run("SHR V0, V1");
+        assert_eq!((v(&chip8)[0], v(&chip8)[1], v(&chip8)[0xF]), (0x02, 0x05, 1));
+        let chip8 = Setup::new().quirks(Quirks::CHIP_48).v(0, 0xF0).v(1, 0x05).run("SHR V0, V1");
+        assert_eq!((v(&chip8)[0], v(&chip8)[0xF]), (0x78, 0));
+
+        let chip8 = Setup::new().v(0, 0x01).v(1, 0x81).run("SHL V0, V1");
+        assert_eq!((v(&chip8)[0], v(&chip8)[0xF]), (0x02, 1));
+        let chip8 = Setup::new().quirks(Quirks::CHIP_48).v(0, 0x41).v(1, 0x81).run("SHL V0, V1");
+        assert_eq!((v(&chip8)[0], v(&chip8)[0xF]), (0x82, 0));
+
+        // the shifted out bit wins over the result in VF
+        assert_eq!(v(&Setup::new().v(0xF, 0x03).run("SHR VF, VF"))[0xF], 1);
+        assert_eq!(v(&Setup::new().v(0xF, 0x40).run("SHL VF, VF"))[0xF], 0);
+    }
+
+    #[test]
+    fn op_annn_bnnn_addresses() {
+        assert_eq!(Setup::new().run("LD I, 0xABC").index_register, 0xABC);
+
+        // BNNN adds V0, or Vx of BXNN with the jump quirk
+        let setup = || Setup::new().v(0, 0x10).v(3, 0x20);
+        assert_eq!(setup().run("JP V0, 0x300").program_counter, 0x310);
+        assert_eq!(setup().quirks(Quirks::SUPER_CHIP).run("JP V0, 0x300").program_counter, 0x320);
+        assert_eq!(Setup::new().v(0, 0xFF).run("JP V0, 0xFFF").program_counter, 0x10FE);
+    }
+
+    #[test]
+    fn op_cxkk_rnd() {
+        for _ in 0..8 {
+            assert_eq!(v(&Setup::new().run("RND V0, 0x0F"))[0] & 0xF0, 0);
+            assert_eq!(v(&Setup::new().v(0, 0xFF).run("RND V0, 0"))[0], 0);
+        }
+    }
+
+    #[test]
+    fn op_dxyn_drw() {
+        // the font's 0 at 0,0: 14 pixels, no collision, and VF is cleared
+        let zero = || Setup::new().i(FONTSET_START_ADDRESS as u16).v(0xF, 1);
+        let chip8 = zero().run("DRW V0, V1, 5");
+        assert_eq!((lit(&chip8), v(&chip8)[0xF]), (14, 0));
+        assert!(chip8.vblank_wait);
+
+        // drawing over a lit pixel turns it off and sets VF
+        let chip8 = zero().pixel(0, 0).run("DRW V0, V1, 5");
+        assert_eq!((chip8.video[0], lit(&chip8), v(&chip8)[0xF]), (0, 13, 1));
+        // lit pixels under a sprite's gaps are no collision
+        assert_eq!(v(&zero().pixel(1, 1).run("DRW V0, V1, 5"))[0xF], 0);
+
+        // the start position wraps, the sprite itself is clipped at the edges unless the clipping quirk is off
+        let chip8 = zero().v(0, 64 + 2).v(1, 32 + 1).run("DRW V0, V1, 1");
+        assert_ne!(chip8.video[64 + 2], 0);
+        let clipped = zero().v(0, 62).v(1, 30).run("DRW V0, V1, 5");
+        assert_eq!(lit(&clipped), 3);
+        let wrapped = zero().quirks(Quirks::XO_CHIP).v(0, 62).v(1, 30).run("DRW V0, V1, 5");
+        assert_eq!(lit(&wrapped), 14);
+        assert!(!wrapped.vblank_wait);
+
+        // DXY0 is an empty sprite on CHIP-8 and 16x16 on SUPER-CHIP
+        assert_eq!(lit(&zero().run("DRW V0, V1, 0")), 0);
+        let big = Setup::new().variant(Variant::SuperChip).i(0x300).memory(0x300, &[0xFF; 32]).run("DRW V0, V1, 0");
+        assert_eq!(lit(&big), 256);
+
+        let end = Setup::new().i(0xFFE);
+        assert_eq!(end.fault("DRW V0, V1, 5"), Chip8Error::MemOob { pc: 0x200, opcode: 0xD015, address: 0x1002 });
+    }
+
+    #[test]
+    fn op_ex9e_exa1_keys() {
+        assert_eq!(Setup::new().v(0, 0xA).key(0xA).run("SKP V0").program_counter, 0x204);
+        assert_eq!(Setup::new().v(0, 0xA).key(0xB).run("SKP V0").program_counter, 0x202);
+        assert_eq!(Setup::new().v(0, 0xA).key(0xA).run("SKNP V0").program_counter, 0x202);
+        assert_eq!(Setup::new().v(0, 0xA).run("SKNP V0").program_counter, 0x204);
+        // only the low nibble of Vx picks the key
+        assert_eq!(Setup::new().v(0, 0x1A).key(0xA).run("SKP V0").program_counter, 0x204);
+    }
+
+    #[test]
+    fn op_fx0a_wait_key() {
+        // the CPU blocks on the instruction until a key is pressed and released
+        let (mut chip8, outcome) = Setup::new().v(0, 9).step("LD V0, K");
+        assert_eq!(outcome, Ok(StepOutcome::WaitingKey));
+        chip8.key_down(3);
+        assert_eq!(chip8.step(), Ok(StepOutcome::WaitingKey));
+        // other keys don't matter once one was pressed
+        chip8.key_down(7);
+        chip8.key_up(7);
+        assert_eq!((chip8.step(), chip8.program_counter, v(&chip8)[0]), (Ok(StepOutcome::WaitingKey), 0x200, 9));
+        chip8.key_up(3);
+        assert_eq!(chip8.step(), Ok(StepOutcome::Executed { pc: 0x200, opcode: 0xF00A }));
+        assert_eq!((chip8.program_counter, v(&chip8)[0]), (0x202, 3));
+
+        // a key held before counts once it is released, a press before doesn't count at all
+        let (mut chip8, _) = Setup::new().key(5).key(6).with(|chip8| chip8.key_up(6)).step("LD V1, K");
+        assert_eq!(chip8.step(), Ok(StepOutcome::WaitingKey));
+        chip8.key_up(5);
+        assert_eq!(chip8.step(), Ok(StepOutcome::Executed { pc: 0x200, opcode: 0xF10A }));
+        assert_eq!(v(&chip8)[1], 5);
+
+        // a tap between two steps is a press and a release
+        let (mut chip8, _) = Setup::new().step("LD V0, K");
+        chip8.key_down(0xC);
+        chip8.key_up(0xC);
+        assert_eq!(chip8.step(), Ok(StepOutcome::Executed { pc: 0x200, opcode: 0xF00A }));
+        assert_eq!(v(&chip8)[0], 0xC);
+
+        // with the quirk the press finishes it, and a key held before doesn't
+        let quirks = Quirks { key_press: true, ..Quirks::COSMAC_VIP };
+        let (mut chip8, _) = Setup::new().quirks(quirks).key(5).step("LD V0, K");
+        assert_eq!(chip8.step(), Ok(StepOutcome::WaitingKey));
+        chip8.key_down(8);
+        assert_eq!(chip8.step(), Ok(StepOutcome::Executed { pc: 0x200, opcode: 0xF00A }));
+        assert_eq!((chip8.program_counter, v(&chip8)[0]), (0x202, 8));
+    }
+
+    #[test]
+    fn op_fx07_fx15_fx18_timers() {
+        assert_eq!(v(&Setup::new().timers(42, 0).run("LD V5, DT"))[5], 42);
+        let chip8 = Setup::new().v(5, 30).run("LD DT, V5");
+        assert_eq!((chip8.delay_timer, chip8.sound_timer), (30, 0));
+        let chip8 = Setup::new().v(5, 30).run("LD ST, V5");
+        assert_eq!((chip8.delay_timer, chip8.sound_timer), (0, 30));
+    }
+
+    #[test]
+    fn op_fx1e_add_i() {
+        let chip8 = Setup::new().i(0x0FFF).v(0, 1).v(0xF, 7).run("ADD I, V0");
+        assert_eq!((chip8.index_register, v(&chip8)[0xF]), (0x1000, 7));
+        assert_eq!(Setup::new().i(0xFFFF).v(0, 2).run("ADD I, V0").index_register, 1);
+    }
+
+    #[test]
+    fn op_fx29_fx30_fonts() {
+        let font = |digit: u8| Setup::new().v(0, digit).run("LD F, V0").index_register as usize;
+        assert_eq!(font(0), FONTSET_START_ADDRESS);
+        assert_eq!(font(0xF), FONTSET_START_ADDRESS + 75);
+        assert_eq!(font(0x1A), FONTSET_START_ADDRESS + 50);
+
+        let big = Setup::new().variant(Variant::SuperChip).v(0, 9).run("LD HF, V0");
+        assert_eq!(big.index_register as usize, BIG_FONTSET_START_ADDRESS + 90);
+    }
+
+    #[test]
+    fn op_fx33_bcd() {
+        let bcd = |value: u8| Setup::new().v(3, value).i(0x300).run("LD B, V3").memory[0x300..0x303].to_vec();
+        assert_eq!(bcd(0), [0, 0, 0]);
+        assert_eq!(bcd(7), [0, 0, 7]);
+        assert_eq!(bcd(42), [0, 4, 2]);
+        assert_eq!(bcd(100), [1, 0, 0]);
+        assert_eq!(bcd(255), [2, 5, 5]);
+
+        let end = Setup::new().i(0xFFE);
+        assert_eq!(end.fault("LD B, V0"), Chip8Error::MemOob { pc: 0x200, opcode: 0xF033, address: 0x1000 });
+    }
+
+    #[test]
+    fn op_fx55_fx65_store_load() {
+        let store = || Setup::new().v(0, 1).v(1, 2).v(2, 3).v(3, 4).i(0x300);
+        let chip8 = store().run("LD [I], V2");
+        assert_eq!((&chip8.memory[0x300..0x304], chip8.index_register), (&[1, 2, 3, 0][..], 0x303));
+        assert_eq!(store().quirks(Quirks::CHIP_48).run("LD [I], V2").index_register, 0x300);
+        assert_eq!(&store().run("LD [I], V0").memory[0x300..0x302], [1, 0]);
+
+        let load = || Setup::new().memory(0x300, &[9, 8, 7, 6]).v(3, 0x33).i(0x300);
+        let chip8 = load().run("LD V2, [I]");
+        assert_eq!((v(&chip8)[..4].to_vec(), chip8.index_register), (vec![9, 8, 7, 0x33], 0x303));
+        assert_eq!(load().quirks(Quirks::CHIP_48).run("LD V2, [I]").index_register, 0x300);
+
+        let end = || Setup::new().i(0xFFF);
+        assert_eq!(end().fault("LD [I], V1"), Chip8Error::MemOob { pc: 0x200, opcode: 0xF155, address: 0x1000 });
+        assert_eq!(end().fault("LD V1, [I]"), Chip8Error::MemOob { pc: 0x200, opcode: 0xF165, address: 0x1000 });
+    }
+
+    #[test]
+    fn schip_screen_opcodes() {
+        let schip = || Setup::new().variant(Variant::SuperChip);
+
+        let chip8 = schip().pixel(8, 0).run("SCD 3");
+        assert_eq!((chip8.video[3 * 64 + 8], lit(&chip8)), (1, 1));
+        assert_eq!(lit(&schip().pixel(8, 31).run("SCD 1")), 0);
+        assert_eq!(schip().pixel(8, 0).run("SCR").video[12], 1);
+        assert_eq!(schip().pixel(8, 0).run("SCL").video[4], 1);
+        assert_eq!(lit(&schip().pixel(2, 0).run("SCL")), 0);
+
+        let chip8 = schip().pixel(1, 1).run("HIGH");
+        assert_eq!((chip8.hires(), lit(&chip8)), (true, 0));
+        let chip8 = schip().with(|chip8| chip8.hires = true).pixel(1, 1).run("LOW");
+        assert_eq!((chip8.hires(), lit(&chip8)), (false, 0));
+
+        assert!(schip().run("EXIT").exited());
+        assert_eq!(Setup::new().fault("EXIT"), Chip8Error::UnknownOpcode { pc: 0x200, opcode: 0x00FD });
+    }
+
+    #[test]
+    fn schip_flag_opcodes() {
+        let chip8 = Setup::new().variant(Variant::SuperChip).v(0, 1).v(1, 2).v(2, 3).run("LD R, V1");
+        assert_eq!(chip8.rpl_flags[..3], [1, 2, 0]);
+        let chip8 = Setup::new().variant(Variant::SuperChip).with(|chip8| chip8.rpl_flags = [5; 16]).run("LD V1, R");
+        assert_eq!(v(&chip8)[..3], [5, 5, 0]);
+    }
+
+    #[test]
+    fn xochip_opcodes() {
+        let xo = || Setup::new().variant(Variant::XoChip);
+
+        let chip8 = xo().run("LD I, LONG 0xBEEF");
+        assert_eq!((chip8.index_register, chip8.program_counter), (0xBEEF, 0x204));
+
+        assert_eq!(xo().pixel(8, 3).run("SCU 3").video[8], 1);
+        assert_eq!(xo().run("PLANE 3").plane, 3);
+        assert_eq!(xo().run("PLANE 0").plane, 0);
+        assert_eq!(xo().v(4, 100).run("PITCH V4").pitch, 100);
+
+        let pattern: Vec<u8> = (0..16).collect();
+        let chip8 = xo().i(0x300).memory(0x300, &pattern).run("AUDIO");
+        assert_eq!(chip8.audio_pattern.to_vec(), pattern);
+
+        // with both planes selected the second plane's rows follow the first plane's
+        let chip8 = xo().with(|chip8| chip8.plane = 3).i(0x300).memory(0x300, &[0x80, 0x40]).run("DRW V0, V1, 1");
+        assert_eq!((chip8.video[0], chip8.video[1]), (1, 2));
+    }
+
+    #[test]
+    fn unknown_opcodes() {
+        for opcode in [0x0123u16, 0x00E1, 0x5121, 0x800F, 0x9121, 0xE000, 0xE00F, 0xE19F, 0xF0FF] {
+            let source = format!("DW {:#06X}", opcode);
+            let (chip8, outcome) = Setup::new().v(0, 1).step(&source);
+            assert_eq!(outcome, Err(Chip8Error::UnknownOpcode { pc: 0x200, opcode }));
+            // a fault leaves the machine as it was
+            assert_eq!((chip8.program_counter, v(&chip8)[0]), (0x200, 1));
+        }
+    }
+}
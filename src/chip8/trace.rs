@@ -0,0 +1,365 @@
+//! Execution trace, one record per instruction: where it ran, what it changed and what it wrote
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::breakpoints::parse_opcode_pattern;
+use super::{Access, Chip8};
+use crate::disasm::Instruction;
+
+/// How trace records are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One aligned line per instruction, e.g. `0204  F133  LD B, V1            I=0300  [0300] 01 02 03`
+    Text,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl TraceFormat {
+    /// JSON Lines for `.jsonl` and `.json` files, text otherwise
+    pub fn for_path(path: &Path) -> TraceFormat {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("jsonl") | Some("json") => TraceFormat::Jsonl,
+            _ => TraceFormat::Text,
+        }
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" | "txt" => Ok(TraceFormat::Text),
+            "jsonl" | "json" => Ok(TraceFormat::Jsonl),
+            _ => Err(format!("Unknown trace format: {} (expected text or jsonl)", name)),
+        }
+    }
+}
+
+/// Addresses from `start` to `end`, both included. Parsed from `0x200-0x2FF` or a single address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl AddressRange {
+    pub fn contains(&self, address: u16) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+}
+
+impl FromStr for AddressRange {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let address = |text: &str| {
+            let text = text.trim();
+            let hex = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+            u16::from_str_radix(hex, 16).map_err(|_| format!("Bad address range: {} (expected something like 0x200-0x2FF)", spec))
+        };
+        let (start, end) = match spec.split_once('-') {
+            Some((start, end)) => (address(start)?, address(end)?),
+            None => (address(spec)?, address(spec)?),
+        };
+        if start > end {
+            return Err(format!("Bad address range: {} (the start is after the end)", spec));
+        }
+        Ok(AddressRange { start, end })
+    }
+}
+
+/// A kind of instruction to trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpClass {
+    /// Opcodes matching a pattern like `Dxyn` or `8xy4`, the letters x, y, n and k are wildcards
+    Opcode { mask: u16, pattern: u16 },
+    /// Instructions with this Cowgod mnemonic, e.g. `DRW` or `CALL`
+    Mnemonic(String),
+}
+
+impl OpClass {
+    pub fn matches(&self, opcode: u16) -> bool {
+        match self {
+            OpClass::Opcode { mask, pattern } => opcode & mask == *pattern,
+            OpClass::Mnemonic(mnemonic) => {
+                let instruction = Instruction::decode(opcode).to_string();
+                instruction.split_whitespace().next().is_some_and(|name| name.eq_ignore_ascii_case(mnemonic))
+            }
+        }
+    }
+}
+
+impl FromStr for OpClass {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let spec = spec.trim();
+        if let Some((mask, pattern)) = parse_opcode_pattern(spec) {
+            return Ok(OpClass::Opcode { mask, pattern });
+        }
+        if spec.is_empty() || !spec.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!("Bad instruction class: {} (expected a mnemonic like DRW or an opcode like Dxyn)", spec));
+        }
+        Ok(OpClass::Mnemonic(spec.to_ascii_uppercase()))
+    }
+}
+
+/// Which instructions get traced. The default traces all of them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Only instructions at these addresses
+    pub pc: Option<AddressRange>,
+    /// Only instructions of one of these classes, all of them when empty
+    pub ops: Vec<OpClass>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: u16, opcode: u16) -> bool {
+        self.pc.is_none_or(|range| range.contains(pc))
+            && (self.ops.is_empty() || self.ops.iter().any(|class| class.matches(opcode)))
+    }
+}
+
+/// What one instruction did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub opcode: u16,
+    /// The V registers it changed: register, before, after
+    pub registers: Vec<(u8, u8, u8)>,
+    /// I before and after the instruction
+    pub index: (u16, u16),
+    /// The bytes it wrote, by start address
+    pub memory: Vec<(u16, Vec<u8>)>,
+}
+
+impl TraceRecord {
+    /// The instruction in Cowgod syntax
+    pub fn mnemonic(&self) -> String {
+        Instruction::decode(self.opcode).to_string()
+    }
+
+    /// One JSON object, without the newline
+    pub fn to_json(&self) -> String {
+        let mut registers: Vec<String> = self.registers
+            .iter()
+            .map(|(x, before, after)| format!("\"V{:X}\":[{},{}]", x, before, after))
+            .collect();
+        if self.index.0 != self.index.1 {
+            registers.push(format!("\"I\":[{},{}]", self.index.0, self.index.1));
+        }
+        let memory: Vec<String> = self.memory
+            .iter()
+            .map(|(address, bytes)| {
+                let bytes: Vec<String> = bytes.iter().map(u8::to_string).collect();
+                format!("{{\"address\":{},\"bytes\":[{}]}}", address, bytes.join(","))
+            })
+            .collect();
+        // mnemonics are plain ASCII without quotes, Debug quoting is valid JSON for them
+        format!(
+            "{{\"pc\":{},\"opcode\":{},\"mnemonic\":{:?},\"registers\":{{{}}},\"i\":{},\"memory\":[{}]}}",
+            self.pc, self.opcode, self.mnemonic(), registers.join(","), self.index.1, memory.join(","),
+        )
+    }
+}
+
+/// The text format
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04X}  {:04X}  {:<18}", self.pc, self.opcode, self.mnemonic())?;
+        for (x, before, after) in &self.registers {
+            write!(f, "  V{:X} {:02X}->{:02X}", x, before, after)?;
+        }
+        if self.index.0 != self.index.1 {
+            write!(f, "  I {:04X}->{:04X}", self.index.0, self.index.1)?;
+        } else {
+            write!(f, "  I={:04X}", self.index.1)?;
+        }
+        for (address, bytes) in &self.memory {
+            write!(f, "  [{:04X}]", address)?;
+            for byte in bytes {
+                write!(f, " {:02X}", byte)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes a record for every instruction that passes the filter, installed with `Chip8::set_tracer`
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    // the first failed write, tracing stops there
+    error: Option<io::Error>,
+    // memory written by the instruction being executed, address and length
+    writes: Vec<(usize, usize)>,
+}
+
+impl Tracer {
+    pub fn new(out: impl Write + Send + 'static, format: TraceFormat) -> Tracer {
+        Tracer { out: Box::new(out), format, filter: TraceFilter::default(), error: None, writes: Vec::new() }
+    }
+
+    pub fn with_filter(mut self, filter: TraceFilter) -> Tracer {
+        self.filter = filter;
+        self
+    }
+
+    pub fn filter(&self) -> &TraceFilter {
+        &self.filter
+    }
+
+    /// Write `record` if it passes the filter
+    pub fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_some() || !self.filter.matches(record.pc, record.opcode) {
+            return;
+        }
+        let result = match self.format {
+            TraceFormat::Text => writeln!(self.out, "{}", record),
+            TraceFormat::Jsonl => writeln!(self.out, "{}", record.to_json()),
+        };
+        if let Err(error) = result {
+            self.error = Some(error);
+        }
+    }
+
+    /// Forget the writes of an instruction that didn't finish
+    pub(crate) fn interrupt(&mut self) {
+        self.writes.clear();
+    }
+
+    /// Flush the output, and report the first write that failed
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.out.flush(),
+        }
+    }
+}
+
+impl Chip8 {
+    /// Trace every instruction from now on, `None` stops tracing
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /// Stop tracing and get the tracer back, to `finish` it
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Toggle a text trace of every instruction on stderr
+    pub fn debug(&mut self) {
+        self.tracer = match self.tracer {
+            Some(_) => None,
+            None => Some(Tracer::new(io::stderr(), TraceFormat::Text)),
+        };
+    }
+
+    /// Report the instruction at `pc` that just ran, `registers` and `index` are the values
+    /// it started with
+    pub(crate) fn trace(&mut self, pc: u16, opcode: u16, registers: &[u8; 16], index: u16) {
+        let Some(tracer) = &mut self.tracer else {
+            return;
+        };
+        let writes = std::mem::take(&mut tracer.writes);
+        if !tracer.filter.matches(pc, opcode) {
+            return;
+        }
+        let (after, memory) = (&self.registers, &self.memory);
+        let record = TraceRecord {
+            pc,
+            opcode,
+            registers: (0..16)
+                .filter(|&x| registers[x] != after[x])
+                .map(|x| (x as u8, registers[x], after[x]))
+                .collect(),
+            index: (index, self.index_register),
+            memory: writes
+                .into_iter()
+                .map(|(address, len)| {
+                    let end = (address + len).min(memory.len());
+                    (address as u16, memory[address.min(end)..end].to_vec())
+                })
+                .collect(),
+        };
+        tracer.record(&record);
+    }
+
+    /// Note a memory access for the trace, only writes are kept
+    pub(crate) fn trace_access(&mut self, address: usize, len: usize, access: Access) {
+        if let (Some(tracer), Access::Write) = (&mut self.tracer, access) {
+            tracer.writes.push((address, len));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AddressRange, OpClass, TraceFilter, TraceFormat, Tracer};
+    use crate::chip8::testing::Shared;
+    use crate::chip8::Chip8;
+
+    const ROM: [u8; 10] = [
+        0x61, 0x7B, // 200: V1 = 123
+        0xA3, 0x00, // 202: I = 0x300
+        0xF1, 0x33, // 204: BCD V1
+        0xD0, 0x05, // 206: draw
+        0x12, 0x08, // 208: jump 0x208
+    ];
+
+    fn traced(format: TraceFormat, filter: TraceFilter) -> (Chip8, Shared) {
+        let mut chip8 = Chip8::new();
+        chip8.reset_and_load_bytes(&ROM).unwrap();
+        let out = Shared::default();
+        chip8.set_tracer(Some(Tracer::new(out.clone(), format).with_filter(filter)));
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
+        (chip8, out)
+    }
+
+    #[test]
+    fn text_and_json_records() {
+        let (_, out) = traced(TraceFormat::Text, TraceFilter::default());
+        assert_eq!(out.lines(), [
+            "0200  617B  LD V1, 0x7B         V1 00->7B  I=0000",
+            "0202  A300  LD I, 0x300         I 0000->0300",
+            "0204  F133  LD B, V1            I=0300  [0300] 01 02 03",
+            "0206  D005  DRW V0, V0, 5       I=0300",
+        ]);
+
+        let (_, out) = traced(TraceFormat::Jsonl, TraceFilter::default());
+        assert_eq!(out.lines()[0], r#"{"pc":512,"opcode":24955,"mnemonic":"LD V1, 0x7B","registers":{"V1":[0,123]},"i":0,"memory":[]}"#);
+        assert_eq!(out.lines()[2], r#"{"pc":516,"opcode":61747,"mnemonic":"LD B, V1","registers":{},"i":768,"memory":[{"address":768,"bytes":[1,2,3]}]}"#);
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!("0x202-0x204".parse(), Ok(AddressRange { start: 0x202, end: 0x204 }));
+        assert!("0x204-0x202".parse::<AddressRange>().is_err());
+        assert_eq!("Dxyn".parse(), Ok(OpClass::Opcode { mask: 0xF000, pattern: 0xD000 }));
+        assert_eq!("call".parse(), Ok(OpClass::Mnemonic("CALL".to_string())));
+
+        let by_pc = TraceFilter { pc: Some("0x202-0x204".parse().unwrap()), ops: Vec::new() };
+        let (_, out) = traced(TraceFormat::Text, by_pc);
+        assert_eq!(out.lines().len(), 2);
+        assert!(out.lines()[0].starts_with("0202"));
+
+        let by_class = TraceFilter { pc: None, ops: vec!["drw".parse().unwrap(), "6xkk".parse().unwrap()] };
+        let (mut chip8, out) = traced(TraceFormat::Text, by_class);
+        let lines = out.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0200") && lines[1].starts_with("0206"));
+
+        chip8.take_tracer().unwrap().finish().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(out.lines().len(), 2);
+    }
+}
@@ -0,0 +1,682 @@
+//! Debug Adapter Protocol server, so editors like VS Code can run and step a ROM.
+//!
+//! Messages are JSON with a `Content-Length` header, over stdio or a socket. Launch takes the ROM
+//! in `program` and optionally a symbol file in `symbols`, one `address label` pair per line.
+//! Breakpoints are set by label or address as function breakpoints (any `Breakpoint` string
+//! works, e.g. `VF == 1` or `Dxyn`) or from the disassembly view. The call stack comes from the
+//! CHIP-8 stack, the variables are the registers, the timers and the keypad.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::chip8::{Break, Breakpoint, BreakpointId, Chip8, StepOutcome, Variant};
+use crate::disasm::Instruction;
+use crate::quirks::Quirks;
+
+/// The only thread, a CHIP-8 has one
+const THREAD_ID: u64 = 1;
+
+// variablesReference of the scopes
+const REGISTERS: u64 = 1;
+const TIMERS: u64 = 2;
+const KEYPAD: u64 = 3;
+
+/// Serve one debugging session, until the editor disconnects or closes `input`
+pub fn serve<R, W>(input: R, output: W) -> io::Result<()>
+where
+    R: Read + Send + 'static,
+    W: Write,
+{
+    // requests keep coming while the ROM runs, e.g. pause
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut adapter = Adapter {
+        output,
+        seq: 0,
+        chip8: Chip8::new(),
+        symbols: Vec::new(),
+        running: false,
+        stop_on_entry: false,
+        function_breakpoints: Vec::new(),
+        instruction_breakpoints: Vec::new(),
+    };
+    adapter.run(requests)
+}
+
+/// Read `address label` lines, addresses are hex with or without `0x`. Blank lines and lines
+/// starting with `#` or `;` are skipped
+pub fn parse_symbols(text: &str) -> Result<Vec<(u16, String)>, String> {
+    let mut symbols = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let bad = || format!("line {}: expected `address label`, got `{}`", number + 1, line);
+        let mut fields = line.split_whitespace();
+        let address = fields.next().ok_or_else(bad)?;
+        let address = address.strip_prefix("0x").or_else(|| address.strip_prefix("0X")).unwrap_or(address);
+        let address = u16::from_str_radix(address, 16).map_err(|_| bad())?;
+        let label = fields.next().ok_or_else(bad)?;
+        symbols.push((address, label.to_string()));
+    }
+    symbols.sort();
+    Ok(symbols)
+}
+
+struct Adapter<W> {
+    output: W,
+    seq: u64,
+    chip8: Chip8,
+    // sorted by address
+    symbols: Vec<(u16, String)>,
+    running: bool,
+    stop_on_entry: bool,
+    // each set request replaces the whole set
+    function_breakpoints: Vec<BreakpointId>,
+    instruction_breakpoints: Vec<BreakpointId>,
+}
+
+impl<W: Write> Adapter<W> {
+    fn run(&mut self, requests: Receiver<Value>) -> io::Result<()> {
+        let frame_time = Duration::from_secs(1) / 60;
+        let mut next_frame = Instant::now();
+        loop {
+            let request = if self.running {
+                match requests.try_recv() {
+                    Ok(request) => Some(request),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            if let Some(request) = request {
+                if !self.handle(&request)? {
+                    return Ok(());
+                }
+                next_frame = Instant::now();
+                continue;
+            }
+
+            self.run_frame()?;
+            next_frame += frame_time;
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            }
+        }
+    }
+
+    /// Answer a request, false once the session is over
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+        let body = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsReadMemoryRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => {
+                // there are no source lines to map, only addresses
+                let breakpoints: Vec<Value> = args["breakpoints"].as_array().map_or(Vec::new(), |lines| {
+                    lines.iter().map(|_| json!({
+                        "verified": false,
+                        "message": "Set breakpoints by label or address, as function breakpoints or in the disassembly",
+                    })).collect()
+                });
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "setFunctionBreakpoints" => Ok(self.set_function_breakpoints(args)),
+            "setInstructionBreakpoints" => Ok(self.set_instruction_breakpoints(args)),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    self.stopped("entry", None, &[])?;
+                } else {
+                    self.running = true;
+                }
+                Ok(json!({}))
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Timers", "variablesReference": TIMERS, "expensive": false },
+                { "name": "Keypad", "variablesReference": KEYPAD, "expensive": false },
+            ] })),
+            "variables" => Ok(self.variables(args["variablesReference"].as_u64().unwrap_or_default())),
+            "readMemory" => self.read_memory(args),
+            "disassemble" => self.disassemble(args),
+            "continue" => {
+                self.running = true;
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "pause" => {
+                self.respond(request, Ok(json!({})))?;
+                self.pause()?;
+                return Ok(true);
+            }
+            "next" | "stepIn" | "stepOut" => {
+                self.respond(request, Ok(json!({})))?;
+                self.step(command)?;
+                return Ok(true);
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(json!({})))?;
+                if command == "terminate" {
+                    self.event("terminated", json!({}))?;
+                }
+                return Ok(command != "disconnect");
+            }
+            _ => Err(format!("Unsupported request: {}", command)),
+        };
+        self.respond(request, body)?;
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"].as_str().ok_or("launch needs the ROM path in `program`")?;
+        let variant: Variant = match args["variant"].as_str() {
+            Some(name) => name.parse()?,
+            None => Variant::Chip8,
+        };
+        let quirks: Quirks = match args["quirks"].as_str() {
+            Some(name) => name.parse::<Quirks>().map_err(|e| e.to_string())?,
+            None => variant.default_quirks(),
+        };
+        if let Some(symbols) = args["symbols"].as_str() {
+            let text = std::fs::read_to_string(symbols).map_err(|e| format!("{}: {}", symbols, e))?;
+            self.symbols = parse_symbols(&text).map_err(|e| format!("{}: {}", symbols, e))?;
+        }
+
+        self.chip8.set_variant(variant);
+        self.chip8.set_quirks(quirks);
+        if let Some(ipf) = args["ipf"].as_u64() {
+            self.chip8.set_ipf(ipf as u32);
+        }
+        self.chip8.load_rom(Path::new(program).to_path_buf()).map_err(|e| format!("{}: {}", program, e))?;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+
+        // the editor sends the breakpoints once it sees this, labels resolve from now on
+        self.event("initialized", json!({})).map_err(|e| e.to_string())?;
+        Ok(json!({}))
+    }
+
+    /// Labels and addresses, optionally with a `VF == 1` style condition, or any `Breakpoint` string
+    fn set_function_breakpoints(&mut self, args: &Value) -> Value {
+        for id in self.function_breakpoints.drain(..) {
+            self.chip8.remove_breakpoint(id);
+        }
+        let mut results = Vec::new();
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+            let name = breakpoint["name"].as_str().unwrap_or_default().trim();
+            let spec = match self.symbols.iter().find(|(_, label)| label == name) {
+                Some((address, _)) => format!("{:#05X}", address),
+                None => name.to_string(),
+            };
+            let spec = match breakpoint["condition"].as_str() {
+                Some(condition) if !condition.trim().is_empty() => format!("{} if {}", spec, condition.trim()),
+                _ => spec,
+            };
+            results.push(match spec.parse::<Breakpoint>() {
+                Ok(parsed) => {
+                    let id = self.chip8.add_breakpoint(parsed);
+                    self.function_breakpoints.push(id);
+                    json!({ "id": id.0, "verified": true })
+                }
+                Err(e) => json!({ "verified": false, "message": e }),
+            });
+        }
+        json!({ "breakpoints": results })
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Value {
+        for id in self.instruction_breakpoints.drain(..) {
+            self.chip8.remove_breakpoint(id);
+        }
+        let mut results = Vec::new();
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+            let address = parse_reference(&breakpoint["instructionReference"])
+                .and_then(|address| address.checked_add(breakpoint["offset"].as_i64().unwrap_or(0)));
+            let condition = breakpoint["condition"].as_str().map(str::trim).filter(|condition| !condition.is_empty());
+            let parsed = match (address, condition) {
+                (Some(address @ 0..=0xFFFF), Some(condition)) => format!("{:#05X} if {}", address, condition).parse(),
+                (Some(address @ 0..=0xFFFF), None) => Ok(Breakpoint::Pc(address as u16)),
+                _ => Err("Bad instruction reference".to_string()),
+            };
+            results.push(match parsed {
+                Ok(parsed) => {
+                    let id = self.chip8.add_breakpoint(parsed);
+                    self.instruction_breakpoints.push(id);
+                    json!({ "id": id.0, "verified": true })
+                }
+                Err(e) => json!({ "verified": false, "message": e }),
+            });
+        }
+        json!({ "breakpoints": results })
+    }
+
+    /// The PC, then the call sites of the return addresses on the stack, innermost first
+    fn stack_trace(&self) -> Value {
+        let pc = self.chip8.program_counter();
+        let calls = self.chip8.stack().iter().rev().map(|address| address.wrapping_sub(2));
+        let frames: Vec<Value> = std::iter::once(pc)
+            .chain(calls)
+            .enumerate()
+            .map(|(id, address)| json!({
+                "id": id,
+                "name": self.symbolize(address),
+                "instructionPointerReference": format!("{:#05X}", address),
+                "line": 0,
+                "column": 0,
+            }))
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn variables(&self, reference: u64) -> Value {
+        let chip8 = &self.chip8;
+        let variables: Vec<Value> = match reference {
+            REGISTERS => {
+                let mut variables: Vec<Value> = chip8
+                    .registers()
+                    .iter()
+                    .enumerate()
+                    .map(|(x, value)| variable(&format!("V{:X}", x), format!("{:#04X}", value), None))
+                    .collect();
+                let i = chip8.index_register();
+                variables.push(variable("I", format!("{:#05X}", i), Some(i)));
+                variables.push(variable("PC", format!("{:#05X}", chip8.program_counter()), Some(chip8.program_counter())));
+                variables.push(variable("SP", chip8.stack_pointer().to_string(), None));
+                variables
+            }
+            TIMERS => vec![
+                variable("DT", chip8.delay_timer().to_string(), None),
+                variable("ST", chip8.sound_timer().to_string(), None),
+            ],
+            KEYPAD => chip8
+                .keypad
+                .iter()
+                .enumerate()
+                .map(|(key, down)| variable(&format!("{:X}", key), if *down != 0 { "down" } else { "up" }.to_string(), None))
+                .collect(),
+            _ => Vec::new(),
+        };
+        json!({ "variables": variables })
+    }
+
+    fn read_memory(&self, args: &Value) -> Result<Value, String> {
+        let address = parse_reference(&args["memoryReference"])
+            .and_then(|address| address.checked_add(args["offset"].as_i64().unwrap_or(0)))
+            .ok_or("Bad memory reference")?;
+        let count = args["count"].as_u64().unwrap_or(0) as usize;
+        let memory = self.chip8.memory();
+        let start = (address.max(0) as usize).min(memory.len());
+        let end = start.saturating_add(count).min(memory.len());
+        Ok(json!({
+            "address": format!("{:#05X}", address),
+            "data": base64(&memory[start..end]),
+            "unreadableBytes": count - (end - start),
+        }))
+    }
+
+    /// Instructions are 2 bytes apart, the view starts wherever it's asked to
+    fn disassemble(&self, args: &Value) -> Result<Value, String> {
+        let address = parse_reference(&args["memoryReference"])
+            .and_then(|address| address.checked_add(args["offset"].as_i64().unwrap_or(0)))
+            .and_then(|address| address.checked_add(args["instructionOffset"].as_i64().unwrap_or(0).checked_mul(2)?))
+            .ok_or("Bad memory reference")?;
+        let count = args["instructionCount"].as_i64().unwrap_or(0);
+        let memory = self.chip8.memory();
+        let instructions: Vec<Value> = (0..count)
+            .map(|i| address.saturating_add(i.saturating_mul(2)))
+            .map(|address| {
+                if address < 0 || address as usize + 1 >= memory.len() {
+                    return json!({ "address": format!("{:#05X}", address), "instruction": "", "presentationHint": "invalid" });
+                }
+                let opcode = (memory[address as usize] as u16) << 8 | memory[address as usize + 1] as u16;
+                let mut instruction = json!({
+                    "address": format!("{:#05X}", address),
+                    "instructionBytes": format!("{:04X}", opcode),
+                    "instruction": Instruction::decode(opcode).to_string(),
+                });
+                if let Some((_, label)) = self.symbols.iter().find(|(symbol, _)| *symbol as i64 == address) {
+                    instruction["symbol"] = json!(label);
+                }
+                instruction
+            })
+            .collect();
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    /// `label`, `label+0x4` or the bare address
+    fn symbolize(&self, address: u16) -> String {
+        match self.symbols.iter().rev().find(|(symbol, _)| *symbol <= address) {
+            Some((symbol, label)) if *symbol == address => label.clone(),
+            Some((symbol, label)) => format!("{}+{:#X}", label, address - symbol),
+            None => format!("{:#05X}", address),
+        }
+    }
+
+    fn pause(&mut self) -> io::Result<()> {
+        self.chip8.cancel_run_to();
+        if self.running {
+            self.stopped("pause", None, &[])?;
+        }
+        Ok(())
+    }
+
+    /// `stepIn` runs one instruction, `next` also runs a whole call and `stepOut` returns from one
+    fn step(&mut self, command: &str) -> io::Result<()> {
+        self.chip8.cancel_run_to();
+        let target = match command {
+            "next" => self.chip8.step_over(),
+            "stepOut" => self.chip8.step_out(),
+            _ => false,
+        };
+        if target {
+            self.running = true;
+            return Ok(());
+        }
+
+        self.running = false;
+        match self.chip8.debug_step() {
+            Ok(StepOutcome::Exited) => self.exited(),
+            Ok(StepOutcome::Break(hit)) => self.hit(hit),
+            Ok(_) => self.stopped("step", None, &[]),
+            Err(e) => self.stopped("exception", Some(e.to_string()), &[]),
+        }
+    }
+
+    fn run_frame(&mut self) -> io::Result<()> {
+        match self.chip8.run_frame() {
+            Ok(StepOutcome::Exited) => self.exited(),
+            Ok(StepOutcome::Break(hit)) => self.hit(hit),
+            Ok(_) => Ok(()),
+            Err(e) => self.stopped("exception", Some(e.to_string()), &[]),
+        }
+    }
+
+    fn hit(&mut self, hit: Break) -> io::Result<()> {
+        let Some(id) = hit.id() else {
+            // the end of a step over or step out
+            return self.stopped("step", None, &[]);
+        };
+        let reason = if self.function_breakpoints.contains(&id) {
+            "function breakpoint"
+        } else if self.instruction_breakpoints.contains(&id) {
+            "instruction breakpoint"
+        } else if matches!(hit, Break::Watchpoint { .. }) {
+            "data breakpoint"
+        } else {
+            "breakpoint"
+        };
+        self.stopped(reason, Some(hit.to_string()), &[id])
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>, hits: &[BreakpointId]) -> io::Result<()> {
+        self.running = false;
+        let mut body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        if !hits.is_empty() {
+            body["hitBreakpointIds"] = json!(hits.iter().map(|id| id.0).collect::<Vec<_>>());
+        }
+        self.event("stopped", body)
+    }
+
+    fn exited(&mut self) -> io::Result<()> {
+        self.running = false;
+        self.event("exited", json!({ "exitCode": 0 }))?;
+        self.event("terminated", json!({}))
+    }
+
+    fn respond(&mut self, request: &Value, body: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.output, &message)
+    }
+}
+
+fn variable(name: &str, value: String, memory: Option<u16>) -> Value {
+    let mut variable = json!({ "name": name, "value": value, "variablesReference": 0 });
+    if let Some(address) = memory {
+        variable["memoryReference"] = json!(format!("{:#05X}", address));
+    }
+    variable
+}
+
+/// Memory and instruction references are addresses, `0x2A4` or decimal
+fn parse_reference(reference: &Value) -> Option<i64> {
+    let reference = reference.as_str()?.trim();
+    match reference.strip_prefix("0x").or_else(|| reference.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => reference.parse().ok(),
+    }
+}
+
+/// One message, `None` once the input is closed
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut content = vec![0; length];
+    input.read_exact(&mut content)?;
+    serde_json::from_slice(&content).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", content.len(), content)?;
+    output.flush()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use serde_json::{json, Value};
+
+    use super::{base64, parse_symbols, read_message, serve, write_message};
+
+    /// An editor connected to an adapter serving over a local socket
+    struct Editor {
+        stream: TcpStream,
+        input: BufReader<TcpStream>,
+        seq: u64,
+        events: Vec<Value>,
+    }
+
+    impl Editor {
+        fn connect() -> Editor {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            thread::spawn(move || {
+                let (stream, _) = listener.accept().unwrap();
+                serve(stream.try_clone().unwrap(), stream).unwrap();
+            });
+            let stream = TcpStream::connect(address).unwrap();
+            Editor { input: BufReader::new(stream.try_clone().unwrap()), stream, seq: 0, events: Vec::new() }
+        }
+
+        /// Send a request and return the body of its response, events that come first are kept
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            self.seq += 1;
+            let request = json!({ "seq": self.seq, "type": "request", "command": command, "arguments": arguments });
+            write_message(&mut self.stream, &request).unwrap();
+            loop {
+                let message = read_message(&mut self.input).unwrap().unwrap();
+                if message["type"] == "event" {
+                    self.events.push(message);
+                    continue;
+                }
+                assert_eq!(message["request_seq"], json!(self.seq));
+                assert_eq!(message["success"], json!(true), "{} failed: {}", command, message);
+                return message["body"].clone();
+            }
+        }
+
+        /// The body of the next event called `name`
+        fn event(&mut self, name: &str) -> Value {
+            if let Some(index) = self.events.iter().position(|event| event["event"] == name) {
+                return self.events.remove(index)["body"].clone();
+            }
+            loop {
+                let message = read_message(&mut self.input).unwrap().unwrap();
+                if message["event"] == name {
+                    return message["body"].clone();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn symbols_and_base64() {
+        let symbols = parse_symbols("# labels\n0x200 main\n\n20A draw ; trailing\n").unwrap();
+        assert_eq!(symbols, vec![(0x200, "main".to_string()), (0x20A, "draw".to_string())]);
+        assert!(parse_symbols("main 0x200").is_err());
+
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn launch_break_inspect_and_step() {
+        let dir = std::env::temp_dir().join(format!("chip8-dap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("test.ch8");
+        let symbols = dir.join("test.sym");
+        std::fs::write(&rom, [
+            0x60, 0x07, // 200: V0 = 7
+            0x22, 0x08, // 202: call 0x208
+            0x12, 0x04, // 204: jump 0x204
+            0x00, 0x00,
+            0xA3, 0x00, // 208: I = 0x300
+            0x00, 0xEE, // 20A: return
+        ]).unwrap();
+        std::fs::write(&symbols, "0x200 main\n0x208 set_i\n").unwrap();
+
+        let mut editor = Editor::connect();
+        let capabilities = editor.request("initialize", json!({ "adapterID": "chip8" }));
+        assert_eq!(capabilities["supportsReadMemoryRequest"], json!(true));
+        editor.request("launch", json!({ "program": rom, "symbols": symbols }));
+        editor.event("initialized");
+
+        let breakpoints = editor.request("setFunctionBreakpoints", json!({ "breakpoints": [{ "name": "set_i" }, { "name": "nowhere" }] }));
+        assert_eq!(breakpoints["breakpoints"][0]["verified"], json!(true));
+        assert_eq!(breakpoints["breakpoints"][1]["verified"], json!(false));
+        editor.request("configurationDone", json!({}));
+
+        let stopped = editor.event("stopped");
+        assert_eq!(stopped["reason"], json!("function breakpoint"));
+        let trace = editor.request("stackTrace", json!({ "threadId": 1 }));
+        let names: Vec<&str> = trace["stackFrames"].as_array().unwrap().iter().map(|frame| frame["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["set_i", "main+0x2"]);
+
+        let registers = editor.request("variables", json!({ "variablesReference": 1 }));
+        assert_eq!(registers["variables"][0], json!({ "name": "V0", "value": "0x07", "variablesReference": 0 }));
+        let memory = editor.request("readMemory", json!({ "memoryReference": "0x200", "count": 2 }));
+        assert_eq!(memory["data"], json!(base64(&[0x60, 0x07])));
+        // sizes and offsets that overflow are cut short or rejected
+        let memory = editor.request("readMemory", json!({ "memoryReference": "0xFFE", "count": u64::MAX }));
+        assert_eq!(memory["data"], json!(base64(&[0, 0])));
+        let far = editor.request("setInstructionBreakpoints", json!({ "breakpoints": [{ "instructionReference": "0x200", "offset": i64::MAX }] }));
+        assert_eq!(far["breakpoints"][0]["verified"], json!(false));
+
+        // step out of the call, then step into the jump loop
+        editor.request("stepOut", json!({ "threadId": 1 }));
+        assert_eq!(editor.event("stopped")["reason"], json!("step"));
+        let trace = editor.request("stackTrace", json!({ "threadId": 1 }));
+        assert_eq!(trace["stackFrames"][0]["instructionPointerReference"], json!("0x204"));
+        editor.request("stepIn", json!({ "threadId": 1 }));
+        assert_eq!(editor.event("stopped")["reason"], json!("step"));
+
+        let disassembly = editor.request("disassemble", json!({ "memoryReference": "0x208", "instructionCount": 2 }));
+        assert_eq!(disassembly["instructions"][0]["instruction"], json!("LD I, 0x300"));
+        assert_eq!(disassembly["instructions"][0]["symbol"], json!("set_i"));
+
+        editor.request("continue", json!({ "threadId": 1 }));
+        editor.request("pause", json!({ "threadId": 1 }));
+        assert_eq!(editor.event("stopped")["reason"], json!("pause"));
+        editor.request("disconnect", json!({}));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,778 @@
+//! Disassembler: decodes opcodes into `Instruction`s and turns whole ROMs into listings, in
+//! Cowgod's syntax (`LD V1, 0x20`) or Octo's (`v1 := 0x20`).
+//!
+//! Code and data are told apart by following the control flow from 0x200: jumps, calls and both
+//! outcomes of every skip. Bytes that are never reached are listed as data.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chip8::Variant;
+
+/// Where ROMs are loaded and execution starts
+pub const ROM_START: u16 = 0x200;
+
+/// A decoded opcode. `x` and `y` are register numbers, `n` a nibble, `byte` the low byte and
+/// `addr` the low 12 bits of the opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0NNN - machine code routine on the original hardware, no interpreter here runs it
+    Sys { addr: u16 },
+    /// 00E0
+    Cls,
+    /// 00EE
+    Ret,
+    /// 00CN (SUPER-CHIP)
+    ScrollDown { n: u8 },
+    /// 00DN (XO-CHIP)
+    ScrollUp { n: u8 },
+    /// 00FB (SUPER-CHIP)
+    ScrollRight,
+    /// 00FC (SUPER-CHIP)
+    ScrollLeft,
+    /// 00FD (SUPER-CHIP)
+    Exit,
+    /// 00FE (SUPER-CHIP)
+    Lores,
+    /// 00FF (SUPER-CHIP)
+    Hires,
+    /// 1NNN
+    Jump { addr: u16 },
+    /// 2NNN
+    Call { addr: u16 },
+    /// 3XKK
+    SkipEqByte { x: u8, byte: u8 },
+    /// 4XKK
+    SkipNeByte { x: u8, byte: u8 },
+    /// 5XY0
+    SkipEqReg { x: u8, y: u8 },
+    /// 5XY2 (XO-CHIP)
+    SaveRange { x: u8, y: u8 },
+    /// 5XY3 (XO-CHIP)
+    LoadRange { x: u8, y: u8 },
+    /// 6XKK
+    LoadByte { x: u8, byte: u8 },
+    /// 7XKK
+    AddByte { x: u8, byte: u8 },
+    /// 8XY0
+    Move { x: u8, y: u8 },
+    /// 8XY1
+    Or { x: u8, y: u8 },
+    /// 8XY2
+    And { x: u8, y: u8 },
+    /// 8XY3
+    Xor { x: u8, y: u8 },
+    /// 8XY4
+    AddReg { x: u8, y: u8 },
+    /// 8XY5
+    Sub { x: u8, y: u8 },
+    /// 8XY6
+    ShiftRight { x: u8, y: u8 },
+    /// 8XY7
+    SubReverse { x: u8, y: u8 },
+    /// 8XYE
+    ShiftLeft { x: u8, y: u8 },
+    /// 9XY0
+    SkipNeReg { x: u8, y: u8 },
+    /// ANNN
+    LoadI { addr: u16 },
+    /// BNNN
+    JumpV0 { addr: u16 },
+    /// CXKK
+    Random { x: u8, byte: u8 },
+    /// DXYN
+    Draw { x: u8, y: u8, n: u8 },
+    /// EX9E
+    SkipKey { x: u8 },
+    /// EXA1
+    SkipNotKey { x: u8 },
+    /// F000 NNNN (XO-CHIP), the address is the word following the opcode
+    LoadILong,
+    /// FN01 (XO-CHIP)
+    Plane { n: u8 },
+    /// F002 (XO-CHIP)
+    Audio,
+    /// FX07
+    GetDelay { x: u8 },
+    /// FX0A
+    WaitKey { x: u8 },
+    /// FX15
+    SetDelay { x: u8 },
+    /// FX18
+    SetSound { x: u8 },
+    /// FX1E
+    AddI { x: u8 },
+    /// FX29
+    Font { x: u8 },
+    /// FX30 (SUPER-CHIP)
+    BigFont { x: u8 },
+    /// FX33
+    Bcd { x: u8 },
+    /// FX3A (XO-CHIP)
+    Pitch { x: u8 },
+    /// FX55
+    Store { x: u8 },
+    /// FX65
+    Load { x: u8 },
+    /// FX75 (SUPER-CHIP)
+    SaveFlags { x: u8 },
+    /// FX85 (SUPER-CHIP)
+    LoadFlags { x: u8 },
+    /// Not an opcode of any variant
+    Unknown(u16),
+}
+
+impl Instruction {
+    /// Decode a 16-bit opcode, words that aren't opcodes decode to `Unknown`
+    ///
+    /// ```
+    /// # use chip8_rs::disasm::Instruction;
+    /// assert_eq!(Instruction::decode(0x6120), Instruction::LoadByte { x: 1, byte: 0x20 });
+    /// assert_eq!(Instruction::decode(0x6120).to_string(), "LD V1, 0x20");
+    /// ```
+    pub fn decode(opcode: u16) -> Instruction {
+        use Instruction::*;
+
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let byte = (opcode & 0x00FF) as u8;
+        let addr = opcode & 0x0FFF;
+
+        match opcode >> 12 {
+            0x0 => match opcode {
+                0x00E0 => Cls,
+                0x00EE => Ret,
+                0x00C0..=0x00CF => ScrollDown { n },
+                0x00D0..=0x00DF => ScrollUp { n },
+                0x00FB => ScrollRight,
+                0x00FC => ScrollLeft,
+                0x00FD => Exit,
+                0x00FE => Lores,
+                0x00FF => Hires,
+                _ => Sys { addr },
+            },
+            0x1 => Jump { addr },
+            0x2 => Call { addr },
+            0x3 => SkipEqByte { x, byte },
+            0x4 => SkipNeByte { x, byte },
+            0x5 => match n {
+                0x0 => SkipEqReg { x, y },
+                0x2 => SaveRange { x, y },
+                0x3 => LoadRange { x, y },
+                _ => Unknown(opcode),
+            },
+            0x6 => LoadByte { x, byte },
+            0x7 => AddByte { x, byte },
+            0x8 => match n {
+                0x0 => Move { x, y },
+                0x1 => Or { x, y },
+                0x2 => And { x, y },
+                0x3 => Xor { x, y },
+                0x4 => AddReg { x, y },
+                0x5 => Sub { x, y },
+                0x6 => ShiftRight { x, y },
+                0x7 => SubReverse { x, y },
+                0xE => ShiftLeft { x, y },
+                _ => Unknown(opcode),
+            },
+            0x9 if n == 0 => SkipNeReg { x, y },
+            0xA => LoadI { addr },
+            0xB => JumpV0 { addr },
+            0xC => Random { x, byte },
+            0xD => Draw { x, y, n },
+            0xE => match byte {
+                0x9E => SkipKey { x },
+                0xA1 => SkipNotKey { x },
+                _ => Unknown(opcode),
+            },
+            0xF => match byte {
+                0x00 if x == 0 => LoadILong,
+                0x01 => Plane { n: x },
+                0x02 if x == 0 => Audio,
+                0x07 => GetDelay { x },
+                0x0A => WaitKey { x },
+                0x15 => SetDelay { x },
+                0x18 => SetSound { x },
+                0x1E => AddI { x },
+                0x29 => Font { x },
+                0x30 => BigFont { x },
+                0x33 => Bcd { x },
+                0x3A => Pitch { x },
+                0x55 => Store { x },
+                0x65 => Load { x },
+                0x75 => SaveFlags { x },
+                0x85 => LoadFlags { x },
+                _ => Unknown(opcode),
+            },
+            _ => Unknown(opcode),
+        }
+    }
+
+    /// The opcode of the instruction, the inverse of `decode`. `LoadILong` only encodes its
+    /// F000 half, the address goes in the next word.
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+
+        let xy = |op: u16, x: u8, y: u8, n: u16| op | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | n;
+        let xkk = |op: u16, x: u8, byte: u8| op | (x as u16 & 0xF) << 8 | byte as u16;
+        let fx = |x: u8, low: u16| 0xF000 | (x as u16 & 0xF) << 8 | low;
+
+        match *self {
+            Sys { addr } => addr & 0x0FFF,
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            ScrollDown { n } => 0x00C0 | (n as u16 & 0xF),
+            ScrollUp { n } => 0x00D0 | (n as u16 & 0xF),
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            Lores => 0x00FE,
+            Hires => 0x00FF,
+            Jump { addr } => 0x1000 | (addr & 0x0FFF),
+            Call { addr } => 0x2000 | (addr & 0x0FFF),
+            SkipEqByte { x, byte } => xkk(0x3000, x, byte),
+            SkipNeByte { x, byte } => xkk(0x4000, x, byte),
+            SkipEqReg { x, y } => xy(0x5000, x, y, 0x0),
+            SaveRange { x, y } => xy(0x5000, x, y, 0x2),
+            LoadRange { x, y } => xy(0x5000, x, y, 0x3),
+            LoadByte { x, byte } => xkk(0x6000, x, byte),
+            AddByte { x, byte } => xkk(0x7000, x, byte),
+            Move { x, y } => xy(0x8000, x, y, 0x0),
+            Or { x, y } => xy(0x8000, x, y, 0x1),
+            And { x, y } => xy(0x8000, x, y, 0x2),
+            Xor { x, y } => xy(0x8000, x, y, 0x3),
+            AddReg { x, y } => xy(0x8000, x, y, 0x4),
+            Sub { x, y } => xy(0x8000, x, y, 0x5),
+            ShiftRight { x, y } => xy(0x8000, x, y, 0x6),
+            SubReverse { x, y } => xy(0x8000, x, y, 0x7),
+            ShiftLeft { x, y } => xy(0x8000, x, y, 0xE),
+            SkipNeReg { x, y } => xy(0x9000, x, y, 0x0),
+            LoadI { addr } => 0xA000 | (addr & 0x0FFF),
+            JumpV0 { addr } => 0xB000 | (addr & 0x0FFF),
+            Random { x, byte } => xkk(0xC000, x, byte),
+            Draw { x, y, n } => xy(0xD000, x, y, n as u16 & 0xF),
+            SkipKey { x } => xkk(0xE000, x, 0x9E),
+            SkipNotKey { x } => xkk(0xE000, x, 0xA1),
+            LoadILong => 0xF000,
+            Plane { n } => fx(n, 0x01),
+            Audio => 0xF002,
+            GetDelay { x } => fx(x, 0x07),
+            WaitKey { x } => fx(x, 0x0A),
+            SetDelay { x } => fx(x, 0x15),
+            SetSound { x } => fx(x, 0x18),
+            AddI { x } => fx(x, 0x1E),
+            Font { x } => fx(x, 0x29),
+            BigFont { x } => fx(x, 0x30),
+            Bcd { x } => fx(x, 0x33),
+            Pitch { x } => fx(x, 0x3A),
+            Store { x } => fx(x, 0x55),
+            Load { x } => fx(x, 0x65),
+            SaveFlags { x } => fx(x, 0x75),
+            LoadFlags { x } => fx(x, 0x85),
+            Unknown(opcode) => opcode,
+        }
+    }
+
+    /// Bytes taken in memory, 4 for `LoadILong` and 2 for everything else
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LoadILong => 4,
+            _ => 2,
+        }
+    }
+
+    /// The first variant that has this instruction, `None` for `Sys` and `Unknown`
+    pub fn variant(&self) -> Option<Variant> {
+        use Instruction::*;
+
+        match self {
+            Sys { .. } | Unknown(_) => None,
+            ScrollDown { .. } | ScrollRight | ScrollLeft | Exit | Lores | Hires
+            | BigFont { .. } | SaveFlags { .. } | LoadFlags { .. } => Some(Variant::SuperChip),
+            ScrollUp { .. } | SaveRange { .. } | LoadRange { .. } | LoadILong | Plane { .. }
+            | Audio | Pitch { .. } => Some(Variant::XoChip),
+            _ => Some(Variant::Chip8),
+        }
+    }
+
+    /// Whether an interpreter running `variant` executes this instruction,
+    /// every variant has all the instructions of the ones before it
+    pub fn supported_by(&self, variant: Variant) -> bool {
+        let rank = |variant| match variant {
+            Variant::Chip8 => 0,
+            Variant::SuperChip => 1,
+            Variant::XoChip => 2,
+        };
+        self.variant().is_some_and(|first| rank(first) <= rank(variant))
+    }
+
+    /// Whether this instruction skips the next one on some condition
+    pub fn is_skip(&self) -> bool {
+        use Instruction::*;
+
+        matches!(
+            self,
+            SkipEqByte { .. } | SkipNeByte { .. } | SkipEqReg { .. } | SkipNeReg { .. }
+                | SkipKey { .. } | SkipNotKey { .. }
+        )
+    }
+
+    pub fn format(&self, syntax: Syntax) -> String {
+        self.format_with(syntax, &|_| None)
+    }
+
+    /// Format the instruction, addresses that `label` names are written with the name
+    pub fn format_with(&self, syntax: Syntax, label: &dyn Fn(u16) -> Option<String>) -> String {
+        match syntax {
+            Syntax::Cowgod => self.cowgod(label),
+            Syntax::Octo => self.octo(label),
+        }
+    }
+
+    fn cowgod(&self, label: &dyn Fn(u16) -> Option<String>) -> String {
+        use Instruction::*;
+
+        let target = |addr: u16| label(addr).unwrap_or_else(|| format!("0x{:03X}", addr));
+        match *self {
+            Sys { addr } => format!("SYS 0x{:03X}", addr),
+            Cls => "CLS".to_string(),
+            Ret => "RET".to_string(),
+            ScrollDown { n } => format!("SCD {}", n),
+            ScrollUp { n } => format!("SCU {}", n),
+            ScrollRight => "SCR".to_string(),
+            ScrollLeft => "SCL".to_string(),
+            Exit => "EXIT".to_string(),
+            Lores => "LOW".to_string(),
+            Hires => "HIGH".to_string(),
+            Jump { addr } => format!("JP {}", target(addr)),
+            Call { addr } => format!("CALL {}", target(addr)),
+            SkipEqByte { x, byte } => format!("SE V{:X}, 0x{:02X}", x, byte),
+            SkipNeByte { x, byte } => format!("SNE V{:X}, 0x{:02X}", x, byte),
+            SkipEqReg { x, y } => format!("SE V{:X}, V{:X}", x, y),
+            SaveRange { x, y } => format!("SAVE V{:X}-V{:X}", x, y),
+            LoadRange { x, y } => format!("LOAD V{:X}-V{:X}", x, y),
+            LoadByte { x, byte } => format!("LD V{:X}, 0x{:02X}", x, byte),
+            AddByte { x, byte } => format!("ADD V{:X}, 0x{:02X}", x, byte),
+            Move { x, y } => format!("LD V{:X}, V{:X}", x, y),
+            Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+            And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+            Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+            AddReg { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+            Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+            ShiftRight { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+            SubReverse { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+            ShiftLeft { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+            SkipNeReg { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+            LoadI { addr } => format!("LD I, {}", target(addr)),
+            JumpV0 { addr } => format!("JP V0, {}", target(addr)),
+            Random { x, byte } => format!("RND V{:X}, 0x{:02X}", x, byte),
+            Draw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            SkipKey { x } => format!("SKP V{:X}", x),
+            SkipNotKey { x } => format!("SKNP V{:X}", x),
+            LoadILong => "LD I, LONG".to_string(),
+            Plane { n } => format!("PLANE {}", n),
+            Audio => "AUDIO".to_string(),
+            GetDelay { x } => format!("LD V{:X}, DT", x),
+            WaitKey { x } => format!("LD V{:X}, K", x),
+            SetDelay { x } => format!("LD DT, V{:X}", x),
+            SetSound { x } => format!("LD ST, V{:X}", x),
+            AddI { x } => format!("ADD I, V{:X}", x),
+            Font { x } => format!("LD F, V{:X}", x),
+            BigFont { x } => format!("LD HF, V{:X}", x),
+            Bcd { x } => format!("LD B, V{:X}", x),
+            Pitch { x } => format!("PITCH V{:X}", x),
+            Store { x } => format!("LD [I], V{:X}", x),
+            Load { x } => format!("LD V{:X}, [I]", x),
+            SaveFlags { x } => format!("LD R, V{:X}", x),
+            LoadFlags { x } => format!("LD V{:X}, R", x),
+            Unknown(opcode) => format!("DW 0x{:04X}", opcode),
+        }
+    }
+
+    fn octo(&self, label: &dyn Fn(u16) -> Option<String>) -> String {
+        use Instruction::*;
+
+        let target = |addr: u16| label(addr).unwrap_or_else(|| format!("0x{:03X}", addr));
+        match *self {
+            // Octo has no mnemonic for these, so they are written as raw bytes
+            Sys { addr } => format!("0x{:02X} 0x{:02X}", addr >> 8, addr & 0xFF),
+            Unknown(opcode) => format!("0x{:02X} 0x{:02X}", opcode >> 8, opcode & 0xFF),
+            Cls => "clear".to_string(),
+            Ret => "return".to_string(),
+            ScrollDown { n } => format!("scroll-down {}", n),
+            ScrollUp { n } => format!("scroll-up {}", n),
+            ScrollRight => "scroll-right".to_string(),
+            ScrollLeft => "scroll-left".to_string(),
+            Exit => "exit".to_string(),
+            Lores => "lores".to_string(),
+            Hires => "hires".to_string(),
+            Jump { addr } => format!("jump {}", target(addr)),
+            Call { addr } => label(addr).unwrap_or_else(|| format!(":call 0x{:03X}", addr)),
+            // Octo writes skips as the condition under which the next instruction runs
+            SkipEqByte { x, byte } => format!("if v{:x} != 0x{:02X} then", x, byte),
+            SkipNeByte { x, byte } => format!("if v{:x} == 0x{:02X} then", x, byte),
+            SkipEqReg { x, y } => format!("if v{:x} != v{:x} then", x, y),
+            SkipNeReg { x, y } => format!("if v{:x} == v{:x} then", x, y),
+            SkipKey { x } => format!("if v{:x} -key then", x),
+            SkipNotKey { x } => format!("if v{:x} key then", x),
+            SaveRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+            LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+            LoadByte { x, byte } => format!("v{:x} := 0x{:02X}", x, byte),
+            AddByte { x, byte } => format!("v{:x} += 0x{:02X}", x, byte),
+            Move { x, y } => format!("v{:x} := v{:x}", x, y),
+            Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+            And { x, y } => format!("v{:x} &= v{:x}", x, y),
+            Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+            AddReg { x, y } => format!("v{:x} += v{:x}", x, y),
+            Sub { x, y } => format!("v{:x} -= v{:x}", x, y),
+            ShiftRight { x, y } => format!("v{:x} >>= v{:x}", x, y),
+            SubReverse { x, y } => format!("v{:x} =- v{:x}", x, y),
+            ShiftLeft { x, y } => format!("v{:x} <<= v{:x}", x, y),
+            LoadI { addr } => format!("i := {}", target(addr)),
+            JumpV0 { addr } => format!("jump0 {}", target(addr)),
+            Random { x, byte } => format!("v{:x} := random 0x{:02X}", x, byte),
+            Draw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+            LoadILong => "i := long".to_string(),
+            Plane { n } => format!("plane {}", n),
+            Audio => "audio".to_string(),
+            GetDelay { x } => format!("v{:x} := delay", x),
+            WaitKey { x } => format!("v{:x} := key", x),
+            SetDelay { x } => format!("delay := v{:x}", x),
+            SetSound { x } => format!("buzzer := v{:x}", x),
+            AddI { x } => format!("i += v{:x}", x),
+            Font { x } => format!("i := hex v{:x}", x),
+            BigFont { x } => format!("i := bighex v{:x}", x),
+            Bcd { x } => format!("bcd v{:x}", x),
+            Pitch { x } => format!("pitch := v{:x}", x),
+            Store { x } => format!("save v{:x}", x),
+            Load { x } => format!("load v{:x}", x),
+            SaveFlags { x } => format!("saveflags v{:x}", x),
+            LoadFlags { x } => format!("loadflags v{:x}", x),
+        }
+    }
+}
+
+/// Cowgod syntax
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(Syntax::Cowgod))
+    }
+}
+
+/// Assembly syntax used by listings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// Mnemonics from Cowgod's CHIP-8 technical reference, e.g. `LD V1, 0x20`
+    #[default]
+    Cowgod,
+    /// The Octo assembly language, e.g. `v1 := 0x20`
+    Octo,
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    /// Parse a syntax name: `"cowgod"` or `"octo"`
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "cowgod" => Ok(Syntax::Cowgod),
+            "octo" => Ok(Syntax::Octo),
+            _ => Err(format!("Unknown syntax: {} (expected one of: cowgod, octo)", name)),
+        }
+    }
+}
+
+/// What a ROM byte turned out to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Byte {
+    Data,
+    /// First byte of an instruction
+    Code,
+    /// Rest of an instruction
+    Operand,
+}
+
+/// A ROM split into code and data, see `disassemble`
+pub struct Disassembly {
+    rom: Vec<u8>,
+    bytes: Vec<Byte>,
+    labels: BTreeMap<u16, String>,
+}
+
+/// Split `rom` into code and data by following every path the program can take from 0x200.
+/// Opcodes `variant` doesn't have end a path, like unknown ones do. Bytes that wouldn't fit in
+/// the memory of `variant` are left out.
+///
+/// ```
+/// # use chip8_rs::chip8::Variant;
+/// # use chip8_rs::disasm::{disassemble, Syntax};
+/// // jump over two bytes of data
+/// let listing = disassemble(&[0x12, 0x04, 0xFF, 0x00, 0x12, 0x04], Variant::Chip8);
+/// assert!(listing.is_code(0x204));
+/// assert!(!listing.is_code(0x202));
+/// assert_eq!(listing.label(0x204), Some("label_204"));
+/// ```
+///
+/// ```
+/// # use chip8_rs::chip8::Variant;
+/// # use chip8_rs::disasm::{disassemble, Syntax};
+/// // 64 KiB is more than even XO-CHIP memory holds past 0x200
+/// let listing = disassemble(&vec![0xFF; 0x10000], Variant::XoChip);
+/// let text = listing.listing(Syntax::Cowgod);
+/// assert!(text.lines().last().unwrap().trim_start().starts_with("FFF8:"));
+/// ```
+pub fn disassemble(rom: &[u8], variant: Variant) -> Disassembly {
+    let start = ROM_START as usize;
+    let rom = &rom[..rom.len().min(variant.memory_size() - start)];
+    let end = start + rom.len();
+    let word = |addr: usize| {
+        if addr >= start && addr + 1 < end {
+            Some((rom[addr - start] as u16) << 8 | rom[addr + 1 - start] as u16)
+        } else {
+            None
+        }
+    };
+
+    let mut bytes = vec![Byte::Data; rom.len()];
+    // branch targets, and whether they were reached by a call
+    let mut targets: BTreeMap<u16, bool> = BTreeMap::new();
+    let mut pending = vec![start];
+
+    while let Some(mut addr) = pending.pop() {
+        // follow the straight-line code from addr until it jumps away or ends
+        while let Some(opcode) = word(addr) {
+            let instruction = Instruction::decode(opcode);
+            let size = instruction.size() as usize;
+            if !instruction.supported_by(variant)
+                || addr + size > end
+                || bytes[addr - start..addr - start + size].iter().any(|b| *b != Byte::Data)
+            {
+                // not an instruction, or this path joins one that was already followed
+                break;
+            }
+            bytes[addr - start] = Byte::Code;
+            for operand in &mut bytes[addr - start + 1..addr - start + size] {
+                *operand = Byte::Operand;
+            }
+
+            let next = addr + size;
+            match instruction {
+                Instruction::Jump { addr: target } | Instruction::JumpV0 { addr: target } => {
+                    targets.entry(target).or_insert(false);
+                    pending.push(target as usize);
+                    break;
+                }
+                Instruction::Call { addr: target } => {
+                    targets.insert(target, true);
+                    pending.push(target as usize);
+                }
+                Instruction::Ret | Instruction::Exit => break,
+                _ if instruction.is_skip() => {
+                    // skips hop over the whole 4 bytes of a long load on XO-CHIP
+                    let long = variant == Variant::XoChip && word(next) == Some(0xF000);
+                    pending.push(next + if long { 4 } else { 2 });
+                }
+                _ => {}
+            }
+            addr = next;
+        }
+    }
+
+    let mut labels = BTreeMap::new();
+    if !rom.is_empty() {
+        labels.insert(ROM_START, "main".to_string());
+    }
+    for (target, called) in targets {
+        let index = (target as usize).wrapping_sub(start);
+        // a label can't point into the middle of an instruction
+        if index >= rom.len() || bytes[index] == Byte::Operand || target == ROM_START {
+            continue;
+        }
+        let kind = if called { "sub" } else { "label" };
+        labels.insert(target, format!("{}_{:03X}", kind, target));
+    }
+
+    Disassembly { rom: rom.to_vec(), bytes, labels }
+}
+
+impl Disassembly {
+    /// Whether an instruction starts at `addr`
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.byte(addr) == Some(Byte::Code)
+    }
+
+    /// Name of the label at `addr`, if something branches there
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    fn byte(&self, addr: u16) -> Option<Byte> {
+        (addr as usize).checked_sub(ROM_START as usize).and_then(|i| self.bytes.get(i)).copied()
+    }
+
+    fn word(&self, index: usize) -> u16 {
+        (self.rom[index] as u16) << 8 | self.rom[index + 1] as u16
+    }
+
+    /// The whole ROM as assembly, one instruction or up to 8 data bytes per line
+    pub fn listing(&self, syntax: Syntax) -> String {
+        let label = |addr: u16| self.label(addr).map(str::to_string);
+        let target = |addr: u16| label(addr).unwrap_or_else(|| format!("0x{:03X}", addr));
+
+        let mut out = String::new();
+        let mut index = 0;
+        while index < self.rom.len() {
+            let addr = ROM_START + index as u16;
+            if let Some(name) = self.label(addr) {
+                match syntax {
+                    Syntax::Cowgod => out.push_str(&format!("{}:\n", name)),
+                    Syntax::Octo => out.push_str(&format!(": {}\n", name)),
+                }
+            }
+
+            if self.bytes[index] == Byte::Code {
+                let opcode = self.word(index);
+                let instruction = Instruction::decode(opcode);
+                let mut raw = format!("{:04X}", opcode);
+                let mut text = instruction.format_with(syntax, &label);
+                if instruction == Instruction::LoadILong {
+                    let long = self.word(index + 2);
+                    raw.push_str(&format!("{:04X}", long));
+                    text.push(' ');
+                    text.push_str(&target(long));
+                }
+                match syntax {
+                    Syntax::Cowgod => out.push_str(&format!("    {:03X}: {:<8}  {}\n", addr, raw, text)),
+                    Syntax::Octo => out.push_str(&format!("\t{}\n", text)),
+                }
+                index += instruction.size() as usize;
+                continue;
+            }
+
+            // data runs until the next instruction or label
+            let mut len = 1;
+            while len < 8
+                && index + len < self.rom.len()
+                && self.bytes[index + len] == Byte::Data
+                && self.label(addr + len as u16).is_none()
+            {
+                len += 1;
+            }
+            let data = &self.rom[index..index + len];
+            match syntax {
+                Syntax::Cowgod => {
+                    let data: Vec<String> = data.iter().map(|b| format!("0x{:02X}", b)).collect();
+                    out.push_str(&format!("    {:03X}: {:<8}  DB {}\n", addr, "", data.join(", ")));
+                }
+                Syntax::Octo => {
+                    let data: Vec<String> = data.iter().map(|b| format!("0x{:02X}", b)).collect();
+                    out.push_str(&format!("\t{}\n", data.join(" ")));
+                }
+            }
+            index += len;
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, Instruction, Syntax};
+    use crate::chip8::Variant;
+
+    #[test]
+    fn decode_and_format() {
+        let cases: [(u16, &str, &str); 10] = [
+            (0x00E0, "CLS", "clear"),
+            (0x00C4, "SCD 4", "scroll-down 4"),
+            (0x3A05, "SE VA, 0x05", "if va != 0x05 then"),
+            (0x5123, "LOAD V1-V2", "load v1 - v2"),
+            (0x8AB6, "SHR VA, VB", "va >>= vb"),
+            (0xA2F0, "LD I, 0x2F0", "i := 0x2F0"),
+            (0xD015, "DRW V0, V1, 5", "sprite v0 v1 5"),
+            (0xE3A1, "SKNP V3", "if v3 key then"),
+            (0xF265, "LD V2, [I]", "load v2"),
+            (0xFFFF, "DW 0xFFFF", "0xFF 0xFF"),
+        ];
+        for (opcode, cowgod, octo) in cases.iter() {
+            let instruction = Instruction::decode(*opcode);
+            assert_eq!(instruction.format(Syntax::Cowgod), *cowgod);
+            assert_eq!(instruction.format(Syntax::Octo), *octo);
+        }
+
+        assert_eq!(Instruction::decode(0x0123), Instruction::Sys { addr: 0x123 });
+        assert_eq!(Instruction::decode(0x5121), Instruction::Unknown(0x5121));
+        assert_eq!(Instruction::decode(0xF100), Instruction::Unknown(0xF100));
+        assert!(Instruction::decode(0x00FF).supported_by(Variant::SuperChip));
+        assert!(!Instruction::decode(0x00FF).supported_by(Variant::Chip8));
+        assert!(!Instruction::decode(0xF002).supported_by(Variant::SuperChip));
+    }
+
+    #[test]
+    fn encode_inverts_decode() {
+        for opcode in 0..=0xFFFF {
+            assert_eq!(Instruction::decode(opcode).encode(), opcode);
+        }
+    }
+
+    #[test]
+    fn code_and_data_are_separated() {
+        let rom = [
+            0x22, 0x08, // 200: call 208
+            0x3A, 0x01, // 202: skip if VA == 1
+            0x12, 0x02, // 204: jump 202
+            0x00, 0xFD, // 206: exit, an opcode plain CHIP-8 doesn't have
+            0xA2, 0x0E, // 208: I = 20E
+            0xD0, 0x11, // 20A: draw
+            0x00, 0xEE, // 20C: return
+            0x80, 0xFF, // 20E: sprite data
+        ];
+
+        let listing = disassemble(&rom, Variant::Chip8);
+        assert_eq!(
+            listing.listing(Syntax::Cowgod),
+            "main:\n\
+             \x20   200: 2208      CALL sub_208\n\
+             label_202:\n\
+             \x20   202: 3A01      SE VA, 0x01\n\
+             \x20   204: 1202      JP label_202\n\
+             \x20   206:           DB 0x00, 0xFD\n\
+             sub_208:\n\
+             \x20   208: A20E      LD I, 0x20E\n\
+             \x20   20A: D011      DRW V0, V1, 1\n\
+             \x20   20C: 00EE      RET\n\
+             \x20   20E:           DB 0x80, 0xFF\n"
+        );
+
+        // SUPER-CHIP has 00FD, which the skip reaches
+        let listing = disassemble(&rom, Variant::SuperChip);
+        assert!(listing.is_code(0x206));
+        assert_eq!(
+            listing.listing(Syntax::Octo),
+            ": main\n\
+             \tsub_208\n\
+             : label_202\n\
+             \tif va != 0x01 then\n\
+             \tjump label_202\n\
+             \texit\n\
+             : sub_208\n\
+             \ti := 0x20E\n\
+             \tsprite v0 v1 1\n\
+             \treturn\n\
+             \t0x80 0xFF\n"
+        );
+    }
+
+    #[test]
+    fn long_loads_are_four_bytes() {
+        // skip over the long load, then exit
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xFD];
+        let listing = disassemble(&rom, Variant::XoChip);
+        assert!(listing.is_code(0x202));
+        assert!(!listing.is_code(0x204));
+        assert!(listing.is_code(0x206));
+        assert!(listing.listing(Syntax::Octo).contains("\ti := long 0x1234\n"));
+    }
+}
@@ -0,0 +1,477 @@
+//! GDB remote serial protocol stub, so gdb or any RSP frontend can debug a running ROM.
+//!
+//! The registers are described by a custom target description (`target.xml`): V0-VF, I, PC,
+//! SP, DT and ST, numbered in that order and sent big-endian like everything else on a CHIP-8.
+//! Memory reads and writes go straight to `Chip8::memory`, breakpoints and watchpoints (`Z0` to
+//! `Z4`) are the core's own, see `Chip8::add_breakpoint`. Continuing runs at 60 frames per
+//! second until a breakpoint, an exit, a fault or a Ctrl-C from the debugger.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::chip8::{Access, Break, BreakpointId, Breakpoint, Chip8, Chip8Error, StepOutcome, Watchpoint};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// Number of registers in `TARGET_XML`
+const REGISTERS: usize = 21;
+
+/// The byte a debugger sends to interrupt a running target
+const INTERRUPT: u8 = 0x03;
+
+// signals in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// Debug `chip8` over `stream` until the debugger detaches, kills the target or disconnects
+pub fn serve(chip8: &mut Chip8, stream: TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut session = Session {
+        chip8,
+        writer: stream.try_clone()?,
+        reader: BufReader::new(stream),
+        no_ack: false,
+        swbreak: false,
+        breakpoints: HashMap::new(),
+    };
+    let result = session.run();
+    // the machine outlives the session, leave it without the debugger's breakpoints
+    for (_, id) in session.breakpoints.drain() {
+        session.chip8.remove_breakpoint(id);
+    }
+    result
+}
+
+struct Session<'a> {
+    chip8: &'a mut Chip8,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    // set by QStartNoAckMode
+    no_ack: bool,
+    // the debugger understands swbreak stop reasons
+    swbreak: bool,
+    // Z packet type and address to the core breakpoint
+    breakpoints: HashMap<(u8, u16), BreakpointId>,
+}
+
+impl Session<'_> {
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(&packet)? {
+                Some(reply) => self.write_packet(&reply)?,
+                None => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Payload of the next packet, `None` once the debugger hung up
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // acks, and interrupts that came in while already stopped
+            let mut byte = [0];
+            loop {
+                if self.reader.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+
+            let mut payload = Vec::new();
+            if self.reader.read_until(b'#', &mut payload)? == 0 || payload.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if self.no_ack {
+                return Ok(Some(String::from_utf8_lossy(&payload).into_owned()));
+            }
+            if expected == Some(sum(&payload)) {
+                self.writer.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&payload).into_owned()));
+            }
+            self.writer.write_all(b"-")?;
+        }
+    }
+
+    fn write_packet(&mut self, payload: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", payload, sum(payload.as_bytes()));
+        loop {
+            self.writer.write_all(packet.as_bytes())?;
+            if self.no_ack {
+                return Ok(());
+            }
+            let mut ack = [0];
+            if self.reader.read(&mut ack)? == 0 || ack[0] != b'-' {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reply to a packet, `None` ends the session
+    fn handle(&mut self, packet: &str) -> io::Result<Option<String>> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => self.registers().iter().map(|bytes| hex(bytes)).collect(),
+            "p" => match usize::from_str_radix(args, 16).ok().and_then(|n| self.registers().get(n).cloned()) {
+                Some(bytes) => hex(&bytes),
+                None => "E00".to_string(),
+            },
+            "m" => self.read_memory(args).unwrap_or_else(|| "E01".to_string()),
+            "M" => self.write_memory(args).unwrap_or_else(|| "E01".to_string()),
+            "s" => match self.chip8.debug_step() {
+                Ok(StepOutcome::Break(hit)) => self.stop_reply(hit),
+                Ok(StepOutcome::Exited) => "W00".to_string(),
+                Ok(_) => format!("S{:02x}", SIGTRAP),
+                Err(e) => fault_reply(&e),
+            },
+            "c" => self.resume()?,
+            "Z" => self.set_breakpoint(args, true).unwrap_or_else(|| "E01".to_string()),
+            "z" => self.set_breakpoint(args, false).unwrap_or_else(|| "E01".to_string()),
+            "H" => "OK".to_string(),
+            "D" => {
+                self.write_packet("OK")?;
+                return Ok(None);
+            }
+            "k" => return Ok(None),
+            _ => self.query(packet),
+        };
+        Ok(Some(reply))
+    }
+
+    /// General queries and settings, an empty reply means unsupported
+    fn query(&mut self, packet: &str) -> String {
+        if let Some(features) = packet.strip_prefix("qSupported") {
+            self.swbreak = features.split([':', ';']).any(|feature| feature == "swbreak+");
+            return "PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, length) = match parse_range(range) {
+                Some(range) => range,
+                None => return "E00".to_string(),
+            };
+            let start = offset.min(TARGET_XML.len());
+            let end = (offset + length).min(TARGET_XML.len());
+            let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+            return format!("{}{}", more, &TARGET_XML[start..end]);
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// Register values in `TARGET_XML` order
+    fn registers(&self) -> Vec<Vec<u8>> {
+        let chip8 = &self.chip8;
+        let mut registers: Vec<Vec<u8>> = chip8.registers().iter().map(|v| vec![*v]).collect();
+        registers.push(chip8.index_register().to_be_bytes().to_vec());
+        registers.push(chip8.program_counter().to_be_bytes().to_vec());
+        registers.push(vec![chip8.stack_pointer()]);
+        registers.push(vec![chip8.delay_timer()]);
+        registers.push(vec![chip8.sound_timer()]);
+        debug_assert_eq!(registers.len(), REGISTERS);
+        registers
+    }
+
+    /// `addr,length`, reads past the end of memory are cut short
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (address, length) = parse_range(args)?;
+        let memory = self.chip8.memory();
+        let end = address.checked_add(length)?;
+        if address >= memory.len() {
+            return None;
+        }
+        Some(hex(&memory[address..end.min(memory.len())]))
+    }
+
+    /// `addr,length:XX...`
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let (range, data) = args.split_once(':')?;
+        let (address, length) = parse_range(range)?;
+        let bytes = unhex(data)?;
+        let memory = self.chip8.memory_mut();
+        let end = address.checked_add(length)?;
+        if bytes.len() != length || end > memory.len() {
+            return None;
+        }
+        memory[address..end].copy_from_slice(&bytes);
+        Some("OK".to_string())
+    }
+
+    /// `type,addr,kind` of a Z or z packet. Types 0 and 1 are breakpoints, 2 to 4 are write, read
+    /// and access watchpoints of `kind` bytes
+    fn set_breakpoint(&mut self, args: &str, insert: bool) -> Option<String> {
+        let mut fields = args.split(',');
+        let kind: u8 = fields.next()?.parse().ok()?;
+        let address = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let len = u16::from_str_radix(fields.next()?, 16).ok()?;
+
+        if !insert {
+            if let Some(id) = self.breakpoints.remove(&(kind, address)) {
+                self.chip8.remove_breakpoint(id);
+            }
+            return Some("OK".to_string());
+        }
+        if self.breakpoints.contains_key(&(kind, address)) {
+            return Some("OK".to_string());
+        }
+        let id = match kind {
+            0 | 1 => self.chip8.add_breakpoint(Breakpoint::Pc(address)),
+            2..=4 => {
+                let access = [Access::Write, Access::Read, Access::ReadWrite][kind as usize - 2];
+                self.chip8.add_watchpoint(Watchpoint { start: address, len: len.max(1), access })
+            }
+            _ => return Some(String::new()),
+        };
+        self.breakpoints.insert((kind, address), id);
+        Some("OK".to_string())
+    }
+
+    /// Run at 60 frames per second until something stops the machine, returns the stop reply
+    fn resume(&mut self) -> io::Result<String> {
+        let frame_time = Duration::from_secs(1) / 60;
+        let mut next_frame = Instant::now();
+        loop {
+            if self.interrupted()? {
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+            match self.chip8.run_frame() {
+                Ok(StepOutcome::Break(hit)) => return Ok(self.stop_reply(hit)),
+                Ok(StepOutcome::Exited) => return Ok("W00".to_string()),
+                Ok(_) => {}
+                Err(e) => return Ok(fault_reply(&e)),
+            }
+
+            next_frame += frame_time;
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            }
+        }
+    }
+
+    /// Whether the debugger sent an interrupt, without waiting for one
+    fn interrupted(&mut self) -> io::Result<bool> {
+        if self.reader.buffer().is_empty() {
+            self.reader.get_ref().set_nonblocking(true)?;
+            let filled = self.reader.fill_buf().map(|bytes| bytes.len());
+            self.reader.get_ref().set_nonblocking(false)?;
+            match filled {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+        match self.reader.buffer().first() {
+            Some(&INTERRUPT) => {
+                self.reader.consume(1);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn stop_reply(&self, hit: Break) -> String {
+        match hit {
+            Break::Watchpoint { id, address, .. } => {
+                let access = self.chip8.watchpoints().find(|(other, _)| *other == id).map(|(_, watchpoint)| watchpoint.access);
+                let reason = match access {
+                    Some(Access::Read) => "rwatch",
+                    Some(Access::ReadWrite) => "awatch",
+                    _ => "watch",
+                };
+                format!("T{:02x}{}:{:x};", SIGTRAP, reason, address)
+            }
+            Break::Breakpoint { .. } if self.swbreak => format!("T{:02x}swbreak:;", SIGTRAP),
+            _ => format!("S{:02x}", SIGTRAP),
+        }
+    }
+}
+
+/// Stop reply for a fault: unknown opcodes are illegal instructions, the rest are bad accesses
+fn fault_reply(error: &Chip8Error) -> String {
+    let signal = match error {
+        Chip8Error::UnknownOpcode { .. } => SIGILL,
+        _ => SIGSEGV,
+    };
+    format!("S{:02x}", signal)
+}
+
+/// `addr,length` in hex
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    Some((usize::from_str_radix(address, 16).ok()?, usize::from_str_radix(length, 16).ok()?))
+}
+
+fn sum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Hex digit pairs into bytes, `None` for an odd number of digits
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use super::{serve, sum};
+    use crate::chip8::Chip8;
+
+    const ROM: [u8; 8] = [
+        0x60, 0x07, // 200: V0 = 7
+        0xA3, 0x00, // 202: I = 0x300
+        0xF0, 0x55, // 204: store V0 at 0x300
+        0x12, 0x06, // 206: jump 0x206
+    ];
+
+    /// A debugger connected to a stub serving a machine loaded with `ROM`
+    fn connect() -> (TcpStream, thread::JoinHandle<Chip8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut chip8 = Chip8::new();
+            chip8.reset_and_load_bytes(&ROM).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            serve(&mut chip8, stream).unwrap();
+            chip8
+        });
+        (TcpStream::connect(address).unwrap(), server)
+    }
+
+    /// Send a packet and return the reply, with acks
+    fn request(stream: &mut TcpStream, payload: &str) -> String {
+        write!(stream, "${}#{:02x}", payload, sum(payload.as_bytes())).unwrap();
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'+');
+
+        stream.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'$');
+        let mut reply = Vec::new();
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            reply.push(byte[0]);
+        }
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum).unwrap();
+        assert_eq!(std::str::from_utf8(&checksum).unwrap(), format!("{:02x}", sum(&reply)));
+        stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let (mut gdb, server) = connect();
+        assert!(request(&mut gdb, "qSupported:swbreak+").contains("qXfer:features:read+"));
+        let xml = request(&mut gdb, "qXfer:features:read:target.xml:0,fff");
+        assert!(xml.starts_with("l<?xml") && xml.contains(r#"<reg name="pc" bitsize="16" type="code_ptr"/>"#));
+        assert_eq!(request(&mut gdb, "?"), "S05");
+
+        // V0-VF, I, PC, SP, DT, ST
+        assert_eq!(request(&mut gdb, "g"), format!("{}00000200000000", "00".repeat(16)));
+        assert_eq!(request(&mut gdb, "s"), "S05");
+        assert_eq!(request(&mut gdb, "p0"), "07");
+        assert_eq!(request(&mut gdb, "p11"), "0202");
+
+        assert_eq!(request(&mut gdb, "m200,4"), "6007a300");
+        assert_eq!(request(&mut gdb, "M300,2:beef"), "OK");
+        assert_eq!(request(&mut gdb, "m300,2"), "beef");
+        assert_eq!(request(&mut gdb, "mfff,8"), "00");
+        assert_eq!(request(&mut gdb, "m1000,1"), "E01");
+        assert_eq!(request(&mut gdb, "m1,ffffffffffffffff"), "E01");
+        assert_eq!(request(&mut gdb, "M1,ffffffffffffffff:00"), "E01");
+        assert_eq!(request(&mut gdb, "vMustReplyEmpty"), "");
+
+        write!(gdb, "$k#6b").unwrap();
+        let chip8 = server.join().unwrap();
+        assert_eq!(&chip8.memory()[0x300..0x302], &[0xBE, 0xEF]);
+    }
+
+    #[test]
+    fn breakpoints_and_continue() {
+        let (mut gdb, server) = connect();
+        request(&mut gdb, "qSupported:swbreak+");
+
+        assert_eq!(request(&mut gdb, "Z0,204,2"), "OK");
+        assert_eq!(request(&mut gdb, "c"), "T05swbreak:;");
+        assert_eq!(request(&mut gdb, "p11"), "0204");
+        assert_eq!(request(&mut gdb, "z0,204,2"), "OK");
+
+        // the store hits a write watchpoint, the PC is past it already
+        assert_eq!(request(&mut gdb, "Z2,300,1"), "OK");
+        assert_eq!(request(&mut gdb, "c"), "T05watch:300;");
+        assert_eq!(request(&mut gdb, "p11"), "0206");
+
+        // the store only runs once, nothing stops the jump loop now but an interrupt
+        assert_eq!(request(&mut gdb, "QStartNoAckMode"), "OK");
+        write!(gdb, "$c#63").unwrap();
+        gdb.write_all(&[0x03]).unwrap();
+        let mut reply = [0; 7];
+        gdb.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"$S02#b5");
+
+        write!(gdb, "$D#44").unwrap();
+        let mut reply = [0; 6];
+        gdb.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"$OK#9a");
+        // the watchpoint left behind goes away with the session
+        let chip8 = server.join().unwrap();
+        assert_eq!(chip8.watchpoints().count(), 0);
+    }
+}
@@ -0,0 +1,215 @@
+//! Running a ROM without a screen or a clock, for scripts and CI.
+//!
+//! Frames run back to back, so a run takes the same path through the ROM every time. Keys come
+//! from an input script and the final screen can be written out as text or PBM and hashed.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chip8::{Break, Chip8, Chip8Error, StepOutcome};
+
+/// Key changes by frame, parsed from lines like `30 down 5` and `45 up 5`.
+/// The frame number counts from 0, a change applies before that frame runs.
+/// Blank lines and everything after a `#` are ignored
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputScript {
+    // frame, key, pressed; sorted by frame
+    events: Vec<(u32, u8, bool)>,
+}
+
+impl InputScript {
+    /// The changes that apply before `frame`, in script order
+    pub fn at(&self, frame: u32) -> impl Iterator<Item = (u8, bool)> + '_ {
+        self.events.iter().filter(move |(at, _, _)| *at == frame).map(|(_, key, down)| (*key, *down))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+impl FromStr for InputScript {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut events = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad = || format!("line {}: expected `<frame> down|up <key>`, got `{}`", number + 1, line);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [frame, action, key] = fields[..] else {
+                return Err(bad());
+            };
+            let frame: u32 = frame.parse().map_err(|_| bad())?;
+            let down = match action {
+                "down" => true,
+                "up" => false,
+                _ => return Err(bad()),
+            };
+            let key = u8::from_str_radix(key, 16).ok().filter(|key| *key <= 0xF).ok_or_else(bad)?;
+            events.push((frame, key, down));
+        }
+        // stable, so changes within a frame keep their order
+        events.sort_by_key(|(frame, _, _)| *frame);
+        Ok(InputScript { events })
+    }
+}
+
+/// Why a headless run ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunEnd {
+    /// All the frames ran
+    Frames,
+    /// The ROM quit with 00FD
+    Exited,
+    /// A breakpoint or watchpoint fired
+    Break(Break),
+}
+
+impl fmt::Display for RunEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunEnd::Frames => write!(f, "all frames ran"),
+            RunEnd::Exited => write!(f, "the ROM exited"),
+            RunEnd::Break(hit) => write!(f, "{}", hit),
+        }
+    }
+}
+
+/// Run up to `frames` frames (forever when `None`) feeding keys from `input`.
+/// Returns how the run ended and the number of frames that ran
+pub fn run(chip8: &mut Chip8, frames: Option<u32>, input: &InputScript) -> Result<(RunEnd, u32), Chip8Error> {
+    run_with(chip8, frames, input, |_| {})
+}
+
+/// `run`, calling `after_frame` after every frame that ran to its end, e.g. to record its sound
+pub fn run_with(
+    chip8: &mut Chip8,
+    frames: Option<u32>,
+    input: &InputScript,
+    mut after_frame: impl FnMut(&mut Chip8),
+) -> Result<(RunEnd, u32), Chip8Error> {
+    let mut frame = 0;
+    while frames.is_none_or(|frames| frame < frames) {
+        for (key, down) in input.at(frame) {
+            if down {
+                chip8.key_down(key);
+            } else {
+                chip8.key_up(key);
+            }
+        }
+        match chip8.run_frame()? {
+            StepOutcome::Exited => {
+                after_frame(chip8);
+                return Ok((RunEnd::Exited, frame + 1));
+            }
+            StepOutcome::Break(hit) => return Ok((RunEnd::Break(hit), frame)),
+            _ => {
+                after_frame(chip8);
+                frame += 1;
+            }
+        }
+    }
+    Ok((RunEnd::Frames, frame))
+}
+
+/// The screen as text, one line per row: `.` is off and `#` is on. XO-CHIP pixels only on the
+/// second bitplane are `+`, pixels on both are `@`
+pub fn screen_text(chip8: &Chip8) -> String {
+    let mut text = String::with_capacity((chip8.width() + 1) * chip8.height());
+    for row in chip8.export_video().chunks(chip8.width()) {
+        text.extend(row.iter().map(|pixel| match pixel & 3 {
+            0 => '.',
+            1 => '#',
+            2 => '+',
+            _ => '@',
+        }));
+        text.push('\n');
+    }
+    text
+}
+
+/// The screen as a plain (P1) PBM image, any lit bitplane is black
+pub fn screen_pbm(chip8: &Chip8) -> String {
+    let mut pbm = format!("P1\n{} {}\n", chip8.width(), chip8.height());
+    for row in chip8.export_video().chunks(chip8.width()) {
+        let row: Vec<&str> = row.iter().map(|pixel| if *pixel != 0 { "1" } else { "0" }).collect();
+        pbm.push_str(&row.join(" "));
+        pbm.push('\n');
+    }
+    pbm
+}
+
+/// 64-bit FNV-1a of the screen size and pixels, equal hashes mean equal screens
+pub fn screen_hash(chip8: &Chip8) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let size = [chip8.width() as u8, chip8.height() as u8];
+    let pixels = chip8.export_video().iter().map(|pixel| *pixel as u8);
+    for byte in size.iter().copied().chain(pixels) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, screen_hash, screen_pbm, screen_text, InputScript, RunEnd};
+    use crate::chip8::{Chip8, Variant};
+
+    // draws the digit of the key held down at 0,0
+    const ROM: [u8; 12] = [
+        0xF0, 0x0A, // 200: V0 = wait for a key
+        0xF0, 0x29, // 202: I = font(V0)
+        0x00, 0xE0, // 204: clear
+        0x61, 0x00, // 206: V1 = 0
+        0xD1, 0x15, // 208: draw at V1, V1
+        0x12, 0x00, // 20A: jump 0x200
+    ];
+
+    #[test]
+    fn scripts() {
+        let script: InputScript = "# press 5 for two frames\n2 down 5\n4 up 5  # released\n\n1 down a\n".parse().unwrap();
+        assert_eq!(script.at(1).collect::<Vec<_>>(), [(0xA, true)]);
+        assert_eq!(script.at(4).collect::<Vec<_>>(), [(5, false)]);
+        assert_eq!(script.at(3).count(), 0);
+
+        assert!("2 down".parse::<InputScript>().is_err());
+        assert!("2 hold 5".parse::<InputScript>().is_err());
+        assert!("2 down 10".parse::<InputScript>().is_err());
+    }
+
+    #[test]
+    fn runs_are_repeatable() {
+        let script: InputScript = "3 down 7\n5 up 7\n".parse().unwrap();
+        let mut hashes = Vec::new();
+        for _ in 0..2 {
+            let mut chip8 = Chip8::new();
+            chip8.reset_and_load_bytes(&ROM).unwrap();
+            assert_eq!(run(&mut chip8, Some(10), &script), Ok((RunEnd::Frames, 10)));
+            assert_eq!(chip8.registers()[0], 7);
+            hashes.push(screen_hash(&chip8));
+
+            let text = screen_text(&chip8);
+            assert_eq!(text.lines().count(), 32);
+            assert!(text.starts_with("####...."));
+            let pbm = screen_pbm(&chip8);
+            assert!(pbm.starts_with("P1\n64 32\n1 1 1 1 0 0"));
+        }
+        assert_eq!(hashes[0], hashes[1]);
+
+        let mut blank = Chip8::new();
+        blank.reset_and_load_bytes(&ROM).unwrap();
+        assert_ne!(screen_hash(&blank), hashes[0]);
+    }
+
+    #[test]
+    fn stops_when_the_rom_exits() {
+        let mut chip8 = Chip8::with_variant(Variant::SuperChip);
+        chip8.reset_and_load_bytes(&[0x00, 0xFD]).unwrap();
+        assert_eq!(run(&mut chip8, None, &InputScript::default()), Ok((RunEnd::Exited, 1)));
+    }
+}
@@ -1,5 +1,15 @@
+pub mod asm;
 pub mod chip8;
+pub mod disasm;
+pub mod headless;
+pub mod quirks;
 
+#[cfg(feature = "cli")]
+pub mod audio;
+#[cfg(feature = "cli")]
+pub mod dap;
+#[cfg(feature = "cli")]
+pub mod gdb;
 #[cfg(feature = "cli")]
 pub mod platform;
 
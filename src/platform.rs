@@ -1,13 +1,15 @@
 #![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
 use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
-use crate::chip8::Chip8;
+use crate::chip8::{Breakpoint, Chip8};
+use crate::disasm::Instruction;
 
 use ratatui::{
     buffer::Buffer,
     layout::Rect,
     style::{Color, Style},
-    text::Line,
+    text::{Line, Span},
     widgets::{Block, Borders, Paragraph, Widget, Wrap},
 };
 
@@ -45,29 +47,161 @@ pub fn map_pc_to_chip8(code: KeyCode) -> Option<u8> {
     }
 }
 
+/// Emulator controls that aren't mapped to the CHIP-8 keypad
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostAction {
+    Quit,
+    SaveState,
+    LoadState,
+    PrevSlot,
+    NextSlot,
+    /// Sent again while the key is held, through the terminal's key repeat
+    Rewind,
+    // debugger
+    Pause,
+    Step,
+    StepOver,
+    RunToCursor,
+    ToggleBreakpoint,
+    CursorUp,
+    CursorDown,
+    MemoryUp,
+    MemoryDown,
+    MemoryToI,
+}
+
+impl HostAction {
+    /// Whether holding the key repeats the action
+    pub fn repeats(self) -> bool {
+        use HostAction::*;
+        matches!(self, Rewind | Step | StepOver | CursorUp | CursorDown | MemoryUp | MemoryDown)
+    }
+}
+
+pub fn map_host_key(code: KeyCode) -> Option<HostAction> {
+    match code {
+        KeyCode::Esc | KeyCode::Char('Q') => Some(HostAction::Quit),
+        KeyCode::F(5) => Some(HostAction::SaveState),
+        KeyCode::F(9) => Some(HostAction::LoadState),
+        KeyCode::F(6) => Some(HostAction::PrevSlot),
+        KeyCode::F(7) => Some(HostAction::NextSlot),
+        KeyCode::Backspace => Some(HostAction::Rewind),
+        // the letters the keypad doesn't use
+        KeyCode::Char('p') | KeyCode::Char('P') => Some(HostAction::Pause),
+        KeyCode::Char('n') | KeyCode::Char('N') => Some(HostAction::Step),
+        KeyCode::Char('o') | KeyCode::Char('O') => Some(HostAction::StepOver),
+        KeyCode::Char('g') | KeyCode::Char('G') => Some(HostAction::RunToCursor),
+        KeyCode::Char('b') | KeyCode::Char('B') => Some(HostAction::ToggleBreakpoint),
+        KeyCode::Char('i') | KeyCode::Char('I') => Some(HostAction::MemoryToI),
+        KeyCode::Up => Some(HostAction::CursorUp),
+        KeyCode::Down => Some(HostAction::CursorDown),
+        KeyCode::PageUp => Some(HostAction::MemoryUp),
+        KeyCode::PageDown => Some(HostAction::MemoryDown),
+        _ => None,
+    }
+}
+
+// ---------- Held keys ----------
+
+/// Without release events a key counts as held this long after it was pressed by default, enough
+/// to bridge the delay before the terminal starts repeating it (660 ms by default on X11)
+pub const HOLD_AFTER_PRESS: Duration = Duration::from_millis(700);
+/// and this long after each repeat
+pub const HOLD_AFTER_REPEAT: Duration = Duration::from_millis(100);
+
+/// The CHIP-8 keys held on the host keyboard, passed on to the machine with `key_down`/`key_up`.
+/// Terminals speaking the kitty keyboard protocol report releases, the others only send presses
+/// and key repeats: there a key is released when its events stop for a while
+pub struct Keyboard {
+    releases: bool,
+    hold: Duration,
+    // held keys, with the time they're released unless another event comes first
+    held: [Option<Instant>; 16],
+}
+
+impl Keyboard {
+    /// `releases` tells whether the terminal reports key releases
+    pub fn new(releases: bool) -> Self {
+        Keyboard { releases, hold: HOLD_AFTER_PRESS, held: [None; 16] }
+    }
+
+    pub fn releases(&self) -> bool { self.releases }
+
+    /// How long a new press holds the key without release events. Shorter keeps quick taps short,
+    /// but below the terminal's key repeat delay a held key is let go and pressed again
+    pub fn set_hold(&mut self, hold: Duration) {
+        self.hold = hold;
+    }
+
+    /// A press or a repeat of `key`, returns whether it was a new press
+    pub fn press(&mut self, chip8: &mut Chip8, key: u8, now: Instant) -> bool {
+        let held = &mut self.held[key as usize];
+        let pressed = held.is_none();
+        let until = now + if pressed { self.hold } else { HOLD_AFTER_REPEAT };
+        // an early repeat doesn't cut the first hold short
+        *held = Some(held.map_or(until, |held| held.max(until)));
+        chip8.key_down(key);
+        pressed
+    }
+
+    pub fn release(&mut self, chip8: &mut Chip8, key: u8) {
+        self.held[key as usize] = None;
+        chip8.key_up(key);
+    }
+
+    /// Release the keys whose events stopped, only without release events. Returns the released keys
+    pub fn expire(&mut self, chip8: &mut Chip8, now: Instant) -> Vec<u8> {
+        if self.releases {
+            return Vec::new();
+        }
+        let expired: Vec<u8> = (0..16).filter(|key| self.held[*key as usize].is_some_and(|until| until <= now)).collect();
+        for key in &expired {
+            self.release(chip8, *key);
+        }
+        expired
+    }
+
+    /// Make the keypad match the held keys again, after a save state or a rewind replaced it
+    pub fn sync(&self, chip8: &mut Chip8) {
+        for (key, held) in self.held.iter().enumerate() {
+            if held.is_some() {
+                chip8.key_down(key as u8);
+            } else {
+                chip8.key_up(key as u8);
+            }
+        }
+    }
+}
+
+/// Feed pending terminal events to the machine and return the host actions that were pressed
 pub fn pump_input(
     chip8: &mut Chip8,
-    frame_keys: &mut [u8; 16],
+    keyboard: &mut Keyboard,
     logs: &mut LogBuf,
-) -> Result<bool, Box<dyn std::error::Error>> {
+) -> Result<Vec<HostAction>, Box<dyn std::error::Error>> {
+    let mut actions = Vec::new();
     while event::poll(std::time::Duration::from_millis(0))? {
         match event::read()? {
             Event::Key(KeyEvent { code, kind, .. }) => {
-                // exit keys
-                if code == KeyCode::Esc || code == KeyCode::Char('Q') {
-                    return Ok(true);
+                if let Some(action) = map_host_key(code) {
+                    let repeat = kind == KeyEventKind::Repeat && action.repeats();
+                    if kind == KeyEventKind::Press || repeat {
+                        actions.push(action);
+                    }
+                    continue;
                 }
                 if let Some(k) = map_pc_to_chip8(code) {
                     match kind {
                         KeyEventKind::Press | KeyEventKind::Repeat => {
-                            frame_keys[k as usize] = 1;
-                            if chip8.keypad[k as usize] == 0 {
-                                chip8.key_down(k);
+                            if keyboard.press(chip8, k, Instant::now()) {
+                                logs.push(format!("key {:X} down", k));
                             }
-                            logs.push(format!("key {:X} down", k));
                         }
                         KeyEventKind::Release => {
-                            // we clear keys each frame anyway
+                            // some terminals report releases without being asked to, e.g. on Windows
+                            keyboard.releases = true;
+                            keyboard.release(chip8, k);
+                            logs.push(format!("key {:X} up", k));
                         }
                     }
                 }
@@ -79,33 +213,154 @@ pub fn pump_input(
             _ => {}
         }
     }
-    Ok(false)
+    for k in keyboard.expire(chip8, Instant::now()) {
+        logs.push(format!("key {:X} up", k));
+    }
+    Ok(actions)
 }
 
+/// Colours for each combination of the two XO-CHIP bitplanes: off, first plane, second plane, both.
+/// CHIP-8 and SUPER-CHIP only ever use the first two
+pub const PALETTE: [Color; 4] = [
+    Color::Black,
+    Color::White,
+    Color::Rgb(0xFF, 0x66, 0x00),
+    Color::Rgb(0x66, 0x22, 0x00),
+];
+
 pub struct Chip8Screen<'a> {
-    pub video: &'a [u32; 64 * 32], // nonzero = ON
-    pub on: Color,
-    pub off: Color,
+    pub video: &'a [u32], // bitplanes of each pixel, row by row
+    pub width: usize,
+    pub height: usize,
+    pub palette: [Color; 4],
 }
 impl<'a> Widget for Chip8Screen<'a> {
     fn render(self, area: Rect, buf: &mut Buffer) {
         if area.width == 0 || area.height == 0 { return; }
         for ty in 0..area.height {
-            let sy = (ty as u32 * 32) / area.height as u32; // 0..31
+            let sy = (ty as usize * self.height) / area.height as usize; // 0..height-1
             for tx in 0..area.width {
-                let sx = (tx as u32 * 64) / area.width as u32; // 0..63
-                let idx = (sy * 64 + sx) as usize;
-                let on = self.video[idx] != 0;
+                let sx = (tx as usize * self.width) / area.width as usize; // 0..width-1
+                let idx = sy * self.width + sx;
+                let colour = self.palette[(self.video[idx] & 0x3) as usize];
 
                 let cell = &mut buf[(area.x + tx, area.y + ty)];
 
                 cell.set_symbol(" ");
-                cell.set_style(Style::default().bg(if on { self.on } else { self.off }));
+                cell.set_style(Style::default().bg(colour));
             }
         }
     }
 }
 
+// ---------- Debugger panels ----------
+
+const VALUE: Style = Style::new().fg(Color::Yellow);
+const CURRENT: Style = Style::new().fg(Color::Black).bg(Color::Yellow);
+const SELECTED: Style = Style::new().bg(Color::DarkGray);
+
+/// V0-VF, I, PC, SP, both timers, the stack and the keypad
+pub fn registers_panel(chip8: &Chip8) -> Paragraph<'static> {
+    let mut lines = Vec::new();
+    for (row, values) in chip8.registers().chunks(4).enumerate() {
+        let mut spans = Vec::new();
+        for (col, value) in values.iter().enumerate() {
+            spans.push(Span::raw(format!("V{:X} ", row * 4 + col)));
+            spans.push(Span::styled(format!("{:02X}", value), VALUE));
+            spans.push(Span::raw("  "));
+        }
+        lines.push(Line::from(spans));
+    }
+
+    lines.push(Line::from(vec![
+        Span::raw("I  "),
+        Span::styled(format!("{:04X}", chip8.index_register()), VALUE),
+        Span::raw("  PC "),
+        Span::styled(format!("{:04X}", chip8.program_counter()), VALUE),
+        Span::raw("  SP "),
+        Span::styled(format!("{:X}", chip8.stack_pointer()), VALUE),
+    ]));
+    lines.push(Line::from(vec![
+        Span::raw("DT "),
+        Span::styled(format!("{:02X}", chip8.delay_timer()), VALUE),
+        Span::raw("    ST "),
+        Span::styled(format!("{:02X}", chip8.sound_timer()), VALUE),
+    ]));
+
+    let stack: Vec<String> = chip8.stack().iter().map(|address| format!("{:03X}", address)).collect();
+    let stack = if stack.is_empty() { "-".to_string() } else { stack.join(" ") };
+    lines.push(Line::from(vec![Span::raw("stack "), Span::styled(stack, VALUE)]));
+
+    let mut keys = vec![Span::raw("keys  ")];
+    for (key, down) in chip8.keypad.iter().enumerate() {
+        let style = if *down != 0 { CURRENT } else { Style::default() };
+        keys.push(Span::styled(format!("{:X}", key), style));
+    }
+    lines.push(Line::from(keys));
+
+    Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("registers"))
+}
+
+/// `rows` instructions around `cursor`, the instruction at PC is highlighted and breakpoints are marked with `*`
+pub fn disasm_panel(chip8: &Chip8, cursor: u16, rows: u16) -> Paragraph<'static> {
+    let memory = chip8.memory();
+    let pc = chip8.program_counter() as usize;
+    let cursor = cursor as usize;
+
+    // keep a third of the rows above the cursor, on the same byte parity
+    let mut address = cursor.saturating_sub(2 * (rows as usize / 3));
+    let mut lines = Vec::new();
+    while lines.len() < rows as usize && address + 1 < memory.len() {
+        let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+        let instruction = Instruction::decode(opcode);
+        let mut text = instruction.to_string();
+        if instruction == Instruction::LoadILong && address + 3 < memory.len() {
+            text.push_str(&format!(" 0x{:02X}{:02X}", memory[address + 2], memory[address + 3]));
+        }
+
+        let breakpoint = chip8.breakpoints().any(|(_, breakpoint)| *breakpoint == Breakpoint::Pc(address as u16));
+        let marker = match (breakpoint, address == pc) {
+            (true, true) => "*>",
+            (true, false) => "* ",
+            (false, true) => "> ",
+            (false, false) => "  ",
+        };
+        let line = format!("{}{:03X} {:04X}  {}", marker, address, opcode, text);
+        let style = match (address == pc, address == cursor) {
+            (true, _) => CURRENT,
+            (false, true) => SELECTED,
+            (false, false) => Style::default(),
+        };
+        lines.push(Line::styled(line, style));
+        address += instruction.size() as usize;
+    }
+
+    Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("disassembly"))
+}
+
+/// Hex dump of `rows` lines of 8 bytes from `start`, the bytes at I are highlighted
+pub fn memory_panel(chip8: &Chip8, start: usize, rows: u16) -> Paragraph<'static> {
+    let memory = chip8.memory();
+    let i = chip8.index_register() as usize;
+
+    let mut lines = Vec::new();
+    for row in 0..rows as usize {
+        let address = start + row * 8;
+        if address >= memory.len() {
+            break;
+        }
+        let mut spans = vec![Span::raw(format!("{:04X} ", address))];
+        for (offset, byte) in memory[address..(address + 8).min(memory.len())].iter().enumerate() {
+            let style = if address + offset == i { CURRENT } else { Style::default() };
+            spans.push(Span::raw(" "));
+            spans.push(Span::styled(format!("{:02X}", byte), style));
+        }
+        lines.push(Line::from(spans));
+    }
+
+    Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("memory"))
+}
+
 pub struct LogBuf {
     lines: VecDeque<String>,
     cap: usize,
@@ -131,8 +386,9 @@ impl LogBuf {
 }
 
 pub fn fit_chip8_top_left(area: Rect) -> Rect {
+    // 64x32 and 128x64 are both 2:1
     let aspect_w = 64.0f32;
-    let aspect_h = 32.0f32; // 2:1
+    let aspect_h = 32.0f32;
     let want = aspect_w / aspect_h;
 
     let aw = area.width.max(1) as f32;
@@ -152,3 +408,92 @@ pub fn fit_chip8_top_left(area: Rect) -> Rect {
         height: h as u16,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Keyboard, HOLD_AFTER_PRESS, HOLD_AFTER_REPEAT};
+    use crate::chip8::Chip8;
+
+    #[test]
+    fn keys_without_release_events_time_out() {
+        let mut chip8 = Chip8::new();
+        let mut keyboard = Keyboard::new(false);
+        let start = Instant::now();
+
+        assert!(keyboard.press(&mut chip8, 5, start));
+        assert!(chip8.is_key_down(5));
+        // held through the delay before the key repeat starts
+        assert!(keyboard.expire(&mut chip8, start + HOLD_AFTER_PRESS - Duration::from_millis(1)).is_empty());
+        let repeat = start + HOLD_AFTER_PRESS - Duration::from_millis(50);
+        assert!(!keyboard.press(&mut chip8, 5, repeat));
+        assert!(keyboard.expire(&mut chip8, start + HOLD_AFTER_PRESS).is_empty());
+        assert!(chip8.is_key_down(5));
+
+        // let go once the repeats stop
+        assert_eq!(keyboard.expire(&mut chip8, repeat + HOLD_AFTER_REPEAT), [5]);
+        assert!(!chip8.is_key_down(5));
+        // one press edge for the whole hold
+        assert_eq!(chip8.take_recent_press(), Some(5));
+        assert_eq!(chip8.take_recent_press(), None);
+    }
+
+    #[test]
+    fn keys_with_release_events_stay_down() {
+        let mut chip8 = Chip8::new();
+        let mut keyboard = Keyboard::new(true);
+        let start = Instant::now();
+
+        keyboard.press(&mut chip8, 0xA, start);
+        assert!(keyboard.expire(&mut chip8, start + Duration::from_secs(10)).is_empty());
+        assert!(chip8.is_key_down(0xA));
+        keyboard.release(&mut chip8, 0xA);
+        assert!(!chip8.is_key_down(0xA));
+    }
+
+    #[test]
+    fn sync_after_restoring_a_state() {
+        let mut chip8 = Chip8::new();
+        let mut keyboard = Keyboard::new(true);
+        keyboard.press(&mut chip8, 1, Instant::now());
+        let state = chip8.save_state();
+        keyboard.release(&mut chip8, 1);
+        keyboard.press(&mut chip8, 2, Instant::now());
+
+        chip8.load_state(&state).unwrap();
+        assert!(chip8.is_key_down(1));
+        keyboard.sync(&mut chip8);
+        assert!(!chip8.is_key_down(1));
+        assert!(chip8.is_key_down(2));
+    }
+
+    #[test]
+    fn shorter_hold() {
+        let mut chip8 = Chip8::new();
+        let mut keyboard = Keyboard::new(false);
+        keyboard.set_hold(Duration::from_millis(100));
+        let start = Instant::now();
+
+        keyboard.press(&mut chip8, 3, start);
+        assert!(keyboard.expire(&mut chip8, start + Duration::from_millis(99)).is_empty());
+        assert_eq!(keyboard.expire(&mut chip8, start + Duration::from_millis(100)), [3]);
+        assert!(!chip8.is_key_down(3));
+    }
+
+    #[test]
+    fn slow_first_repeat_is_one_press() {
+        // the X11 default repeat delay, a release in between would count twice in FX0A
+        let mut chip8 = Chip8::new();
+        let mut keyboard = Keyboard::new(false);
+        let start = Instant::now();
+        let repeat = start + Duration::from_millis(660);
+
+        keyboard.press(&mut chip8, 7, start);
+        assert!(keyboard.expire(&mut chip8, repeat).is_empty());
+        assert!(!keyboard.press(&mut chip8, 7, repeat));
+        assert!(keyboard.expire(&mut chip8, repeat + Duration::from_millis(30)).is_empty());
+        assert_eq!(chip8.take_recent_press(), Some(7));
+        assert_eq!(chip8.take_recent_press(), None);
+    }
+}
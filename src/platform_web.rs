@@ -2,14 +2,95 @@ use std::cell::{RefCell, Cell};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{window, HtmlCanvasElement, CanvasRenderingContext2d};
+use web_sys::{AudioContext, AudioContextState, GainNode};
 
-use crate::chip8::Chip8;
+use crate::chip8::{Breakpoint, BreakpointId, Chip8, Chip8Error, StepOutcome, Variant};
+use crate::quirks::Quirks;
+
+/// Length of a 60 Hz frame in milliseconds
+const FRAME_MS: f64 = 1000.0 / 60.0;
+/// Frames we catch up on at most after the tab was in the background
+const MAX_CATCHUP_FRAMES: u32 = 4;
+
+/// How far ahead of the audio clock the sound is scheduled in seconds, it covers a late timeout
+const AUDIO_LEAD: f64 = 0.05;
+/// Time constant of the volume changes in seconds, short enough to sound instant and long enough not to click
+const VOLUME_RAMP: f64 = 0.005;
+/// Page events that count as a user gesture, browsers only let audio start after one
+const GESTURES: [&str; 3] = ["pointerdown", "keydown", "touchend"];
+
+/// Canvas colours for each combination of the two XO-CHIP bitplanes: off, first plane, second plane, both
+const PALETTE: [&str; 4] = ["#000", "#2182ff", "#ff6600", "#662200"];
 
 thread_local! {
     static EMU: RefCell<Emu> = RefCell::new(Emu::new_uninit());
     // Keep the JS callback alive for setTimeout
     static TICK_CB: RefCell<Option<Closure<dyn FnMut()>>> = RefCell::new(None);
     static KEYS_DOWN_MASK: Cell<u32> = Cell::new(0); // bit i set => key i down
+    // Keep the JS callback alive for the gesture listeners that resume the audio
+    static GESTURE_CB: RefCell<Option<Closure<dyn FnMut()>>> = RefCell::new(None);
+}
+
+/// The beeper: the sound rendered by the machine, scheduled in short buffers through a volume gain
+struct Beeper {
+    ctx: AudioContext,
+    gain: GainNode,
+    volume: f32,
+    muted: bool,
+    // audio clock time the next buffer starts at
+    next_time: f64,
+    samples: Vec<f32>,
+}
+
+impl Beeper {
+    fn new() -> Result<Self, JsValue> {
+        let ctx = AudioContext::new()?;
+        let gain = ctx.create_gain()?;
+        gain.connect_with_audio_node(&ctx.destination())?;
+        let beeper = Self { ctx, gain, volume: 0.25, muted: false, next_time: 0.0, samples: Vec::new() };
+        beeper.gain.gain().set_value(beeper.volume);
+        Ok(beeper)
+    }
+
+    /// Schedule the sound of the frames that ran, up to `AUDIO_LEAD` ahead of the audio clock.
+    /// Frames that haven't run yet are silence
+    fn play(&mut self, chip8: &mut Chip8) -> Result<(), JsValue> {
+        let now = self.ctx.current_time();
+        // after falling behind, e.g. while the tab was in the background, start again from now
+        self.next_time = self.next_time.max(now);
+        let rate = self.ctx.sample_rate();
+        let count = ((now + AUDIO_LEAD - self.next_time) * rate as f64) as usize;
+        if count == 0 {
+            return Ok(());
+        }
+        self.samples.resize(count, 0.0);
+        chip8.fill_audio(&mut self.samples, rate as u32);
+
+        // silence only has to take its time
+        if self.samples.iter().any(|sample| *sample != 0.0) {
+            let buffer = self.ctx.create_buffer(1, count as u32, rate)?;
+            buffer.copy_to_channel(&self.samples, 0)?;
+            let source = self.ctx.create_buffer_source()?;
+            source.set_buffer(Some(&buffer));
+            source.connect_with_audio_node(&self.gain)?;
+            source.start_with_when(self.next_time)?;
+        }
+        self.next_time += count as f64 / rate as f64;
+        Ok(())
+    }
+
+    /// Glide the gain to the volume, jumping to it would click
+    fn ramp(&self) {
+        let level = if self.muted { 0.0 } else { self.volume };
+        let _ = self.gain.gain().set_target_at_time(level, self.ctx.current_time(), VOLUME_RAMP);
+    }
+
+    /// The context starts suspended until the page gets a user gesture
+    fn resume(&self) {
+        if self.ctx.state() == AudioContextState::Suspended {
+            let _ = self.ctx.resume();
+        }
+    }
 }
 
 struct Emu {
@@ -19,7 +100,10 @@ struct Emu {
     ctx: CanvasRenderingContext2d,
     loaded: bool,
     running: bool,
-    delay_ms: u32, // delay between *instruction* ticks
+    next_frame_ms: f64, // when the next 60 Hz frame is due, from Date.now()
+    halt_message: Option<String>, // why the ROM stopped, cleared by load/reset
+    break_message: Option<String>, // the breakpoint that paused the machine, cleared when it runs again
+    beeper: Option<Beeper>, // None when the browser has no WebAudio
 }
 
 impl Emu {
@@ -34,7 +118,10 @@ impl Emu {
             ctx,
             loaded: false,
             running: false,   // start paused (manual stepping)
-            delay_ms: 12,      // ~250 Hz instruction rate; tweak as you like
+            next_frame_ms: 0.0,
+            halt_message: None,
+            break_message: None,
+            beeper: None,
         }
     }
 
@@ -48,35 +135,92 @@ impl Emu {
         }
     }
 
+    /// Play the sound of the frames that ran, a paused or halted machine runs none and is silent
+    fn play_audio(&mut self) {
+        if let Some(beeper) = &mut self.beeper {
+            if let Err(e) = beeper.play(&mut self.chip8) {
+                web_sys::console::warn_2(&"audio:".into(), &e);
+            }
+        }
+    }
+
     /// Execute exactly ONE CHIP-8 instruction, then present.
     fn tick_once(&mut self) {
-        // If your core has Result, handle/log it instead of panicking.
-        self.chip8.Cycle();   // or self.chip8.cycle().ok();
+        if self.halt_message.is_none() {
+            let result = self.chip8.debug_step();
+            self.check_halt(result);
+        }
+        self.present();
+    }
+
+    /// Run the 60 Hz frames that are due by `now`: `ipf` instructions and one timer tick each.
+    fn run_due_frames(&mut self, now: f64) {
+        if now - self.next_frame_ms > FRAME_MS * MAX_CATCHUP_FRAMES as f64 {
+            self.next_frame_ms = now;
+        }
+        while self.next_frame_ms <= now && self.halt_message.is_none() && self.running {
+            let result = self.chip8.run_frame();
+            self.check_halt(result);
+            self.next_frame_ms += FRAME_MS;
+        }
+    }
+
+    /// Once the ROM exits or faults we pause and keep the message for `halt_message()`.
+    /// A breakpoint pauses too, its message is kept for `break_message()`.
+    fn check_halt(&mut self, result: Result<StepOutcome, Chip8Error>) {
+        if let Ok(StepOutcome::Break(hit)) = result {
+            let message = hit.to_string();
+            web_sys::console::log_1(&message.clone().into());
+            self.break_message = Some(message);
+            self.running = false;
+            return;
+        }
+        let message = match result {
+            Ok(StepOutcome::Exited) => Some("ROM exited".to_string()),
+            Ok(_) => None,
+            Err(e) => Some(format!("halted: {e}")),
+        };
+        if let Some(message) = message {
+            web_sys::console::error_1(&message.clone().into());
+            self.halt_message = Some(message);
+            self.running = false;
+        }
+    }
+
+    /// Sync the frontend after the machine state was replaced by a save state or a rewind
+    fn restored(&mut self) {
+        // the next frame sends edges for whatever differs from the keys actually held
+        for i in 0..16 {
+            self.keys_down[i] = self.chip8.keypad[i] != 0;
+        }
+        self.halt_message = None;
         self.present();
     }
 
     fn present(&self) {
         let w = self.canvas.width() as f64;
         let h = self.canvas.height() as f64;
-        let cols = 64f64;
-        let rows = 32f64;
-        let pw = (w / cols).floor();
-        let ph = (h / rows).floor();
+        let (width, height) = (self.chip8.width(), self.chip8.height());
+        let pw = (w / width as f64).floor();
+        let ph = (h / height as f64).floor();
 
         // Clear
-        self.ctx.set_fill_style(&"#000".into());
+        self.ctx.set_fill_style_str(PALETTE[0]);
         self.ctx.fill_rect(0.0, 0.0, w, h);
         self.ctx.set_image_smoothing_enabled(false);
-        self.ctx.set_fill_style(&"#2182ff".into());
 
-        let fb = &self.chip8.video; // works for [u8] / [u32] as 0/!0
+        let fb = self.chip8.export_video(); // bitplanes of each pixel
 
-        for y in 0..32usize {
-            for x in 0..64usize {
-                if fb[y * 64 + x] != 0 {
-                    let xf = (x as f64) * pw;
-                    let yf = (y as f64) * ph;
-                    self.ctx.fill_rect(xf, yf, pw, ph);
+        // one pass per colour so the fill style only changes three times
+        for (planes, colour) in PALETTE.iter().enumerate().skip(1) {
+            self.ctx.set_fill_style_str(colour);
+            for y in 0..height {
+                for x in 0..width {
+                    if (fb[y * width + x] & 0x3) as usize == planes {
+                        let xf = (x as f64) * pw;
+                        let yf = (y as f64) * ph;
+                        self.ctx.fill_rect(xf, yf, pw, ph);
+                    }
                 }
             }
         }
@@ -108,11 +252,29 @@ pub fn init(canvas_id: &str) -> Result<(), JsValue> {
         let mut emu = cell.borrow_mut();
         emu.canvas = canvas;
         emu.ctx = ctx;
+        emu.beeper = Beeper::new()
+            .map_err(|e| web_sys::console::warn_2(&"no audio:".into(), &e))
+            .ok();
         // draw once so you see a blank screen
         emu.present();
     });
 
-    // Build the tick closure (one instruction per timeout)
+    // Resume the audio on the first user gesture, later ones find it running and do nothing
+    GESTURE_CB.with(|slot| {
+        let cb = Closure::wrap(Box::new(move || {
+            EMU.with(|cell| {
+                if let Some(beeper) = &cell.borrow().beeper {
+                    beeper.resume();
+                }
+            });
+        }) as Box<dyn FnMut()>);
+        for gesture in GESTURES {
+            let _ = window().unwrap().add_event_listener_with_callback(gesture, cb.as_ref().unchecked_ref());
+        }
+        *slot.borrow_mut() = Some(cb);
+    });
+
+    // Build the tick closure (the frames that are due per timeout)
     TICK_CB.with(|slot| {
         let cb = Closure::wrap(Box::new(move || {
             // read input mask (no borrow)
@@ -123,20 +285,24 @@ pub fn init(canvas_id: &str) -> Result<(), JsValue> {
                 // Edge-detect keypad regardless of paused/running
                 emu.apply_keymask_edges(mask);
 
+                let now = js_sys::Date::now();
                 if emu.loaded && emu.running {
-                    emu.tick_once(); // ONE instruction, ONE present
+                    emu.run_due_frames(now);
                 } else {
-                    // Even if paused, repaint (e.g., after reset/load)
-                    emu.present();
+                    // keep the clock current so resuming doesn't replay the paused time
+                    emu.next_frame_ms = now;
                 }
+                emu.play_audio();
+                // Even if paused, repaint (e.g., after reset/load)
+                emu.present();
 
                 // Log a cheap “is it alive” counter (optional)
                 // let fb = &emu.chip8.video;
                 // let lit: u32 = fb.iter().map(|&p| (p != 0) as u32).sum();
                 // web_sys::console::log_1(&format!("lit={}", lit).into());
 
-                // schedule next instruction tick
-                let delay = emu.delay_ms as i32;
+                // schedule the next tick when the next frame is due
+                let delay = (emu.next_frame_ms - now).clamp(1.0, FRAME_MS) as i32;
                 drop(emu); // explicit drop before scheduling
                 schedule_next_timeout(delay);
             });
@@ -157,7 +323,7 @@ pub fn step(n: u32) {
     EMU.with(|cell| {
         let mut emu = cell.borrow_mut();
         for _ in 0..n {
-            if !emu.loaded { break; }
+            if !emu.loaded || emu.halt_message.is_some() { break; }
             emu.tick_once();
         }
     });
@@ -165,12 +331,25 @@ pub fn step(n: u32) {
 
 #[wasm_bindgen]
 pub fn set_running(run: bool) {
-    EMU.with(|cell| cell.borrow_mut().running = run);
+    EMU.with(|cell| {
+        let mut emu = cell.borrow_mut();
+        emu.running = run;
+        if run {
+            emu.break_message = None;
+        }
+    });
+}
+
+/// Whether frames are running, the core pauses itself on breakpoints, exits and faults
+#[wasm_bindgen]
+pub fn is_running() -> bool {
+    EMU.with(|cell| cell.borrow().running)
 }
 
+/// Instructions per 60 Hz frame
 #[wasm_bindgen]
-pub fn set_delay_ms(ms: u32) {
-    EMU.with(|cell| cell.borrow_mut().delay_ms = ms.max(1));
+pub fn set_ipf(ipf: u32) {
+    EMU.with(|cell| cell.borrow_mut().chip8.set_ipf(ipf));
 }
 
 #[wasm_bindgen]
@@ -180,6 +359,7 @@ pub fn load_rom(bytes: &[u8]) -> Result<(), JsValue> {
         emu.chip8.reset_and_load_bytes(bytes)
             .map_err(|e| JsValue::from_str(&format!("{e:?}")))?;
         emu.loaded = true;
+        emu.halt_message = None;
         // after loading, present once; stay paused by default
         emu.present();
         Ok(())
@@ -190,14 +370,129 @@ pub fn load_rom(bytes: &[u8]) -> Result<(), JsValue> {
 pub fn reset() {
     EMU.with(|cell| {
         let mut emu = cell.borrow_mut();
-        emu.chip8 = Chip8::new();
+        let (variant, quirks, ipf) = (emu.chip8.variant(), emu.chip8.quirks(), emu.chip8.ipf());
+        let breakpoints: Vec<Breakpoint> = emu.chip8.breakpoints().map(|(_, breakpoint)| *breakpoint).collect();
+        emu.chip8 = Chip8::with_variant(variant);
+        emu.chip8.set_quirks(quirks);
+        emu.chip8.set_ipf(ipf);
+        // the breakpoints survive, under new ids
+        for breakpoint in breakpoints {
+            emu.chip8.add_breakpoint(breakpoint);
+        }
         emu.keys_down = [false;16];
         emu.loaded = false;
+        emu.halt_message = None;
+        emu.break_message = None;
         KEYS_DOWN_MASK.with(|c| c.set(0));
         emu.present();
     });
 }
 
+/// Snapshot the whole machine, e.g. to keep it in localStorage
+#[wasm_bindgen]
+pub fn save_state() -> Vec<u8> {
+    EMU.with(|cell| cell.borrow().chip8.save_state())
+}
+
+/// Restore a snapshot made by `save_state`, the machine is untouched if it's rejected
+#[wasm_bindgen]
+pub fn load_state(bytes: &[u8]) -> Result<(), JsValue> {
+    EMU.with(|cell| {
+        let mut emu = cell.borrow_mut();
+        emu.chip8.load_state(bytes)
+            .map_err(|e| JsValue::from_str(&format!("{e}")))?;
+        emu.loaded = true;
+        emu.restored();
+        Ok(())
+    })
+}
+
+/// Step back `frames` 60 Hz frames, returns how many frames were actually rewound
+#[wasm_bindgen]
+pub fn rewind(frames: usize) -> usize {
+    EMU.with(|cell| {
+        let mut emu = cell.borrow_mut();
+        let rewound = emu.chip8.rewind(frames);
+        if rewound > 0 {
+            emu.restored();
+        }
+        rewound
+    })
+}
+
+/// Why the ROM stopped running (it exited or faulted), if it did
+#[wasm_bindgen]
+pub fn halt_message() -> Option<String> {
+    EMU.with(|cell| cell.borrow().halt_message.clone())
+}
+
+/// Pause when the breakpoint is hit: "0x2A4", "0x2A4 if VF == 1", "VF == 1" or an opcode class like "Dxyn".
+/// Returns its id for `remove_breakpoint`
+#[wasm_bindgen]
+pub fn add_breakpoint(spec: &str) -> Result<u32, JsValue> {
+    let breakpoint: Breakpoint = spec.parse().map_err(|e: String| JsValue::from_str(&e))?;
+    EMU.with(|cell| Ok(cell.borrow_mut().chip8.add_breakpoint(breakpoint).0))
+}
+
+#[wasm_bindgen]
+pub fn remove_breakpoint(id: u32) -> bool {
+    EMU.with(|cell| cell.borrow_mut().chip8.remove_breakpoint(BreakpointId(id)))
+}
+
+#[wasm_bindgen]
+pub fn clear_breakpoints() {
+    EMU.with(|cell| cell.borrow_mut().chip8.clear_breakpoints());
+}
+
+/// The breakpoint that paused the machine, until it runs again
+#[wasm_bindgen]
+pub fn break_message() -> Option<String> {
+    EMU.with(|cell| cell.borrow().break_message.clone())
+}
+
+/// Select the instruction set by name: "chip8", "schip" or "xochip". This also selects its default quirks
+#[wasm_bindgen]
+pub fn set_variant(name: &str) -> Result<(), JsValue> {
+    let variant: Variant = name.parse().map_err(|e: String| JsValue::from_str(&e))?;
+    EMU.with(|cell| {
+        let mut emu = cell.borrow_mut();
+        emu.chip8.set_variant(variant);
+        emu.chip8.set_quirks(variant.default_quirks());
+    });
+    Ok(())
+}
+
+/// Select the quirks preset by name: "vip", "chip48", "schip" or "xochip", quirks to turn on can
+/// follow after a "+", e.g. "vip+key_press"
+#[wasm_bindgen]
+pub fn set_quirks(name: &str) -> Result<(), JsValue> {
+    let quirks: Quirks = name.parse().map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    EMU.with(|cell| cell.borrow_mut().chip8.set_quirks(quirks));
+    Ok(())
+}
+
+/// Volume of the beep, from 0 to 1
+#[wasm_bindgen]
+pub fn set_volume(volume: f32) {
+    EMU.with(|cell| {
+        if let Some(beeper) = &mut cell.borrow_mut().beeper {
+            beeper.volume = volume.clamp(0.0, 1.0);
+            beeper.ramp();
+        }
+    });
+}
+
+/// Silence the beep without losing the volume
+#[wasm_bindgen]
+pub fn set_mute(muted: bool) {
+    EMU.with(|cell| {
+        if let Some(beeper) = &mut cell.borrow_mut().beeper {
+            beeper.muted = muted;
+            beeper.ramp();
+        }
+    });
+}
+
 #[wasm_bindgen]
 pub fn set_key(idx: u8, down: bool) {
     if idx >= 16 { return; }
@@ -0,0 +1,155 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Interpreter quirks for the CHIP-8 instructions whose behaviour differs between
+/// the original COSMAC VIP interpreter and its successors.
+///
+/// Every flag is named after the behaviour it turns on, the names follow the ones used
+/// by Timendus' quirks test ROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift Vx in place and ignore Vy. When off, Vy is shifted into Vx.
+    pub shift: bool,
+    /// `FX55`/`FX65` leave I untouched. When off, I is incremented by X + 1.
+    pub load_store: bool,
+    /// `BNNN` behaves like `BXNN` and jumps to `XNN + Vx`. When off, it jumps to `NNN + V0`.
+    pub jump: bool,
+    /// `8XY1`, `8XY2` and `8XY3` reset VF to 0.
+    pub vf_reset: bool,
+    /// Sprites are clipped at the edges of the screen. When off, they wrap around.
+    pub clipping: bool,
+    /// `DXYN` waits for the next vertical blank before the CPU continues.
+    pub display_wait: bool,
+    /// `FX0A` finishes as soon as a key is pressed. When off, it waits for the key to be
+    /// released, like the COSMAC VIP.
+    pub key_press: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift: false,
+        load_store: false,
+        jump: false,
+        vf_reset: true,
+        clipping: true,
+        display_wait: true,
+        key_press: false,
+    };
+
+    /// CHIP-48 for the HP-48 calculators
+    pub const CHIP_48: Quirks = Quirks {
+        shift: true,
+        load_store: true,
+        jump: true,
+        vf_reset: false,
+        clipping: true,
+        display_wait: false,
+        key_press: false,
+    };
+
+    /// SUPER-CHIP 1.1
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift: true,
+        load_store: true,
+        jump: true,
+        vf_reset: false,
+        clipping: true,
+        display_wait: false,
+        key_press: false,
+    };
+
+    /// XO-CHIP, as implemented by Octo
+    pub const XO_CHIP: Quirks = Quirks {
+        shift: false,
+        load_store: false,
+        jump: false,
+        vf_reset: false,
+        clipping: false,
+        display_wait: false,
+        key_press: false,
+    };
+
+    /// Names accepted by `Quirks::from_str`, in the same order as `Quirks::PRESETS`
+    pub const PRESET_NAMES: [&'static str; 4] = ["vip", "chip48", "schip", "xochip"];
+
+    /// Names of the flags `Quirks::from_str` turns on after a `+`
+    pub const NAMES: [&'static str; 7] =
+        ["shift", "load_store", "jump", "vf_reset", "clipping", "display_wait", "key_press"];
+
+    pub const PRESETS: [Quirks; 4] = [
+        Quirks::COSMAC_VIP,
+        Quirks::CHIP_48,
+        Quirks::SUPER_CHIP,
+        Quirks::XO_CHIP,
+    ];
+}
+
+impl Quirks {
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        Some(match name {
+            "shift" => &mut self.shift,
+            "load_store" => &mut self.load_store,
+            "jump" => &mut self.jump,
+            "vf_reset" => &mut self.vf_reset,
+            "clipping" => &mut self.clipping,
+            "display_wait" => &mut self.display_wait,
+            "key_press" => &mut self.key_press,
+            _ => return None,
+        })
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::COSMAC_VIP
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = ParseQuirksError;
+
+    /// Parse a preset name, e.g. `"vip"` or `"schip"`, followed by the flags to turn on on top of
+    /// it, e.g. `"vip+key_press"`
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut parts = spec.split('+');
+        let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase().replace(['-', '_'], "");
+        let name = match name.as_str() {
+            "cosmacvip" | "chip8" => "vip",
+            "superchip" => "schip",
+            other => other,
+        };
+
+        let mut quirks = Quirks::PRESET_NAMES
+            .iter()
+            .position(|preset| *preset == name)
+            .map(|i| Quirks::PRESETS[i])
+            .ok_or_else(|| ParseQuirksError::UnknownPreset(name.to_string()))?;
+        for flag in parts {
+            let flag = flag.trim().to_ascii_lowercase().replace('-', "_");
+            *quirks.flag_mut(&flag).ok_or(ParseQuirksError::UnknownQuirk(flag))? = true;
+        }
+        Ok(quirks)
+    }
+}
+
+/// Returned when parsing quirks fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseQuirksError {
+    UnknownPreset(String),
+    /// A name after a `+` that isn't one of `Quirks::NAMES`
+    UnknownQuirk(String),
+}
+
+impl fmt::Display for ParseQuirksError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseQuirksError::UnknownPreset(name) =>
+                write!(f, "Unknown quirks preset: {} (expected one of: {})", name, Quirks::PRESET_NAMES.join(", ")),
+            ParseQuirksError::UnknownQuirk(name) =>
+                write!(f, "Unknown quirk: {} (expected one of: {})", name, Quirks::NAMES.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for ParseQuirksError {}
@@ -0,0 +1,62 @@
+//! Golden-image regression tests: every bundled test ROM runs headless for a fixed number of
+//! frames and its final screen has to match the text image in `tests/golden/`.
+//!
+//! After a change that is meant to alter a screen, rerun with `UPDATE_GOLDEN=1` to rewrite the
+//! images, and check the new ones before committing them.
+
+use std::fs;
+use std::path::Path;
+
+use chip8_rs::chip8::Chip8;
+use chip8_rs::headless::{self, InputScript, RunEnd};
+use chip8_rs::quirks::Quirks;
+
+/// Run `rom` for `frames` frames and compare its screen with `tests/golden/<rom>.txt`
+fn check(rom: &str, quirks: Quirks, frames: u32) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut chip8 = Chip8::with_quirks(quirks);
+    chip8.load_rom(root.join("roms").join(rom)).unwrap();
+    let outcome = headless::run(&mut chip8, Some(frames), &InputScript::default());
+    assert_eq!(outcome, Ok((RunEnd::Frames, frames)), "{} didn't run all its frames", rom);
+    let actual = headless::screen_text(&chip8);
+
+    let golden = root.join("tests").join("golden").join(Path::new(rom).with_extension("txt"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&golden)
+        .unwrap_or_else(|e| panic!("{}: {} (run with UPDATE_GOLDEN=1 to create it)", golden.display(), e));
+    if actual != expected {
+        panic!("{} doesn't match {}\n{}", rom, golden.display(), diff(&expected, &actual));
+    }
+}
+
+/// The two screens side by side, rows that differ are marked with `>`
+fn diff(expected: &str, actual: &str) -> String {
+    let (expected, actual): (Vec<&str>, Vec<&str>) = (expected.lines().collect(), actual.lines().collect());
+    let width = expected.iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut diff = format!("    row {:<width$} | actual\n", "expected", width = width);
+    for row in 0..expected.len().max(actual.len()) {
+        let (old, new) = (expected.get(row).copied().unwrap_or(""), actual.get(row).copied().unwrap_or(""));
+        let marker = if old == new { ' ' } else { '>' };
+        diff.push_str(&format!("  {} {:>3} {:<width$} | {}\n", marker, row, old, new, width = width));
+    }
+    diff
+}
+
+#[test]
+fn test_opcode() {
+    check("test_opcode.ch8", Quirks::COSMAC_VIP, 200);
+}
+
+// BC_test expects the CHIP-48 shifts of Vx in place, and FX55/FX65 leaving I alone
+#[test]
+fn bc_test() {
+    check("BC_test.ch8", Quirks::CHIP_48, 200);
+}
+
+#[test]
+fn ibm_logo() {
+    check("IBM-Logo.ch8", Quirks::COSMAC_VIP, 200);
+}
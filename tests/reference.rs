@@ -0,0 +1,324 @@
+//! Differential test: `Chip8` and a deliberately simple reference interpreter run the same
+//! random instruction streams in lockstep, and their whole state has to agree after every step.
+//!
+//! The reference decodes with one plain `match` and follows Cowgod's technical reference
+//! (http://devernay.free.fr/hacks/chip8/C8TECH10.HTM) for the original CHIP-8 instruction set.
+//! It shares nothing with the interpreter under test, so a slip in the dispatch tables or in a
+//! handler shows up as a mismatch.
+
+use chip8_rs::chip8::{Chip8, Chip8Error, StepOutcome};
+use chip8_rs::quirks::Quirks;
+use proptest::prelude::*;
+
+/// The behaviour Cowgod describes: shifts work on Vx, FX55/FX65 leave I alone, BNNN adds V0,
+/// the logic operations leave VF alone, sprites wrap around the screen and FX0A takes a key press
+const COWGOD: Quirks = Quirks {
+    shift: true,
+    load_store: true,
+    jump: false,
+    vf_reset: false,
+    clipping: false,
+    display_wait: false,
+    key_press: true,
+};
+
+const MEMORY_SIZE: usize = 4096;
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+/// Where the interpreter keeps its hex digit sprites, Cowgod leaves that to the interpreter
+const FONT: u16 = 0x50;
+
+#[derive(Clone)]
+struct Reference {
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    stack: Vec<u16>,
+    delay: u8,
+    sound: u8,
+    memory: Vec<u8>,
+    screen: [[bool; WIDTH]; HEIGHT],
+    keys: [bool; 16],
+}
+
+impl Reference {
+    /// Start from the memory of a freshly loaded `Chip8`, which has the font and the ROM in it
+    fn new(memory: &[u8]) -> Reference {
+        Reference {
+            v: [0; 16],
+            i: 0,
+            pc: 0x200,
+            stack: Vec::new(),
+            delay: 0,
+            sound: 0,
+            memory: memory.to_vec(),
+            screen: [[false; WIDTH]; HEIGHT],
+            keys: [false; 16],
+        }
+    }
+
+    /// The opcode at PC
+    fn opcode(&self) -> Option<u16> {
+        let pc = self.pc as usize;
+        Some(u16::from_be_bytes([*self.memory.get(pc)?, *self.memory.get(pc + 1)?]))
+    }
+
+    /// Run one instruction. CXKK masks `random` instead of drawing its own number. A failing
+    /// instruction leaves the machine as it was
+    fn step(&mut self, random: u8) -> Result<(), Chip8Error> {
+        let mut next = self.clone();
+        next.execute(random)?;
+        *self = next;
+        Ok(())
+    }
+
+    fn execute(&mut self, random: u8) -> Result<(), Chip8Error> {
+        let pc = self.pc;
+        let opcode = self.opcode().ok_or(Chip8Error::PcOob { pc })?;
+        let x = (opcode >> 8 & 0xF) as usize;
+        let y = (opcode >> 4 & 0xF) as usize;
+        let n = opcode & 0xF;
+        let kk = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+        let unknown = Chip8Error::UnknownOpcode { pc, opcode };
+        // the last address of `len` bytes at I has to be in memory
+        let check = |i: u16, len: usize| {
+            let end = i as usize + len;
+            if end > MEMORY_SIZE {
+                Err(Chip8Error::MemOob { pc, opcode, address: end - 1 })
+            } else {
+                Ok(i as usize)
+            }
+        };
+
+        self.pc += 2;
+        match opcode >> 12 {
+            0x0 => match opcode {
+                // 00E0 - CLS
+                0x00E0 => self.screen = [[false; WIDTH]; HEIGHT],
+                // 00EE - RET
+                0x00EE => self.pc = self.stack.pop().ok_or(Chip8Error::StackUnderflow { pc, opcode })?,
+                // 0NNN - SYS, ignored by modern interpreters and not run by this one
+                _ => return Err(unknown),
+            },
+            // 1NNN - JP addr
+            0x1 => self.pc = nnn,
+            // 2NNN - CALL addr
+            0x2 => {
+                if self.stack.len() == 16 {
+                    return Err(Chip8Error::StackOverflow { pc, opcode });
+                }
+                self.stack.push(self.pc);
+                self.pc = nnn;
+            }
+            // 3XKK - SE Vx, byte
+            0x3 => {
+                if self.v[x] == kk {
+                    self.pc += 2;
+                }
+            }
+            // 4XKK - SNE Vx, byte
+            0x4 => {
+                if self.v[x] != kk {
+                    self.pc += 2;
+                }
+            }
+            // 5XY0 - SE Vx, Vy
+            0x5 if n == 0 => {
+                if self.v[x] == self.v[y] {
+                    self.pc += 2;
+                }
+            }
+            // 6XKK - LD Vx, byte
+            0x6 => self.v[x] = kk,
+            // 7XKK - ADD Vx, byte
+            0x7 => self.v[x] = self.v[x].wrapping_add(kk),
+            // 8XYN - arithmetic. Cowgod sets VF before Vx, but on the real machine the flag is
+            // the last write, so it wins when x is F. VF after a subtraction is NOT borrow, which
+            // is 1 for equal operands too, not only for Vx > Vy as the text says
+            0x8 => {
+                let (vx, vy) = (self.v[x], self.v[y]);
+                let (result, flag) = match n {
+                    0x0 => (vy, None),
+                    0x1 => (vx | vy, None),
+                    0x2 => (vx & vy, None),
+                    0x3 => (vx ^ vy, None),
+                    0x4 => (vx.wrapping_add(vy), Some((vx as u16 + vy as u16 > 0xFF) as u8)),
+                    0x5 => (vx.wrapping_sub(vy), Some((vx >= vy) as u8)),
+                    0x6 => (vx >> 1, Some(vx & 1)),
+                    0x7 => (vy.wrapping_sub(vx), Some((vy >= vx) as u8)),
+                    0xE => (vx << 1, Some(vx >> 7)),
+                    _ => return Err(unknown),
+                };
+                self.v[x] = result;
+                if let Some(flag) = flag {
+                    self.v[0xF] = flag;
+                }
+            }
+            // 9XY0 - SNE Vx, Vy
+            0x9 if n == 0 => {
+                if self.v[x] != self.v[y] {
+                    self.pc += 2;
+                }
+            }
+            // ANNN - LD I, addr
+            0xA => self.i = nnn,
+            // BNNN - JP V0, addr
+            0xB => self.pc = nnn + self.v[0] as u16,
+            // CXKK - RND Vx, byte
+            0xC => self.v[x] = random & kk,
+            // DXYN - DRW Vx, Vy, nibble
+            0xD => {
+                let sprite = check(self.i, n as usize)?;
+                let (left, top) = (self.v[x] as usize, self.v[y] as usize);
+                self.v[0xF] = 0;
+                for row in 0..n as usize {
+                    let bits = self.memory[sprite + row];
+                    for col in 0..8 {
+                        if bits & 0x80 >> col == 0 {
+                            continue;
+                        }
+                        let pixel = &mut self.screen[(top + row) % HEIGHT][(left + col) % WIDTH];
+                        if *pixel {
+                            self.v[0xF] = 1;
+                        }
+                        *pixel = !*pixel;
+                    }
+                }
+            }
+            0xE => {
+                let key = self.keys[(self.v[x] & 0xF) as usize];
+                match kk {
+                    // EX9E - SKP Vx
+                    0x9E if key => self.pc += 2,
+                    // EXA1 - SKNP Vx
+                    0xA1 if !key => self.pc += 2,
+                    0x9E | 0xA1 => {}
+                    _ => return Err(unknown),
+                }
+            }
+            0xF => match kk {
+                // FX07 - LD Vx, DT
+                0x07 => self.v[x] = self.delay,
+                // FX0A - LD Vx, K: the keys are held from the start and nothing presses one,
+                // so the wait never ends
+                0x0A => self.pc = pc,
+                // FX15 - LD DT, Vx
+                0x15 => self.delay = self.v[x],
+                // FX18 - LD ST, Vx
+                0x18 => self.sound = self.v[x],
+                // FX1E - ADD I, Vx
+                0x1E => self.i = self.i.wrapping_add(self.v[x] as u16),
+                // FX29 - LD F, Vx
+                0x29 => self.i = FONT + 5 * (self.v[x] & 0xF) as u16,
+                // FX33 - LD B, Vx
+                0x33 => {
+                    let i = check(self.i, 3)?;
+                    let value = self.v[x];
+                    self.memory[i..i + 3].copy_from_slice(&[value / 100, value / 10 % 10, value % 10]);
+                }
+                // FX55 - LD [I], Vx
+                0x55 => {
+                    let i = check(self.i, x + 1)?;
+                    self.memory[i..=i + x].copy_from_slice(&self.v[..=x]);
+                }
+                // FX65 - LD Vx, [I]
+                0x65 => {
+                    let i = check(self.i, x + 1)?;
+                    self.v[..=x].copy_from_slice(&self.memory[i..=i + x]);
+                }
+                _ => return Err(unknown),
+            },
+            _ => return Err(unknown),
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self) {
+        self.delay = self.delay.saturating_sub(1);
+        self.sound = self.sound.saturating_sub(1);
+    }
+}
+
+/// Compare everything the two machines have in common
+fn assert_same(chip8: &Chip8, reference: &Reference, step: usize) -> Result<(), TestCaseError> {
+    let at = format!("after step {} (PC {:03X})", step, reference.pc);
+    prop_assert_eq!(chip8.program_counter(), reference.pc, "PC {}", at);
+    prop_assert_eq!(chip8.registers(), &reference.v, "V0-VF {}", at);
+    prop_assert_eq!(chip8.index_register(), reference.i, "I {}", at);
+    prop_assert_eq!(chip8.stack(), &reference.stack[..], "stack {}", at);
+    prop_assert_eq!((chip8.delay_timer(), chip8.sound_timer()), (reference.delay, reference.sound), "timers {}", at);
+    if chip8.memory() != &reference.memory[..] {
+        let address = chip8.memory().iter().zip(&reference.memory).position(|(a, b)| a != b).unwrap();
+        prop_assert!(false, "memory differs at {:03X} {}", address, at);
+    }
+    let screen: Vec<bool> = reference.screen.iter().flatten().copied().collect();
+    let pixels: Vec<bool> = chip8.export_video().iter().map(|pixel| *pixel != 0).collect();
+    prop_assert!(pixels == screen, "screens differ {}", at);
+    Ok(())
+}
+
+/// Opcodes with their operand bits cleared, with the mask of the operand bits. The last one is
+/// any word at all, mostly opcodes that don't exist
+const OPCODES: [(u16, u16); 35] = [
+    (0x00E0, 0x0000), (0x00EE, 0x0000), (0x1000, 0x0FFF), (0x2000, 0x0FFF), (0x3000, 0x0FFF),
+    (0x4000, 0x0FFF), (0x5000, 0x0FF0), (0x6000, 0x0FFF), (0x7000, 0x0FFF), (0x8000, 0x0FF0),
+    (0x8001, 0x0FF0), (0x8002, 0x0FF0), (0x8003, 0x0FF0), (0x8004, 0x0FF0), (0x8005, 0x0FF0),
+    (0x8006, 0x0FF0), (0x8007, 0x0FF0), (0x800E, 0x0FF0), (0x9000, 0x0FF0), (0xA000, 0x0FFF),
+    (0xB000, 0x0FFF), (0xC000, 0x0FFF), (0xD000, 0x0FFF), (0xE09E, 0x0F00), (0xE0A1, 0x0F00),
+    (0xF007, 0x0F00), (0xF00A, 0x0F00), (0xF015, 0x0F00), (0xF018, 0x0F00), (0xF01E, 0x0F00),
+    (0xF029, 0x0F00), (0xF033, 0x0F00), (0xF055, 0x0F00), (0xF065, 0x0F00), (0x0000, 0xFFFF),
+];
+
+/// A random program. Jumps and calls land on one of its own instructions, so it keeps running
+/// for a while instead of falling into empty memory
+fn program() -> impl Strategy<Value = Vec<u8>> {
+    prop::collection::vec((0..OPCODES.len(), any::<u16>()), 1..64).prop_map(|words| {
+        let len = words.len() as u16;
+        let mut rom = Vec::new();
+        for (kind, operands) in words {
+            let (opcode, mask) = OPCODES[kind];
+            let opcode = match opcode >> 12 {
+                0x1 | 0x2 | 0xB => opcode | (0x200 + operands % len * 2),
+                _ => opcode | operands & mask,
+            };
+            rom.extend_from_slice(&opcode.to_be_bytes());
+        }
+        rom
+    })
+}
+
+proptest! {
+    #[test]
+    fn matches_the_reference(rom in program(), keys in any::<u16>()) {
+        let mut chip8 = Chip8::with_quirks(COWGOD);
+        chip8.reset_and_load_bytes(&rom).unwrap();
+        let mut reference = Reference::new(chip8.memory());
+        for key in (0..16).filter(|key| keys & 1 << key != 0) {
+            chip8.key_down(key);
+            reference.keys[key as usize] = true;
+        }
+
+        for step in 0..500 {
+            let outcome = chip8.step();
+            // CXKK draws from the interpreter's generator, so the reference takes its result
+            let random = reference.opcode().map_or(0, |opcode| chip8.registers()[(opcode >> 8 & 0xF) as usize]);
+            let expected = reference.step(random);
+            match (&outcome, &expected) {
+                (Ok(StepOutcome::Executed { .. }), Ok(())) => {}
+                (Ok(StepOutcome::WaitingKey), Ok(())) => {}
+                (Err(error), Err(expected)) => prop_assert_eq!(error, expected, "after step {}", step),
+                _ => prop_assert!(false, "step {} at {:03X}: {:?}, the reference {:?}", step, chip8.last_pc(), outcome, expected),
+            }
+            assert_same(&chip8, &reference, step)?;
+            // a failed step halts the machine, and FX0A blocks it for good
+            if outcome.is_err() || outcome == Ok(StepOutcome::WaitingKey) {
+                break;
+            }
+            if step % 8 == 7 {
+                chip8.tick_timers();
+                reference.tick();
+            }
+        }
+    }
+}